@@ -3,9 +3,9 @@
 //! This file is a mechanical translation of the reference Golang code, available at <https://github.com/dgraph-io/ristretto/blob/master/z/bbloom.go>
 //!
 //! I claim no additional copyright over the original implementation.
-use std::ptr::addr_of;
+use crate::error::CacheError;
+use crate::utils::StateReader;
 
-const MASK: [u8; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
 const LN_2: f64 = std::f64::consts::LN_2;
 
 struct Size {
@@ -35,7 +35,7 @@ struct EntriesLocs {
 }
 
 fn calc_size_by_wrong_positives(num_entries: f64, wrongs: f64) -> EntriesLocs {
-    let size = -1f64 * num_entries * wrongs.ln() / LN_2.powf(2f64);
+    let size = -num_entries * wrongs.ln() / LN_2.powf(2f64);
     let locs = (LN_2 * size / num_entries).ceil();
 
     EntriesLocs {
@@ -105,23 +105,12 @@ impl Bloom {
 
     /// `set` sets the bit[idx] of bitset
     pub fn set(&mut self, idx: usize) {
-        let raw = (addr_of!(self.bitset[idx >> 6]) as *const u64) as u64;
-        let offset = ((idx % 64) >> 3) as u64;
-        let ptr = (raw + offset) as *mut u64;
-        unsafe {
-            *ptr |= MASK[idx % 8] as u64;
-        }
+        self.bitset[idx >> 6] |= 1 << (idx % 64);
     }
 
     /// `is_set` checks if bit[idx] of bitset is set, returns true/false.
     pub fn is_set(&self, idx: usize) -> bool {
-        let raw = (addr_of!(self.bitset[idx >> 6]) as *const u64) as u64;
-        let offset = ((idx % 64) >> 3) as u64;
-        let ptr = (raw + offset) as *mut u64;
-        unsafe {
-            let r = (*ptr >> (idx % 8)) & 1;
-            r == 1
-        }
+        (self.bitset[idx >> 6] >> (idx % 64)) & 1 == 1
     }
 
     /// `add` adds hash of a key to the bloom filter
@@ -159,6 +148,47 @@ impl Bloom {
         }
     }
 
+    /// `encode` appends the geometry and the bitset of the bloom filter to `buf`.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.size.to_le_bytes());
+        buf.extend_from_slice(&self.set_locs.to_le_bytes());
+        buf.extend_from_slice(&self.elem_num.to_le_bytes());
+        self.bitset
+            .iter()
+            .for_each(|v| buf.extend_from_slice(&v.to_le_bytes()));
+    }
+
+    /// `decode` reads a bloom filter written by [`encode`], which must have the geometry
+    /// of this one.
+    ///
+    /// [`encode`]: struct.Bloom.html#method.encode
+    pub fn decode(&self, r: &mut StateReader) -> Result<Self, CacheError> {
+        let size = r.u64()?;
+        let set_locs = r.u64()?;
+        if size != self.size || set_locs != self.set_locs {
+            return Err(CacheError::InvalidPolicyState(format!(
+                "doorkeeper of {} bits and {} locations, expected {} bits and {} locations",
+                size.wrapping_add(1),
+                set_locs,
+                self.size + 1,
+                self.set_locs
+            )));
+        }
+
+        let elem_num = r.u64()?;
+        let bitset = (0..self.bitset.len())
+            .map(|_| r.u64())
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            bitset,
+            elem_num,
+            size_exp: self.size_exp,
+            size,
+            set_locs,
+            shift: self.shift,
+        })
+    }
+
     /// `total_size` returns the total size of the bloom filter.
     #[allow(dead_code)]
     #[inline]
@@ -58,6 +58,35 @@ macro_rules! impl_builder {
                 }
             }
 
+            /// Set the size of Get buffers for the Cache.
+            ///
+            /// `buffer_items` determines the size of Get buffers. Reads are batched into
+            /// lossy, striped buffers of this size before they are handed to the admission policy.
+            ///
+            /// Unless you have a rare use case, using `64` as the `buffer_items` value
+            /// results in good performance.
+            #[inline]
+            pub fn set_buffer_items(self, sz: usize) -> Self {
+                Self {
+                    inner: self.inner.set_buffer_items(sz),
+                }
+            }
+
+            /// Set whether the Cache retains the original keys or not.
+            ///
+            /// By default, the Cache only stores the hashes of the keys. When the keys are
+            /// retained, they are kept alongside the values, so that the Cache can be iterated
+            /// with `iter`, `keys` and `for_each`. Default is false.
+            #[inline]
+            pub fn set_retain_keys(self, val: bool) -> Self
+            where
+                K: Send + Sync + 'static,
+            {
+                Self {
+                    inner: self.inner.set_retain_keys(val),
+                }
+            }
+
             /// Set whether record the metrics or not.
             ///
             /// Metrics is true when you want real-time logging of a variety of stats.
@@ -89,6 +118,30 @@ macro_rules! impl_builder {
                 }
             }
 
+            /// Set the resolution of the expirations for Cache, default is 1s.
+            ///
+            /// The expiring entries are kept in a timer wheel whose slots span `d`, so an expired
+            /// entry is reclaimed at most `d` after its deadline (plus up to one cleanup duration).
+            /// Lower it along with the cleanup duration when the values live for less than a few
+            /// seconds. The resolution can not be finer than one millisecond.
+            #[inline]
+            pub fn set_expiration_resolution(self, d: Duration) -> Self {
+                Self {
+                    inner: self.inner.set_expiration_resolution(d),
+                }
+            }
+
+            /// Set the time-to-idle for the Cache.
+            ///
+            /// The values inserted without a TTL expire once they have not been read by `get`,
+            /// `get_mut` or `get_many` for `d`. A zero `d` means the values never expire, which is the default.
+            #[inline]
+            pub fn set_time_to_idle(self, d: Duration) -> Self {
+                Self {
+                    inner: self.inner.set_time_to_idle(d),
+                }
+            }
+
             /// Set the [`KeyBuilder`] for the Cache
             ///
             /// [`KeyBuilder`] is the hashing algorithm used for every key. In Stretto, the Cache will never store the real key.
@@ -138,6 +191,85 @@ macro_rules! impl_builder {
                 }
             }
 
+            /// Set the expiry for the Cache.
+            ///
+            /// [`Expiry`] computes the TTL of a value from the value itself when it is created,
+            /// updated or read, instead of taking the one given at the call site.
+            ///
+            /// [`Expiry`]: trait.Expiry.html
+            #[inline]
+            pub fn set_expiry<E: Expiry<Key = K, Value = V> + 'static>(self, expiry: E) -> Self {
+                Self {
+                    inner: self.inner.set_expiry(expiry),
+                }
+            }
+
+            /// Set the clock for the Cache.
+            ///
+            /// The [`Clock`] measures all the expirations of the Cache, it defaults to the
+            /// [`DefaultClock`]. A [`MockClock`] lets the tests advance the time by hand.
+            ///
+            /// [`Clock`]: trait.Clock.html
+            /// [`DefaultClock`]: struct.DefaultClock.html
+            /// [`MockClock`]: struct.MockClock.html
+            #[inline]
+            pub fn set_clock<CL: Clock>(self, clock: CL) -> Self {
+                Self {
+                    inner: self.inner.set_clock(clock),
+                }
+            }
+
+            /// Set the eviction policy for the Cache.
+            ///
+            /// The [`EvictionPolicy`] chooses the keys to evict when the Cache is full, it
+            /// defaults to the [`SampledLfu`]. [`Lru`], [`Slru`], [`S3Fifo`] and [`Sieve`] are
+            /// also provided. The admission policy still rejects a new key which is less
+            /// popular than the victim, unless the policy decides the admission by itself as
+            /// the [`WTinyLfu`] does.
+            ///
+            /// [`EvictionPolicy`]: trait.EvictionPolicy.html
+            /// [`SampledLfu`]: struct.SampledLfu.html
+            /// [`Lru`]: struct.Lru.html
+            /// [`Slru`]: struct.Slru.html
+            /// [`S3Fifo`]: struct.S3Fifo.html
+            /// [`Sieve`]: struct.Sieve.html
+            /// [`WTinyLfu`]: struct.WTinyLfu.html
+            #[inline]
+            pub fn set_eviction_policy<E: EvictionPolicy>(self, eviction: E) -> Self {
+                Self {
+                    inner: self.inner.set_eviction_policy(eviction),
+                }
+            }
+
+            /// Set the admission for the Cache.
+            ///
+            /// The [`Admission`] decides whether a new key is worth evicting the victims when
+            /// the Cache is full. It defaults to strict TinyLFU, `Always` admits all the new keys
+            /// and `TinyLfuJitter` admits a small share of the warm keys TinyLFU would reject, so
+            /// that the hot keys cannot be pinned out of the Cache by hash flooding.
+            ///
+            /// [`Admission`]: enum.Admission.html
+            #[inline]
+            pub fn set_admission(self, admission: Admission) -> Self {
+                Self {
+                    inner: self.inner.set_admission(admission),
+                }
+            }
+
+            /// Set the tuning of the policies for the Cache.
+            ///
+            /// The [`PolicyConfig`] sets the number of keys sampled by the default eviction
+            /// policy, the false positive ratio of the doorkeeper, the reset interval of TinyLFU
+            /// and the depth of its count-min sketch. An invalid config makes `finalize` fail.
+            ///
+            /// [`PolicyConfig`]: struct.PolicyConfig.html
+            #[inline]
+            pub fn set_policy_config(self, config: PolicyConfig) -> Self {
+                Self {
+                    inner: self.inner.set_policy_config(config),
+                }
+            }
+
             /// Set the update validator for the Cache.
             ///
             /// By default, the Cache will always update the value if the value already exists in the cache.
@@ -187,8 +319,11 @@ macro_rules! impl_builder {
 
 macro_rules! impl_cache {
     ($cache: ident, $builder: ident, $item: ident) => {
+        use crate::cache::entry::EntryBackend;
+        #[cfg(feature = "serilization")]
+        use crate::cache::snapshot::{SnapshotEntry, SnapshotWriter};
         use crate::store::UpdateResult;
-        use crate::{ValueRef, ValueRefMut};
+        use crate::{DropReason, Entry, InsertOutcome, ValueRef, ValueRefMut};
 
         impl<K, V, KH, C, U, CB, S> $cache<K, V, KH, C, U, CB, S>
         where
@@ -202,7 +337,7 @@ macro_rules! impl_cache {
         {
             /// `get` returns a `Option<ValueRef<V, SS>>` (if any) representing whether the
             /// value was found or not.
-            pub fn get<Q>(&self, key: &Q) -> Option<ValueRef<V, S>>
+            pub fn get<Q>(&self, key: &Q) -> Option<ValueRef<'_, V, S>>
             where
                 K: core::borrow::Borrow<Q>,
                 Q: core::hash::Hash + Eq + ?Sized,
@@ -212,6 +347,7 @@ macro_rules! impl_cache {
                 }
 
                 let (index, conflict) = self.key_to_hash.build_key(key);
+                self.get_buf.push(index);
 
                 match self.store.get(&index, conflict) {
                     None => {
@@ -227,7 +363,7 @@ macro_rules! impl_cache {
 
             /// `get_mut` returns a `Option<ValueRefMut<V, SS>>` (if any) representing whether the
             /// value was found or not.
-            pub fn get_mut<Q>(&self, key: &Q) -> Option<ValueRefMut<V, S>>
+            pub fn get_mut<Q>(&self, key: &Q) -> Option<ValueRefMut<'_, V, S>>
             where
                 K: core::borrow::Borrow<Q>,
                 Q: core::hash::Hash + Eq + ?Sized,
@@ -237,6 +373,8 @@ macro_rules! impl_cache {
                 }
 
                 let (index, conflict) = self.key_to_hash.build_key(key);
+                self.get_buf.push(index);
+
                 match self.store.get_mut(&index, conflict) {
                     None => {
                         self.metrics.add(MetricType::Miss, index, 1);
@@ -249,6 +387,84 @@ macro_rules! impl_cache {
                 }
             }
 
+            /// `entry` returns the [`Entry`] of the key for in-place manipulation.
+            ///
+            /// The entry holds the write lock of the store shard of the key until it is dropped.
+            ///
+            /// [`Entry`]: enum.Entry.html
+            pub fn entry(&self, key: K) -> Entry<'_, V, S> {
+                let (index, conflict) = self.key_to_hash.build_key(&key);
+                self.get_buf.push(index);
+
+                Entry::new(
+                    self.store.shard_mut(index),
+                    index,
+                    conflict,
+                    Box::new(move |val, ttl| {
+                        let expiration =
+                            self.expire_after_create(&key, val, self.new_expiration(ttl));
+                        (expiration, self.retain_key.map(|retain| retain(key)))
+                    }),
+                    self,
+                )
+            }
+
+            /// `iter` returns an iterator over clones of the keys and values in the cache.
+            ///
+            /// The keys are only kept when the cache is built with `set_retain_keys(true)`,
+            /// otherwise the iterator is empty. Each store shard is copied under its read lock
+            /// when the iterator reaches it, so the items of a shard are a consistent snapshot,
+            /// but different shards are not taken at the same time.
+            pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_
+            where
+                K: Clone + 'static,
+                V: Clone,
+            {
+                let now = self.store.now();
+                self.store.shards().flat_map(move |shard| {
+                    shard
+                        .values()
+                        .filter_map(|item| item.retained::<K>(now))
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect::<Vec<_>>()
+                })
+            }
+
+            /// `keys` returns an iterator over clones of the keys in the cache.
+            ///
+            /// See [`iter`](#method.iter) for when the keys are available and the snapshot semantics.
+            pub fn keys(&self) -> impl Iterator<Item = K> + '_
+            where
+                K: Clone + 'static,
+            {
+                let now = self.store.now();
+                self.store.shards().flat_map(move |shard| {
+                    shard
+                        .values()
+                        .filter_map(|item| item.retained::<K>(now))
+                        .map(|(k, _)| k.clone())
+                        .collect::<Vec<_>>()
+                })
+            }
+
+            /// `for_each` calls `f` on every key and value in the cache, without cloning them.
+            ///
+            /// The read lock of each store shard is held while `f` is called on its items, so
+            /// `f` must not write to the cache. See [`iter`](#method.iter) for when the keys are available.
+            pub fn for_each<F>(&self, mut f: F)
+            where
+                K: 'static,
+                F: FnMut(&K, &V),
+            {
+                let now = self.store.now();
+                for shard in self.store.shards() {
+                    shard
+                        .values()
+                        .filter_map(|item| item.retained::<K>(now))
+                        .for_each(|(k, v)| f(k, v));
+                }
+            }
+
             /// Returns the TTL for the specified key if the
             /// item was found and is not expired.
             pub fn get_ttl<Q>(&self, key: &Q) -> Option<Duration>
@@ -258,8 +474,212 @@ macro_rules! impl_cache {
             {
                 let (index, conflict) = self.key_to_hash.build_key(key);
                 self.store
-                    .get(&index, conflict)
-                    .and_then(|_| self.store.expiration(&index).map(|time| time.get_ttl()))
+                    .live_expiration(&index, conflict)
+                    .map(|time| time.get_ttl(self.store.now()))
+            }
+
+            /// Returns the time, on the [`Clock`] of the cache, at which the value of the key
+            /// expires, if the item was found, is not expired and has an expiration.
+            ///
+            /// [`Clock`]: trait.Clock.html
+            pub fn expires_at<Q>(&self, key: &Q) -> Option<Instant>
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                let (index, conflict) = self.key_to_hash.build_key(key);
+                self.store
+                    .live_expiration(&index, conflict)
+                    .and_then(|time| time.deadline())
+            }
+
+            /// `set_ttl` makes the value of the key expire after `ttl`, counted from now,
+            /// without rewriting the value. A zero `ttl` means the value never expires.
+            ///
+            /// Returns false if the key was not found or is expired.
+            pub fn set_ttl<Q>(&self, key: &Q, ttl: Duration) -> bool
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                self.try_set_ttl(key, ttl).unwrap()
+            }
+
+            /// `try_set_ttl` is the non-panicking version of [`set_ttl`](#method.set_ttl)
+            pub fn try_set_ttl<Q>(&self, key: &Q, ttl: Duration) -> Result<bool, CacheError>
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                self.try_update_expiration(key, |_| Time::new(self.store.now(), ttl))
+            }
+
+            /// `expire_at` makes the value of the key expire at `deadline`, which is either a
+            /// [`SystemTime`] or an [`Instant`], without rewriting the value.
+            ///
+            /// Returns false if the key was not found or is expired.
+            ///
+            /// [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+            /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
+            pub fn expire_at<Q>(&self, key: &Q, deadline: impl Into<Deadline>) -> bool
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                self.try_expire_at(key, deadline).unwrap()
+            }
+
+            /// `try_expire_at` is the non-panicking version of [`expire_at`](#method.expire_at)
+            pub fn try_expire_at<Q>(
+                &self,
+                key: &Q,
+                deadline: impl Into<Deadline>,
+            ) -> Result<bool, CacheError>
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                let now = self.store.now();
+                let expiration = Time::at(now, deadline.into().instant(now));
+                self.try_update_expiration(key, |_| expiration)
+            }
+
+            /// `extend_ttl` pushes the expiration of the value of the key back by `d`. A value
+            /// which never expires is left as is.
+            ///
+            /// Returns false if the key was not found or is expired.
+            pub fn extend_ttl<Q>(&self, key: &Q, d: Duration) -> bool
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                self.try_extend_ttl(key, d).unwrap()
+            }
+
+            /// `try_extend_ttl` is the non-panicking version of [`extend_ttl`](#method.extend_ttl)
+            pub fn try_extend_ttl<Q>(&self, key: &Q, d: Duration) -> Result<bool, CacheError>
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                self.try_update_expiration(key, |exp| exp.extend(d))
+            }
+
+            /// `persist` removes the expiration of the value of the key, so that it never expires.
+            ///
+            /// Returns false if the key was not found or is expired.
+            pub fn persist<Q>(&self, key: &Q) -> bool
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                self.try_persist(key).unwrap()
+            }
+
+            /// `try_persist` is the non-panicking version of [`persist`](#method.persist)
+            pub fn try_persist<Q>(&self, key: &Q) -> Result<bool, CacheError>
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+            {
+                self.try_update_expiration(key, |_| Time::never(self.store.now()))
+            }
+
+            #[inline]
+            fn try_update_expiration<Q, F>(&self, key: &Q, f: F) -> Result<bool, CacheError>
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized,
+                F: FnOnce(Time) -> Time,
+            {
+                if self.is_closed.load(Ordering::SeqCst) {
+                    return Ok(false);
+                }
+
+                let (index, conflict) = self.key_to_hash.build_key(key);
+                self.store.try_update_expiration(index, conflict, f)
+            }
+
+            /// `new_expiration` returns the expiration of a value inserted with `ttl`. The values
+            /// inserted without TTL expire after the time-to-idle of the cache, if it has one.
+            #[inline]
+            fn new_expiration(&self, ttl: Duration) -> Time {
+                let now = self.store.now();
+                if ttl.is_zero() {
+                    Time::idle(now, self.time_to_idle)
+                } else {
+                    Time::new(now, ttl)
+                }
+            }
+
+            /// `expire_after_create` returns the expiration of a new value, as computed by the
+            /// [`Expiry`] of the cache, or `expiration` if there is none or it keeps the TTL.
+            #[inline]
+            fn expire_after_create(&self, key: &K, val: &V, expiration: Time) -> Time {
+                let now = self.store.now();
+                self.expiry
+                    .as_ref()
+                    .and_then(|expiry| expiry.expire_after_create(key, val, now))
+                    .map_or(expiration, |ttl| Time::new(now, ttl))
+            }
+
+            /// `expire_after_update` returns the expiration of an updated value whose current
+            /// expiration is `current`, as computed by the [`Expiry`] of the cache, or
+            /// `expiration` if there is none or it keeps the TTL.
+            #[inline]
+            fn expire_after_update(
+                &self,
+                key: &K,
+                val: &V,
+                current: Time,
+                expiration: Time,
+            ) -> Time {
+                let now = self.store.now();
+                self.expiry
+                    .as_ref()
+                    .and_then(|expiry| {
+                        let current_ttl = (!current.is_zero()).then(|| current.get_ttl(now));
+                        expiry.expire_after_update(key, val, now, current_ttl)
+                    })
+                    .map_or(expiration, |ttl| Time::new(now, ttl))
+            }
+
+            /// `kept_expiration` returns the current expiration of the key, or the expiration
+            /// of `insert` if the key is absent, for the inserts which keep the TTL.
+            #[inline]
+            fn kept_expiration(&self, key: &K) -> Time {
+                let (index, conflict) = self.key_to_hash.build_key(key);
+                self.store
+                    .live_expiration(&index, conflict)
+                    .unwrap_or_else(|| self.new_expiration(Duration::ZERO))
+            }
+
+            /// `export_policy_state` returns the access history of the admission policy: the
+            /// rows and the seeds of the count-min sketch, the bitset of the doorkeeper and the
+            /// reset counter, in a versioned binary format. The buffered reads are applied first.
+            ///
+            /// A new Cache built with the same `num_counters` and [`PolicyConfig`] can inherit
+            /// the history with [`import_policy_state`], e.g. after a restart, so that it does
+            /// not take its admission decisions from an empty sketch. The items are not exported.
+            ///
+            /// [`PolicyConfig`]: struct.PolicyConfig.html
+            /// [`import_policy_state`]: #method.import_policy_state
+            pub fn export_policy_state(&self) -> Vec<u8> {
+                self.get_buf.flush();
+                self.policy.flush();
+                self.policy.export_state()
+            }
+
+            /// `import_policy_state` replaces the access history of the admission policy with
+            /// the one returned by [`export_policy_state`].
+            ///
+            /// It returns an error and keeps the current history if the state is corrupted, has
+            /// an unknown version or comes from a Cache with a different number of counters or
+            /// sketch tuning.
+            ///
+            /// [`export_policy_state`]: #method.export_policy_state
+            pub fn import_policy_state(&self, state: &[u8]) -> Result<(), CacheError> {
+                self.policy.import_state(state)
             }
 
             /// `max_cost` returns the max cost of the cache.
@@ -269,9 +689,18 @@ macro_rules! impl_cache {
             }
 
             /// `update_max_cost` updates the maxCost of an existing cache.
+            ///
+            /// When the max cost is lowered, the processor evicts the items over the new
+            /// budget in the background, with the usual callbacks and metrics. If the insert
+            /// buffer is full, they are evicted by the next inserts instead. Use
+            /// `update_max_cost_and_wait` to wait until the used cost fits in the new budget.
             #[inline]
             pub fn update_max_cost(&self, max_cost: i64) {
-                self.policy.update_max_cost(max_cost)
+                let shrunk = max_cost < self.policy.max_cost();
+                self.policy.update_max_cost(max_cost);
+                if shrunk && !self.is_closed.load(Ordering::SeqCst) {
+                    let _ = self.insert_buf_tx.try_send($item::Shrink(None));
+                }
             }
 
             /// Returns the number of items in the Cache
@@ -286,36 +715,241 @@ macro_rules! impl_cache {
                 self.store.len() == 0
             }
 
+            /// `get_many` returns clones of the values of the keys, in the order of the keys.
+            ///
+            /// The keys are grouped by store shard, so that each shard lock is taken only once.
+            pub fn get_many<'q, Q, I>(&self, keys: I) -> Vec<Option<V>>
+            where
+                K: core::borrow::Borrow<Q>,
+                Q: core::hash::Hash + Eq + ?Sized + 'q,
+                I: IntoIterator<Item = &'q Q>,
+                V: Clone,
+            {
+                let keys = keys
+                    .into_iter()
+                    .map(|key| self.key_to_hash.build_key(key))
+                    .collect::<Vec<_>>();
+                let mut vals = vec![None; keys.len()];
+                if self.is_closed.load(Ordering::SeqCst) {
+                    return vals;
+                }
+
+                self.store
+                    .get_many(&keys, |pos, val| vals[pos] = Some(val.clone()));
+                for ((index, _), val) in keys.iter().zip(vals.iter()) {
+                    self.get_buf.push(*index);
+                    match val {
+                        None => self.metrics.add(MetricType::Miss, *index, 1),
+                        Some(_) => self.metrics.add(MetricType::Hit, *index, 1),
+                    };
+                }
+                vals
+            }
+
+            /// `try_update_many` is the batch version of `try_update`, it returns the items
+            /// to send to the insert buffer, and the key hashes of the new items among them.
+            #[inline]
+            fn try_update_many<I>(&self, items: I) -> Result<(Vec<$item<V>>, Vec<u64>), CacheError>
+            where
+                I: IntoIterator<Item = (K, V, i64)>,
+            {
+                let expiration = self.new_expiration(Duration::ZERO);
+                let (metas, updates): (Vec<_>, Vec<_>) = items
+                    .into_iter()
+                    .map(|(key, val, cost)| {
+                        let (index, conflict) = self.key_to_hash.build_key(&key);
+                        let external_cost = if cost == 0 { self.coster.cost(&val) } else { 0 };
+                        (
+                            (key, index, conflict, cost, external_cost),
+                            (index, val, conflict),
+                        )
+                    })
+                    .unzip();
+
+                let results = self.store.try_update_many(updates, |pos, val, current| {
+                    self.expire_after_update(&metas[pos].0, val, current, expiration)
+                })?;
+                let mut batch = Vec::with_capacity(results.len());
+                let mut new_keys = Vec::new();
+                for ((key, index, conflict, cost, external_cost), rst) in
+                    metas.into_iter().zip(results)
+                {
+                    match rst {
+                        UpdateResult::NotExist(v)
+                        | UpdateResult::Reject(v)
+                        | UpdateResult::Conflict(v) => {
+                            let expiration = self.expire_after_create(&key, &v, expiration);
+                            let retained_key = self.retain_key.map(|retain| retain(key));
+                            new_keys.push(index);
+                            batch.push($item::new(
+                                index,
+                                conflict,
+                                cost + external_cost,
+                                v,
+                                expiration,
+                                retained_key,
+                            ));
+                        }
+                        UpdateResult::Update(v) => {
+                            self.callback.on_exit(Some(v));
+                            batch.push($item::update(index, cost, external_cost));
+                        }
+                    }
+                }
+                Ok((batch, new_keys))
+            }
+
+            /// `try_send_deletes` pushes the deletions of the keys just removed from the store to
+            /// the insert buffer, so that they follow the pending insertions of the keys. If the
+            /// buffer is full, they are left to the next cleanup instead, rather than waiting for
+            /// the processor, and an error is returned.
+            fn try_send_deletes(&self, keys: Vec<(u64, u64)>) -> Result<(), CacheError> {
+                let item = match keys.as_slice() {
+                    [(index, conflict)] => $item::delete(*index, *conflict),
+                    _ => $item::Batch(
+                        keys.iter()
+                            .map(|(index, conflict)| $item::delete(*index, *conflict))
+                            .collect(),
+                    ),
+                };
+                self.insert_buf_tx.try_send(item).map_err(|e| {
+                    self.dropped_deletes.lock().extend(keys);
+                    CacheError::ChannelError(format!(
+                        "failed to send message to the insert buffer: {}",
+                        &e
+                    ))
+                })
+            }
+
+            /// `take_item` hands a removed item over to the caller instead of `on_exit`,
+            /// unless it is already expired.
+            #[inline]
+            fn take_item(&self, item: StoreItem<V>) -> Option<StoreItem<V>> {
+                if item.is_expired(self.store.now()) {
+                    self.callback.on_exit(Some(item.value.into_inner()));
+                    None
+                } else {
+                    self.callback.on_exit(None);
+                    Some(item)
+                }
+            }
+
+            /// `reject_expired` passes a new value whose deadline is already past to `on_reject`,
+            /// instead of charging the policy for a value which would expire at once.
+            fn reject_expired(&self, key: &K, val: V, cost: i64, expiration: Time) {
+                let (index, conflict) = self.key_to_hash.build_key(key);
+                self.metrics.add(MetricType::RejectSets, index, 1);
+                self.callback.on_reject(CrateItem {
+                    val: Some(val),
+                    index,
+                    conflict,
+                    cost,
+                    exp: expiration,
+                });
+            }
+
+            /// `reject_too_large` passes a new item whose cost, with the internal cost, is bigger
+            /// than the whole cache to `on_reject`, as the policy would never admit it. Other items
+            /// are returned as is.
+            ///
+            /// Only the inserts which report an [`InsertOutcome`] and the snapshot restore reject
+            /// them early, the plain inserts leave it to the policy.
+            #[inline]
+            fn reject_too_large(&self, item: $item<V>) -> Option<$item<V>> {
+                let internal_cost = if self.ignore_internal_cost {
+                    0
+                } else {
+                    self.store.item_size() as i64
+                };
+                match item {
+                    $item::New {
+                        key,
+                        conflict,
+                        cost,
+                        value,
+                        expiration,
+                        ..
+                    } if cost + internal_cost > self.policy.max_cost() => {
+                        self.metrics.add(MetricType::RejectSets, key, 1);
+                        self.metrics.add(MetricType::RejectCost, key, 1);
+                        self.callback.on_reject(CrateItem {
+                            val: Some(value),
+                            index: key,
+                            conflict,
+                            cost,
+                            exp: expiration,
+                        });
+                        None
+                    }
+                    item => Some(item),
+                }
+            }
+
+            /// `dropped` returns the outcome of an item which could not be sent to the insert buffer.
+            #[inline]
+            fn dropped(
+                &self,
+                index: u64,
+                is_update: bool,
+                outcome: InsertOutcome,
+            ) -> InsertOutcome {
+                if is_update {
+                    // The store is already updated, only the cost update of the policy is lost.
+                    InsertOutcome::Updated
+                } else {
+                    self.metrics.add(MetricType::DropSets, index, 1);
+                    outcome
+                }
+            }
+
+            /// `drop_batch` records the new items of a dropped batch, returns true if the batch
+            /// only had updates, which are already applied to the store.
+            #[inline]
+            fn drop_batch(&self, new_keys: &[u64]) -> bool {
+                for index in new_keys {
+                    self.metrics.add(MetricType::DropSets, *index, 1);
+                }
+                new_keys.is_empty()
+            }
+
             #[inline]
             fn try_update(
                 &self,
                 key: K,
                 val: V,
                 cost: i64,
-                ttl: Duration,
+                expiration: Time,
                 only_update: bool,
             ) -> Result<Option<(u64, $item<V>)>, CacheError> {
-                let expiration = if ttl.is_zero() {
-                    Time::now()
-                } else {
-                    Time::now_with_expiration(ttl)
-                };
-
                 let (index, conflict) = self.key_to_hash.build_key(&key);
 
                 // cost is eventually updated. The expiration must also be immediately updated
                 // to prevent items from being prematurely removed from the map.
                 let external_cost = if cost == 0 { self.coster.cost(&val) } else { 0 };
-                match self.store.try_update(index, val, conflict, expiration)? {
+                let update = self
+                    .store
+                    .try_update_with(index, val, conflict, |val, current| {
+                        self.expire_after_update(&key, val, current, expiration)
+                    })?;
+                match update {
                     UpdateResult::NotExist(v)
                     | UpdateResult::Reject(v)
                     | UpdateResult::Conflict(v) => {
                         if only_update {
                             Ok(None)
                         } else {
+                            let expiration = self.expire_after_create(&key, &v, expiration);
+                            let retained_key = self.retain_key.map(|retain| retain(key));
                             Ok(Some((
                                 index,
-                                $item::new(index, conflict, cost + external_cost, v, expiration),
+                                $item::new(
+                                    index,
+                                    conflict,
+                                    cost + external_cost,
+                                    v,
+                                    expiration,
+                                    retained_key,
+                                ),
                             )))
                         }
                     }
@@ -325,6 +959,72 @@ macro_rules! impl_cache {
                     }
                 }
             }
+
+            /// `warm_in` loads a new item directly in the store and the policy, if there is
+            /// room left for it and its key is not in the store. It returns whether the item
+            /// was loaded, a skipped value is passed to `on_reject`.
+            fn warm_in(
+                &self,
+                key: K,
+                val: V,
+                cost: i64,
+                frequency: u8,
+            ) -> Result<bool, CacheError> {
+                let (index, conflict) = self.key_to_hash.build_key(&key);
+                let cost = if cost == 0 {
+                    self.coster.cost(&val)
+                } else {
+                    cost
+                };
+                let internal_cost = if self.ignore_internal_cost {
+                    0
+                } else {
+                    self.store.item_size() as i64
+                };
+                let expiration =
+                    self.expire_after_create(&key, &val, self.new_expiration(Duration::ZERO));
+                let retained_key = self.retain_key.map(|retain| retain(key));
+
+                // the journal is locked before the store, as by the compaction. The item is put
+                // in the store before it is charged to the policy, and both happen under the
+                // shard lock, so that the processor cannot evict it in between.
+                let mut journal = self.journal.as_ref().map(|journal| journal.lock());
+                let mut data = self.store.shard_mut(index);
+                if data.contains_key(&index) {
+                    drop((data, journal));
+                    self.callback.on_reject(CrateItem {
+                        val: Some(val),
+                        index,
+                        conflict,
+                        cost,
+                        exp: expiration,
+                    });
+                    return Ok(false);
+                }
+
+                self.store
+                    .insert_in(&mut data, index, val, conflict, expiration, retained_key);
+                if !self.policy.warm(index, cost + internal_cost, frequency) {
+                    let item = self.store.try_remove_in(&mut data, &index, conflict)?;
+                    drop((data, journal));
+                    if let Some(item) = item {
+                        self.callback.on_reject(CrateItem {
+                            val: Some(item.value.into_inner()),
+                            index,
+                            conflict,
+                            cost,
+                            exp: item.expiration,
+                        });
+                    }
+                    return Ok(false);
+                }
+
+                self.metrics.add(MetricType::KeyAdd, index, 1);
+                if let (Some(journal), Some(item)) = (journal.as_mut(), data.get(&index)) {
+                    journal.put(item, cost, self.store.now());
+                }
+                Ok(true)
+            }
         }
 
         impl<K, V, KH, C, U, CB, S> AsRef<$cache<K, V, KH, C, U, CB, S>>
@@ -343,6 +1043,125 @@ macro_rules! impl_cache {
             }
         }
 
+        impl<K, V, KH, C, U, CB, S> EntryBackend<V, S> for $cache<K, V, KH, C, U, CB, S>
+        where
+            K: Hash + Eq,
+            V: Send + Sync + 'static,
+            KH: KeyBuilder<Key = K>,
+            C: Coster<Value = V>,
+            U: UpdateValidator<Value = V>,
+            CB: CacheCallback<Value = V>,
+            S: BuildHasher + Clone + 'static,
+        {
+            fn now(&self) -> Instant {
+                self.store.now()
+            }
+
+            fn insert_new(
+                &self,
+                data: &mut HashMap<u64, StoreItem<V>, S>,
+                index: u64,
+                conflict: u64,
+                val: V,
+                expiration: Time,
+                retained_key: Option<RetainedKey>,
+            ) {
+                self.store
+                    .insert_in(data, index, val, conflict, expiration, retained_key);
+            }
+
+            fn try_send_stored(&self, index: u64, conflict: u64, cost: i64, val: &V) -> bool {
+                let external_cost = if cost == 0 { self.coster.cost(val) } else { 0 };
+                let item = $item::stored(index, conflict, cost + external_cost);
+                if self.insert_buf_tx.try_send(item).is_ok() {
+                    true
+                } else {
+                    self.metrics.add(MetricType::DropSets, index, 1);
+                    false
+                }
+            }
+
+            fn try_send_update(&self, index: u64, cost: i64, val: &V) {
+                let external_cost = if cost == 0 { self.coster.cost(val) } else { 0 };
+                // The value is already updated in the store, if the update is dropped
+                // only the cost tracked by the policy is stale, as in `insert`.
+                let _ = self
+                    .insert_buf_tx
+                    .try_send($item::update(index, cost, external_cost));
+            }
+
+            fn try_send_delete(&self, index: u64, conflict: u64, expiration: Time) {
+                // A stale expiration is harmless, the cleanup checks the store before removing.
+                let _ = self.store.try_remove_expiration(&index, expiration);
+                let _ = self.try_send_deletes(vec![(index, conflict)]);
+            }
+        }
+
+        #[cfg(feature = "serilization")]
+        #[cfg_attr(docsrs, doc(cfg(feature = "serilization")))]
+        impl<K, V, KH, C, U, CB, S> $cache<K, V, KH, C, U, CB, S>
+        where
+            K: Hash + Eq,
+            V: Send + Sync + 'static,
+            KH: KeyBuilder<Key = K>,
+            C: Coster<Value = V>,
+            U: UpdateValidator<Value = V>,
+            CB: CacheCallback<Value = V>,
+            S: BuildHasher + Clone + 'static,
+        {
+            /// `save_snapshot` writes the items of the cache to `writer` as JSON, with their key,
+            /// value, cost and remaining TTL (or time-to-idle), so that a new Cache can be
+            /// filled with them by `load_snapshot` of the builder, e.g. after a restart.
+            ///
+            /// The keys are only kept when the cache is built with `set_retain_keys(true)`,
+            /// otherwise it returns an error. Each store shard is written under its read lock,
+            /// see [`iter`](#method.iter) for the snapshot semantics.
+            pub fn save_snapshot<W: std::io::Write>(&self, writer: W) -> Result<(), CacheError>
+            where
+                K: serde::Serialize + 'static,
+                V: serde::Serialize,
+            {
+                if self.retain_key.is_none() {
+                    return Err(CacheError::SnapshotError(
+                        "the keys are not retained, see set_retain_keys".to_string(),
+                    ));
+                }
+
+                SnapshotWriter::<K, _, _, _, _>::new(&self.store, |key| self.item_cost(key))
+                    .write(writer)
+            }
+
+            /// `item_cost` returns the cost of the item of the key hash given at the insert,
+            /// without the internal cost.
+            fn item_cost(&self, key: u64) -> i64 {
+                let internal_cost = if self.ignore_internal_cost {
+                    0
+                } else {
+                    self.store.item_size() as i64
+                };
+                self.policy.cost(&key) - internal_cost
+            }
+
+            /// `restore_item` prepares the item of a snapshot entry for the insert buffer,
+            /// its TTL is counted from now.
+            fn restore_item(
+                &self,
+                entry: SnapshotEntry<K, V>,
+            ) -> Result<Option<$item<V>>, CacheError> {
+                let now = self.store.now();
+                let expiration = match (entry.ttl, entry.tti) {
+                    // a zero TTL would never expire.
+                    (Some(ttl), _) => Time::new(now, ttl.max(Duration::from_nanos(1))),
+                    (None, Some(tti)) => Time::idle(now, tti),
+                    (None, None) => self.new_expiration(Duration::ZERO),
+                };
+
+                Ok(self
+                    .try_update(entry.key, entry.value, entry.cost, expiration, false)?
+                    .and_then(|(_, item)| self.reject_too_large(item)))
+            }
+        }
+
         impl<K, V, KH, C, U, CB, S> Clone for $cache<K, V, KH, C, U, CB, S>
         where
             K: Hash + Eq,
@@ -357,6 +1176,14 @@ macro_rules! impl_cache {
                 Self {
                     store: self.store.clone(),
                     policy: self.policy.clone(),
+                    get_buf: self.get_buf.clone(),
+                    loaders: self.loaders.clone(),
+                    retain_key: self.retain_key,
+                    time_to_idle: self.time_to_idle,
+                    ignore_internal_cost: self.ignore_internal_cost,
+                    journal: self.journal.clone(),
+                    dropped_deletes: self.dropped_deletes.clone(),
+                    expiry: self.expiry.clone(),
                     insert_buf_tx: self.insert_buf_tx.clone(),
                     stop_tx: self.stop_tx.clone(),
                     clear_tx: self.clear_tx.clone(),
@@ -375,13 +1202,14 @@ macro_rules! impl_cache {
 macro_rules! impl_cache_processor {
     ($processor: ident, $item: ident) => {
         use crate::cache::CrateItem;
+        use crate::policy::PolicyPair;
 
         impl<V, U, CB, S> $processor<V, U, CB, S>
         where
             V: Send + Sync + 'static,
             U: UpdateValidator<Value = V>,
             CB: CacheCallback<Value = V>,
-            S: BuildHasher + Clone + 'static + Send,
+            S: BuildHasher + Clone + 'static + Send + Sync,
         {
             #[inline]
             fn handle_item(&mut self, item: $item<V>) -> Result<(), CacheError> {
@@ -392,12 +1220,22 @@ macro_rules! impl_cache_processor {
                         cost,
                         value,
                         expiration,
+                        retained_key,
+                        admission,
                     } => {
+                        self.report.applied += 1;
                         let cost = self.calculate_internal_cost(cost);
                         let (victim_sets, added) = self.policy.add(key, cost);
                         if added {
-                            self.store.try_insert(key, value, conflict, expiration)?;
+                            self.store.try_insert(
+                                key,
+                                value,
+                                conflict,
+                                expiration,
+                                retained_key,
+                            )?;
                             self.track_admission(key);
+                            self.journal_put(key);
                         } else {
                             self.callback.on_reject(CrateItem {
                                 val: Some(value),
@@ -407,21 +1245,43 @@ macro_rules! impl_cache_processor {
                                 exp: expiration,
                             });
                         }
+                        if let Some(tx) = admission {
+                            let _ = tx.try_send(added);
+                        }
 
-                        for victims in victim_sets {
-                            for victim in victims {
-                                let sitem = self.store.try_remove(&victim.key, 0)?;
-                                if let Some(sitem) = sitem {
-                                    let item = CrateItem {
-                                        index: victim.key,
-                                        val: Some(sitem.value.into_inner()),
-                                        cost: victim.cost,
-                                        conflict: sitem.conflict,
-                                        exp: sitem.expiration,
-                                    };
-                                    self.on_evict(item);
-                                }
-                            }
+                        if let Some(victims) = victim_sets {
+                            self.evict_victims(victims)?;
+                        }
+
+                        Ok(())
+                    }
+                    $item::Stored {
+                        key,
+                        conflict,
+                        cost,
+                    } => {
+                        self.report.applied += 1;
+                        let cost = self.calculate_internal_cost(cost);
+                        let (victim_sets, added) = self.policy.add(key, cost);
+                        if added {
+                            self.track_admission(key);
+                            self.journal_put(key);
+                        } else if self.policy.contains(&key) {
+                            // the entry replaced an expired item which the policy still tracks,
+                            // its cost is updated.
+                            self.journal_put(key);
+                        } else if let Some(sitem) = self.store.try_remove(&key, conflict)? {
+                            self.callback.on_reject(CrateItem {
+                                val: Some(sitem.value.into_inner()),
+                                index: key,
+                                conflict,
+                                cost,
+                                exp: sitem.expiration,
+                            });
+                        }
+
+                        if let Some(victims) = victim_sets {
+                            self.evict_victims(victims)?;
                         }
 
                         Ok(())
@@ -431,23 +1291,109 @@ macro_rules! impl_cache_processor {
                         cost,
                         external_cost,
                     } => {
+                        self.report.applied += 1;
                         let cost = self.calculate_internal_cost(cost) + external_cost;
                         self.policy.update(&key, cost);
+                        self.journal_put(key);
 
                         Ok(())
                     }
                     $item::Delete { key, conflict } => {
+                        self.report.applied += 1;
                         self.policy.remove(&key); // deals with metrics updates.
+                        self.journal_remove(key, conflict);
                         if let Some(sitem) = self.store.try_remove(&key, conflict)? {
                             self.callback.on_exit(Some(sitem.value.into_inner()));
                         }
 
                         Ok(())
                     }
+                    $item::Batch(items) => items
+                        .into_iter()
+                        .try_for_each(|item| self.handle_item(item)),
                     $item::Wait(wg) => {
                         wg.done();
                         Ok(())
                     }
+                    $item::Shrink(wg) => {
+                        let victims = self.policy.evict_overflow();
+                        self.evict_victims(victims)?;
+                        if let Some(wg) = wg {
+                            wg.done();
+                        }
+                        Ok(())
+                    }
+                    $item::Maintain(tx) => {
+                        // the items pushed before are already applied, settle the rest.
+                        self.policy.flush();
+                        self.cleanup_expired()?;
+                        let _ = tx.try_send(mem::take(&mut self.report));
+                        Ok(())
+                    }
+                }
+            }
+
+            /// `evict_victims` removes the items evicted by the policy from the store.
+            fn evict_victims(&mut self, victims: Vec<PolicyPair>) -> Result<(), CacheError> {
+                for victim in victims {
+                    if let Some(sitem) = self.store.try_remove(&victim.key, 0)? {
+                        self.report.evicted += 1;
+                        self.journal_remove(victim.key, sitem.conflict);
+                        let item = CrateItem {
+                            index: victim.key,
+                            val: Some(sitem.value.into_inner()),
+                            cost: victim.cost,
+                            conflict: sitem.conflict,
+                            exp: sitem.expiration,
+                        };
+                        self.on_evict(item);
+                    }
+                }
+                Ok(())
+            }
+
+            /// `apply_dropped_deletes` applies the deletions which were dropped by the full
+            /// insert buffer, so that the policy does not keep the cost of the removed keys.
+            fn apply_dropped_deletes(&mut self) -> Result<(), CacheError> {
+                let dropped = mem::take(&mut *self.dropped_deletes.lock());
+                dropped
+                    .into_iter()
+                    .try_for_each(|(key, conflict)| self.handle_item($item::delete(key, conflict)))
+            }
+
+            /// `journal_put` appends the item of the key to the journal, if any.
+            fn journal_put(&self, key: u64) {
+                if let Some(journal) = self.journal.as_ref() {
+                    let cost = self.policy.cost(&key) - self.calculate_internal_cost(0);
+                    let now = self.store.now();
+                    // the journal is locked before the store, as by the compaction.
+                    let mut journal = journal.lock();
+                    self.store
+                        .inspect(&key, |item| journal.put(item, cost, now));
+                }
+            }
+
+            /// `journal_remove` appends the removal of the key to the journal, if any.
+            fn journal_remove(&self, key: u64, conflict: u64) {
+                if let Some(journal) = self.journal.as_ref() {
+                    journal.lock().remove(key, conflict);
+                }
+            }
+
+            /// `maintain_journal` flushes the journal to the disk, or compacts it once it
+            /// grew enough. Both run on the worker of the journal, the processor only copies
+            /// the live items for a compaction. A failed compaction is retried by the next one.
+            fn maintain_journal(&self) {
+                if let Some(journal) = self.journal.as_ref() {
+                    let mut journal = journal.lock();
+                    if journal.needs_compaction() {
+                        let internal_cost = self.calculate_internal_cost(0);
+                        journal.start_compaction(&self.store, |key| {
+                            self.policy.cost(&key) - internal_cost
+                        });
+                    } else {
+                        journal.sync();
+                    }
                 }
             }
 
@@ -478,7 +1424,7 @@ macro_rules! impl_cache_processor {
                             ctr += 1;
                             ctr < self.num_to_keep - 1
                         });
-                        self.start_ts.insert(key, Time::now());
+                        self.start_ts.insert(key, self.store.now());
                     }
                 }
             }
@@ -486,7 +1432,8 @@ macro_rules! impl_cache_processor {
             #[inline]
             fn prepare_evict(&mut self, item: &CrateItem<V>) {
                 if let Some(ts) = self.start_ts.get(&item.index) {
-                    self.metrics.track_eviction(ts.elapsed().as_secs() as i64);
+                    let life = self.store.now().saturating_duration_since(*ts);
+                    self.metrics.track_eviction(life.as_secs() as i64);
                     self.start_ts.remove(&item.index);
                 }
             }
@@ -517,6 +1464,7 @@ macro_rules! impl_cache_cleaner {
                         cost,
                         value,
                         expiration,
+                        ..
                     } => self.processor.callback.on_evict(CrateItem::new(
                         key,
                         conflict,
@@ -524,8 +1472,31 @@ macro_rules! impl_cache_cleaner {
                         Some(value),
                         expiration,
                     )),
+                    // the store is cleared as well, unless the entry put the value there after.
+                    $item::Stored {
+                        key,
+                        conflict,
+                        cost,
+                    } => {
+                        if let Ok(Some(sitem)) = self.processor.store.try_remove(&key, conflict) {
+                            self.processor.callback.on_evict(CrateItem::new(
+                                key,
+                                conflict,
+                                cost,
+                                Some(sitem.value.into_inner()),
+                                sitem.expiration,
+                            ))
+                        }
+                    }
                     $item::Delete { .. } | $item::Update { .. } => {}
-                    $item::Wait(wg) => wg.done(),
+                    $item::Batch(items) => {
+                        items.into_iter().for_each(|item| self.handle_item(item))
+                    }
+                    $item::Wait(wg) | $item::Shrink(Some(wg)) => wg.done(),
+                    $item::Shrink(None) => {}
+                    $item::Maintain(tx) => {
+                        let _ = tx.try_send(mem::take(&mut self.processor.report));
+                    }
                 }
             }
         }
@@ -533,9 +1504,19 @@ macro_rules! impl_cache_cleaner {
 }
 
 mod builder;
+mod entry;
+#[cfg_attr(not(feature = "serilization"), allow(dead_code))]
+mod journal;
+mod outcome;
+mod singleflight;
+#[cfg(feature = "serilization")]
+mod snapshot;
 #[cfg(test)]
 mod test;
 
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use outcome::{DropReason, InsertOutcome, MaintenanceReport, WarmReport};
+
 use crate::Item as CrateItem;
 use std::time::Duration;
 
@@ -555,5 +1536,7 @@ pub use axync::{AsyncCache, AsyncCacheBuilder};
 
 // TODO: find the optimal value for this
 const DEFAULT_INSERT_BUF_SIZE: usize = 32 * 1024;
-// const DEFAULT_BUFFER_ITEMS: usize = 64;
+const DEFAULT_BUFFER_ITEMS: usize = 64;
 const DEFAULT_CLEANUP_DURATION: Duration = Duration::from_millis(500);
+pub(crate) const DEFAULT_EXPIRATION_RESOLUTION: Duration = Duration::from_secs(1);
+const MIN_EXPIRATION_RESOLUTION: Duration = Duration::from_millis(1);
@@ -2,25 +2,39 @@ use crate::axync::{
     bounded, select, stop_channel, unbounded, Receiver, RecvError, Sender, WaitGroup,
 };
 use crate::cache::builder::CacheBuilderCore;
+#[cfg(feature = "serilization")]
+use crate::cache::journal;
+use crate::cache::journal::Journal;
+use crate::cache::singleflight::{Flight, Group};
+#[cfg(feature = "serilization")]
+use crate::cache::snapshot::Snapshot;
+use crate::clock::COARSE_RESOLUTION;
 use crate::policy::AsyncLFUPolicy;
-use crate::store::ShardedMap;
+use crate::ring::RingBuffer;
+use crate::store::{RetainedKey, ShardedMap, StoreItem};
 use crate::ttl::{ExpirationMap, Time};
 use crate::{
-    metrics::MetricType, CacheCallback, CacheError, Coster, DefaultCacheCallback, DefaultCoster,
-    DefaultKeyBuilder, DefaultUpdateValidator, KeyBuilder, Metrics, UpdateValidator,
+    metrics::MetricType, Admission, CacheCallback, CacheError, Clock, Coster, Deadline,
+    DefaultCacheCallback, DefaultClock, DefaultCoster, DefaultKeyBuilder, DefaultUpdateValidator,
+    EvictionPolicy, Expiry, KeyBuilder, MaintenanceReport, Metrics, PolicyConfig, SampledLfu,
+    UpdateValidator, WarmReport,
 };
 use async_io::Timer;
 use futures::{
-    future::{BoxFuture, FutureExt},
-    stream::StreamExt,
+    future::{BoxFuture, Future, FutureExt},
+    stream::{Stream, StreamExt},
 };
+use parking_lot::Mutex;
 use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::hash::{BuildHasher, Hash};
 use std::marker::PhantomData;
+use std::mem;
+#[cfg(feature = "serilization")]
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// The `AsyncCacheBuilder` struct is used when creating [`AsyncCache`] instances if you want to customize the [`AsyncCache`] settings.
 ///
@@ -66,6 +80,13 @@ use std::time::Duration;
 ///     try increasing this value in increments of 32 * 1024. This is a fine-tuning mechanism
 ///     and you probably won't have to touch this.
 ///
+/// - **buffer_items**
+///
+///     `buffer_items` is the size of the Get buffers. Reads are recorded into lossy, striped buffers,
+///     and each full buffer is handed to the admission policy as one batch.
+///
+///     Unless you have a rare use case, using `64` as the `buffer_items` value results in good performance.
+///
 /// - **metrics**
 ///
 ///     Metrics is true when you want real-time logging of a variety of stats.
@@ -82,6 +103,57 @@ use std::time::Duration;
 ///
 ///     The Cache will cleanup the expired values every 500ms by default.
 ///
+/// - **expiration_resolution**
+///
+///     The expiring values are kept in a hierarchical timer wheel whose slots span 1s by default,
+///     so the cleanup reclaims a value at most one slot after its deadline. Lower it (down to
+///     one millisecond) along with the `cleanup_duration` for values which live less than a few seconds.
+///
+/// - **time_to_idle**
+///
+///     The values inserted without a TTL expire once they have not been read for `time_to_idle`.
+///     Reads only record the access time, the expiration is rescheduled lazily by the cleanup.
+///     Zero by default, which means the values never expire.
+///
+/// - **expiry**
+///
+///     An [`Expiry`] computes the TTL of a value from the value itself when it is created, updated
+///     or read, and takes precedence over the TTL given to `insert_with_ttl`. Returning `None` from
+///     one of its callbacks keeps the TTL the value would have had without it.
+///
+/// - **clock**
+///
+///     The [`Clock`] measures all the expirations of the Cache. The default [`DefaultClock`] is
+///     monotonic and coarse, its time is cached and refreshed every millisecond by the processor.
+///     Set a [`MockClock`] to advance the time by hand in tests.
+///
+/// - **eviction_policy**
+///
+///     The [`EvictionPolicy`] chooses the keys to evict when the Cache is full. The default is the
+///     [`SampledLfu`], [`Lru`], [`Slru`], [`S3Fifo`] and [`Sieve`] are also provided, TinyLFU still
+///     rejects a new key which is less popular than their victim. The adaptive [`WTinyLfu`] admits
+///     all the new keys into a window and decides the admission by itself.
+///
+/// - **admission**
+///
+///     The [`Admission`] decides whether a new key is worth evicting the victims when the Cache is
+///     full. The default is strict TinyLFU, `Always` admits all the new keys, and `TinyLfuJitter`
+///     admits a small share of the warm keys TinyLFU would reject, against hash flooding.
+///
+/// - **policy_config**
+///
+///     The [`PolicyConfig`] tunes the number of keys sampled by the default [`SampledLfu`], the
+///     false positive ratio of the doorkeeper, the reset interval of TinyLFU and the depth of its
+///     count-min sketch. It is validated by `finalize`.
+///
+/// - **retain_keys**
+///
+///     By default, the Cache only stores the hashes of the keys. Set `retain_keys` to true to keep the
+///     original keys alongside the values, so that the Cache can be iterated with `iter`, `keys` and
+///     `for_each` (and `stream` for the `AsyncCache`).
+///
+///     Keep in mind that retaining the keys increases the memory usage.
+///
 /// - **update_validator**
 ///
 ///     By default, the Cache will always update the value if the value already exists in the cache.
@@ -118,6 +190,19 @@ use std::time::Duration;
 /// [`UpdateValidator`]: trait.UpdateValidator.html
 /// [`CacheCallback`]: trait.CacheCallback.html
 /// [`Coster`]: trait.Coster.html
+/// [`Expiry`]: trait.Expiry.html
+/// [`Clock`]: trait.Clock.html
+/// [`DefaultClock`]: struct.DefaultClock.html
+/// [`MockClock`]: struct.MockClock.html
+/// [`EvictionPolicy`]: trait.EvictionPolicy.html
+/// [`SampledLfu`]: struct.SampledLfu.html
+/// [`Lru`]: struct.Lru.html
+/// [`Slru`]: struct.Slru.html
+/// [`S3Fifo`]: struct.S3Fifo.html
+/// [`Sieve`]: struct.Sieve.html
+/// [`WTinyLfu`]: struct.WTinyLfu.html
+/// [`Admission`]: enum.Admission.html
+/// [`PolicyConfig`]: struct.PolicyConfig.html
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 pub struct AsyncCacheBuilder<
     K,
@@ -178,6 +263,18 @@ where
         self,
         spawner: SP,
     ) -> Result<AsyncCache<K, V, KH, C, U, CB, S>, CacheError>
+    where
+        SP: Fn(BoxFuture<'static, ()>) -> R + Send + Sync + 'static + Copy,
+    {
+        self.build(spawner, None)
+    }
+
+    /// `build` builds the Cache, whose processor appends to `journal` if it is set.
+    fn build<SP, R>(
+        self,
+        spawner: SP,
+        journal: Option<Arc<Mutex<Journal<V>>>>,
+    ) -> Result<AsyncCache<K, V, KH, C, U, CB, S>, CacheError>
     where
         SP: Fn(BoxFuture<'static, ()>) -> R + Send + Sync + 'static + Copy,
     {
@@ -192,25 +289,59 @@ where
             return Err(CacheError::InvalidMaxCost);
         }
 
+        let policy_config = self.inner.policy_config;
+        policy_config.validate()?;
+
         let insert_buffer_size = self.inner.insert_buffer_size;
         if insert_buffer_size == 0 {
             return Err(CacheError::InvalidBufferSize);
         }
 
+        let buffer_items = self.inner.buffer_items;
+        if buffer_items == 0 {
+            return Err(CacheError::InvalidBufferItems);
+        }
+
         let (buf_tx, buf_rx) = bounded(insert_buffer_size);
         let (stop_tx, stop_rx) = stop_channel();
         let (clear_tx, clear_rx) = unbounded();
 
         let hasher = self.inner.hasher.unwrap();
-        let expiration_map = ExpirationMap::with_hasher(hasher.clone());
+        let clock = self
+            .inner
+            .clock
+            .unwrap_or_else(|| Arc::new(DefaultClock::new()));
+        let expiration_map = ExpirationMap::with_hasher(
+            hasher.clone(),
+            clock.now(),
+            self.inner.expiration_resolution,
+        );
 
-        let store = Arc::new(ShardedMap::with_validator_and_hasher(
+        let mut store = ShardedMap::with_validator_and_hasher(
             expiration_map,
             self.inner.update_validator.unwrap(),
             hasher.clone(),
-        ));
-
-        let mut policy = AsyncLFUPolicy::with_hasher(num_counters, max_cost, hasher, spawner)?;
+            clock,
+        );
+        if let Some(read_expiry) = self.inner.read_expiry {
+            store.set_read_expiry(read_expiry);
+        }
+        let store = Arc::new(store);
+
+        let eviction = self
+            .inner
+            .eviction
+            .unwrap_or_else(|| Box::new(SampledLfu::with_samples(policy_config.samples)));
+        let mut policy = AsyncLFUPolicy::with_hasher(
+            num_counters,
+            max_cost,
+            hasher,
+            eviction,
+            policy_config,
+            spawner,
+        )?;
+
+        policy.set_admission(self.inner.admission);
 
         let coster = Arc::new(self.inner.coster.unwrap());
         let callback = Arc::new(self.inner.callback.unwrap());
@@ -223,6 +354,8 @@ where
         };
 
         let policy = Arc::new(policy);
+        let dropped_deletes = Arc::new(Mutex::new(Vec::new()));
+        let get_buf = Arc::new(RingBuffer::new(policy.clone(), buffer_items));
         CacheProcessor::new(
             100000,
             self.inner.ignore_internal_cost,
@@ -234,6 +367,8 @@ where
             clear_rx,
             metrics.clone(),
             callback.clone(),
+            journal.clone(),
+            dropped_deletes.clone(),
         )
         .spawn(Box::new(move |fut| {
             spawner(fut);
@@ -242,6 +377,14 @@ where
         let this = AsyncCache {
             store,
             policy,
+            get_buf,
+            loaders: Arc::new(Group::new()),
+            retain_key: self.inner.retain_key,
+            time_to_idle: self.inner.time_to_idle,
+            ignore_internal_cost: self.inner.ignore_internal_cost,
+            journal,
+            dropped_deletes,
+            expiry: self.inner.expiry,
             insert_buf_tx: buf_tx,
             callback,
             key_to_hash: Arc::new(self.inner.key_to_hash),
@@ -255,6 +398,88 @@ where
 
         Ok(this)
     }
+
+    /// Build Cache and fill it with the items of a snapshot written by
+    /// [`AsyncCache::save_snapshot`](struct.AsyncCache.html#method.save_snapshot).
+    ///
+    /// `spawner` is the spawn function of your async runtime, as for
+    /// [`finalize`](#method.finalize).
+    ///
+    /// The items are admitted directly, whatever the admission of the Cache, and keep the
+    /// cost and the remaining TTL they had when the snapshot was saved. They still evict each
+    /// other if the snapshot does not fit in the max cost. It returns once all the items
+    /// have been handled by the policy.
+    #[cfg(feature = "serilization")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serilization")))]
+    pub async fn load_snapshot<SP, R, Rd>(
+        self,
+        reader: Rd,
+        spawner: SP,
+    ) -> Result<AsyncCache<K, V, KH, C, U, CB, S>, CacheError>
+    where
+        SP: Fn(BoxFuture<'static, ()>) -> R + Send + Sync + 'static + Copy,
+        Rd: std::io::Read,
+        K: serde::de::DeserializeOwned,
+        V: serde::de::DeserializeOwned,
+    {
+        let snapshot = Snapshot::<K, V>::from_reader(reader)?;
+        let admission = self.inner.admission;
+        let cache = self.finalize(spawner)?;
+        cache.restore(snapshot.entries, admission).await?;
+        Ok(cache)
+    }
+
+    /// Build Cache with a journal at `path`, and fill it with the items the journal holds,
+    /// e.g. after a restart or a crash. The journal is created if it does not exist.
+    ///
+    /// `spawner` is the spawn function of your async runtime, as for
+    /// [`finalize`](#method.finalize).
+    ///
+    /// The processor appends the admitted inserts, the updates, the removals, the evictions and
+    /// the expirations to the journal, and flushes it to the disk at each cleanup. The records
+    /// torn by a crash are ignored by the replay. The journal is rewritten with the live items
+    /// when it is opened, and again once it has grown to twice their number. The flushes and
+    /// the later rewrites run on a dedicated thread, off the processor. Closing the Cache
+    /// flushes and detaches the journal first, so that it keeps the items. The keys are
+    /// retained, see [`set_retain_keys`](#method.set_retain_keys).
+    ///
+    /// The items are restored as by [`load_snapshot`](#method.load_snapshot), and keep their
+    /// deadline: the time spent down counts toward their TTL. The values changed in place,
+    /// e.g. with `get_mut`, are journaled by their next update only.
+    #[cfg(feature = "serilization")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serilization")))]
+    pub async fn open_journal<SP, R, P>(
+        self,
+        path: P,
+        spawner: SP,
+    ) -> Result<AsyncCache<K, V, KH, C, U, CB, S>, CacheError>
+    where
+        SP: Fn(BoxFuture<'static, ()>) -> R + Send + Sync + 'static + Copy,
+        P: AsRef<Path>,
+        K: serde::Serialize + serde::de::DeserializeOwned + Send + Sync + 'static,
+        V: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let path = path.as_ref();
+        let entries = journal::replay(path)?
+            .iter()
+            .filter_map(|payload| journal::decode_put::<K, V>(payload).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let journal = Arc::new(Mutex::new(Journal::new(
+            path.to_path_buf(),
+            journal::encode_put::<K, V>,
+        )));
+        let admission = self.inner.admission;
+        let cache = self
+            .set_retain_keys(true)
+            .build(spawner, Some(journal.clone()))?;
+        cache.restore(entries, admission).await?;
+        journal
+            .lock()
+            .compact(&cache.store, |key| cache.item_cost(key))?;
+        Journal::spawn_worker(&journal);
+        Ok(cache)
+    }
 }
 
 pub(crate) struct CacheProcessor<V, U, CB, S> {
@@ -264,12 +489,19 @@ pub(crate) struct CacheProcessor<V, U, CB, S> {
     metrics: Arc<Metrics>,
     store: Arc<ShardedMap<V, U, S, S>>,
     policy: Arc<AsyncLFUPolicy<S>>,
-    start_ts: HashMap<u64, Time, S>,
+    start_ts: HashMap<u64, Instant, S>,
     num_to_keep: usize,
     callback: Arc<CB>,
     ignore_internal_cost: bool,
     item_size: usize,
     cleanup_duration: Duration,
+    /// report counts the work done since the last `Maintain` item.
+    report: MaintenanceReport,
+    /// journal is the write-ahead journal of the cache, if it has one.
+    journal: Option<Arc<Mutex<Journal<V>>>>,
+    /// dropped_deletes keeps the deletions dropped by the full insert buffer, they are
+    /// applied at the next cleanup.
+    dropped_deletes: Arc<Mutex<Vec<(u64, u64)>>>,
 }
 
 pub(crate) struct CacheCleaner<'a, V, U, CB, S> {
@@ -283,6 +515,15 @@ pub(crate) enum Item<V> {
         cost: i64,
         value: V,
         expiration: Time,
+        retained_key: Option<RetainedKey>,
+        admission: Option<Sender<bool>>,
+    },
+    /// Stored is a new item which an entry put in the store already, the policy decides
+    /// whether it stays there.
+    Stored {
+        key: u64,
+        conflict: u64,
+        cost: i64,
     },
     Update {
         key: u64,
@@ -293,18 +534,50 @@ pub(crate) enum Item<V> {
         key: u64,
         conflict: u64,
     },
+    Batch(Vec<Item<V>>),
     Wait(WaitGroup),
+    /// Shrink makes the processor evict the items over the max cost, then mark the wait group done.
+    Shrink(Option<WaitGroup>),
+    /// Maintain makes the processor settle the pending tasks and send its report to the sender.
+    Maintain(Sender<MaintenanceReport>),
 }
 
 impl<V> Item<V> {
     #[inline]
-    fn new(key: u64, conflict: u64, cost: i64, val: V, exp: Time) -> Self {
+    fn new(
+        key: u64,
+        conflict: u64,
+        cost: i64,
+        val: V,
+        exp: Time,
+        retained_key: Option<RetainedKey>,
+    ) -> Self {
         Self::New {
             key,
             conflict,
             cost,
             value: val,
             expiration: exp,
+            retained_key,
+            admission: None,
+        }
+    }
+
+    /// `with_admission` makes the processor send whether the policy admitted the new item to `tx`.
+    #[inline]
+    fn with_admission(mut self, tx: Sender<bool>) -> Self {
+        if let Self::New { admission, .. } = &mut self {
+            *admission = Some(tx);
+        }
+        self
+    }
+
+    #[inline]
+    fn stored(key: u64, conflict: u64, cost: i64) -> Self {
+        Self::Stored {
+            key,
+            conflict,
+            cost,
         }
     }
 
@@ -340,7 +613,7 @@ impl<V> Item<V> {
 ///   * In async, Cache starts two extra green threads. One is policy thread, the other is writing thread.
 /// * **Store policy** Stretto only store the value, which means the cache does not store the key.
 /// * **High Hit Ratios** - with our unique admission/eviction policy pairing, Ristretto's performance is best in class.
-///     * **Eviction: SampledLFU** - on par with exact LRU and better performance on Search and Database traces.
+///     * **Eviction: SampledLFU** - on par with exact LRU and better performance on Search and Database traces, or pluggable: LRU, SLRU, S3-FIFO, SIEVE and an adaptive W-TinyLFU are also provided.
 ///     * **Admission: TinyLFU** - extra performance with little memory overhead (12 bits per counter).
 /// * **Fast Throughput** - we use a variety of techniques for managing contention and the result is excellent throughput.
 /// * **Cost-Based Eviction** - any large new item deemed valuable can evict multiple smaller items (cost could be anything).
@@ -369,6 +642,33 @@ pub struct AsyncCache<
     /// policy determines what gets let in to the cache and what gets kicked out.
     pub(crate) policy: Arc<AsyncLFUPolicy<S>>,
 
+    /// get_buf is a set of buffers allowing us to batch the key hashes of Gets
+    /// before they are handed to the policy.
+    pub(crate) get_buf: Arc<RingBuffer<AsyncLFUPolicy<S>>>,
+
+    /// loaders keeps track of the `get_or_insert_with` loads in flight.
+    pub(crate) loaders: Arc<Group<V, Receiver<()>>>,
+
+    /// retain_key keeps the original key in the store, it is only set when the keys are retained.
+    pub(crate) retain_key: Option<fn(K) -> RetainedKey>,
+
+    /// time_to_idle is the time-to-idle of the values inserted without TTL, zero means none.
+    pub(crate) time_to_idle: Duration,
+
+    /// ignore_internal_cost is set when the cost of the items does not include the size of
+    /// their store item.
+    pub(crate) ignore_internal_cost: bool,
+
+    /// journal is the journal the processor appends to, if any. It is detached on close.
+    pub(crate) journal: Option<Arc<Mutex<Journal<V>>>>,
+
+    /// dropped_deletes keeps the deletions dropped by the full insert buffer, for the
+    /// processor.
+    pub(crate) dropped_deletes: Arc<Mutex<Vec<(u64, u64)>>>,
+
+    /// expiry computes the TTL of the values, instead of the call sites.
+    pub(crate) expiry: Option<Arc<dyn Expiry<Key = K, Value = V>>>,
+
     /// insert_buf is a buffer allowing us to batch/drop Sets during times of high
     /// contention.
     pub(crate) insert_buf_tx: Sender<Item<V>>,
@@ -478,6 +778,12 @@ where
             CacheError::SendError(format!("fail to send clear signal to working thread {}", e))
         })?;
 
+        // the journal stays locked until the store is cleared, so that the processor does not
+        // journal an item cleared from the store.
+        let mut journal = self.journal.as_ref().map(|journal| journal.lock());
+        if let Some(journal) = journal.as_mut() {
+            journal.clear();
+        }
         self.policy.clear();
         self.store.clear();
         self.metrics.clear();
@@ -505,10 +811,10 @@ where
     }
 
     /// `insert_with_ttl` works like Set but adds a key-value pair to the cache that will expire
-    /// after the specified TTL (time to live) has passed. A zero value means the value never
-    /// expires, which is identical to calling `insert`.
+    /// after the specified TTL (time to live) has passed. A zero value is identical to calling
+    /// `insert`, the value then never expires unless the cache has a time-to-idle.
     pub async fn insert_with_ttl(&self, key: K, val: V, cost: i64, ttl: Duration) -> bool {
-        self.try_insert_in(key, val, cost, ttl, false)
+        self.try_insert_in(key, val, cost, self.new_expiration(ttl), false)
             .await
             .unwrap()
     }
@@ -521,13 +827,71 @@ where
         cost: i64,
         ttl: Duration,
     ) -> Result<bool, CacheError> {
-        self.try_insert_in(key, val, cost, ttl, false).await
+        self.try_insert_in(key, val, cost, self.new_expiration(ttl), false)
+            .await
+    }
+
+    /// `insert_at` works like `insert_with_ttl`, but the value expires at `deadline`, which is
+    /// either a [`SystemTime`] (e.g. the `exp` claim of a token) or an [`Instant`]. A deadline
+    /// which is already past is rejected: the value is passed to `on_reject` and `false` is
+    /// returned, the current value of the key, if any, is kept.
+    ///
+    /// [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+    /// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
+    pub async fn insert_at(
+        &self,
+        key: K,
+        val: V,
+        cost: i64,
+        deadline: impl Into<Deadline>,
+    ) -> bool {
+        self.try_insert_at(key, val, cost, deadline).await.unwrap()
+    }
+
+    /// `try_insert_at` is the non-panicking version of [`insert_at`](#method.insert_at)
+    pub async fn try_insert_at(
+        &self,
+        key: K,
+        val: V,
+        cost: i64,
+        deadline: impl Into<Deadline>,
+    ) -> Result<bool, CacheError> {
+        let now = self.store.now();
+        let deadline = deadline.into().instant(now);
+        if deadline <= now {
+            if !self.is_closed.load(Ordering::SeqCst) {
+                self.reject_expired(&key, val, cost, Time::at(now, deadline));
+            }
+            return Ok(false);
+        }
+
+        self.try_insert_in(key, val, cost, Time::at(now, deadline), false)
+            .await
+    }
+
+    /// `insert_with_tti` works like `insert`, but the value expires once it has not been read
+    /// for the specified TTI (time to idle), each `get`, `get_mut` or `get_many` of the key
+    /// resets it. A zero value means the value never expires, even if the cache has a time-to-idle.
+    pub async fn insert_with_tti(&self, key: K, val: V, cost: i64, tti: Duration) -> bool {
+        self.try_insert_with_tti(key, val, cost, tti).await.unwrap()
+    }
+
+    /// `try_insert_with_tti` is the non-panicking version of [`insert_with_tti`](#method.insert_with_tti)
+    pub async fn try_insert_with_tti(
+        &self,
+        key: K,
+        val: V,
+        cost: i64,
+        tti: Duration,
+    ) -> Result<bool, CacheError> {
+        self.try_insert_in(key, val, cost, Time::idle(self.store.now(), tti), false)
+            .await
     }
 
     /// `insert_if_present` is like `insert`, but only updates the value of an existing key. It
     /// does NOT add the key to cache if it's absent.
     pub async fn insert_if_present(&self, key: K, val: V, cost: i64) -> bool {
-        self.try_insert_in(key, val, cost, Duration::ZERO, true)
+        self.try_insert_in(key, val, cost, self.new_expiration(Duration::ZERO), true)
             .await
             .unwrap()
     }
@@ -539,8 +903,191 @@ where
         val: V,
         cost: i64,
     ) -> Result<bool, CacheError> {
-        self.try_insert_in(key, val, cost, Duration::ZERO, true)
+        self.try_insert_in(key, val, cost, self.new_expiration(Duration::ZERO), true)
+            .await
+    }
+
+    /// `insert_with_outcome` works like [`insert_with_ttl`](#method.insert_with_ttl), but tells
+    /// what happened to the item instead of a bare bool, see [`InsertOutcome`].
+    ///
+    /// A new item which was queued may still be rejected by the policy later on, use
+    /// [`insert_and_wait`](#method.insert_and_wait) to know whether it is really cached.
+    ///
+    /// [`InsertOutcome`]: enum.InsertOutcome.html
+    pub async fn insert_with_outcome(
+        &self,
+        key: K,
+        val: V,
+        cost: i64,
+        ttl: Duration,
+    ) -> InsertOutcome {
+        self.try_insert_with_outcome(key, val, cost, ttl)
+            .await
+            .unwrap()
+    }
+
+    /// `try_insert_with_outcome` is the non-panicking version of [`insert_with_outcome`](#method.insert_with_outcome)
+    pub async fn try_insert_with_outcome(
+        &self,
+        key: K,
+        val: V,
+        cost: i64,
+        ttl: Duration,
+    ) -> Result<InsertOutcome, CacheError> {
+        self.try_insert_outcome(key, val, cost, ttl, None).await
+    }
+
+    /// `insert_and_wait` works like [`insert_with_outcome`](#method.insert_with_outcome), but a
+    /// queued item waits until the policy has handled it, and resolves to
+    /// [`InsertOutcome::Admitted`] or [`InsertOutcome::Rejected`].
+    ///
+    /// An item which is still in the insert buffer when the cache is cleared is `Rejected`.
+    ///
+    /// [`InsertOutcome::Admitted`]: enum.InsertOutcome.html#variant.Admitted
+    /// [`InsertOutcome::Rejected`]: enum.InsertOutcome.html#variant.Rejected
+    pub async fn insert_and_wait(&self, key: K, val: V, cost: i64, ttl: Duration) -> InsertOutcome {
+        self.try_insert_and_wait(key, val, cost, ttl).await.unwrap()
+    }
+
+    /// `try_insert_and_wait` is the non-panicking version of [`insert_and_wait`](#method.insert_and_wait)
+    pub async fn try_insert_and_wait(
+        &self,
+        key: K,
+        val: V,
+        cost: i64,
+        ttl: Duration,
+    ) -> Result<InsertOutcome, CacheError> {
+        let (tx, rx) = bounded(1);
+        match self
+            .try_insert_outcome(key, val, cost, ttl, Some(tx))
+            .await?
+        {
+            // the sender is dropped without a result if the item is cleared before the policy sees it.
+            InsertOutcome::Queued => match rx.recv().await {
+                Ok(true) => Ok(InsertOutcome::Admitted),
+                Ok(false) | Err(_) => Ok(InsertOutcome::Rejected),
+            },
+            outcome => Ok(outcome),
+        }
+    }
+
+    /// `insert_many` is the batch version of [`insert`](#method.insert), each item is a
+    /// `(key, value, cost)` tuple.
+    ///
+    /// The keys are grouped by store shard, so that each shard lock is taken only once,
+    /// and all the items are pushed to the insert buffer in a single send. So the new
+    /// items of the batch are either all dropped or none of them: it returns false if
+    /// they were dropped. Updates of existing keys are applied to the store in any case.
+    pub async fn insert_many<I>(&self, items: I) -> bool
+    where
+        I: IntoIterator<Item = (K, V, i64)>,
+    {
+        self.try_insert_many(items).await.unwrap()
+    }
+
+    /// `try_insert_many` is the non-panicking version of [`insert_many`](#method.insert_many)
+    pub async fn try_insert_many<I>(&self, items: I) -> Result<bool, CacheError>
+    where
+        I: IntoIterator<Item = (K, V, i64)>,
+    {
+        if self.is_closed.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+
+        let (batch, new_keys) = self.try_update_many(items)?;
+        if batch.is_empty() {
+            return Ok(true);
+        }
+
+        select! {
+            res = self.insert_buf_tx.send(Item::Batch(batch)).fuse() => {
+                res.map_or_else(|_| Ok(self.drop_batch(&new_keys)), |_| Ok(true))
+            },
+            default => Ok(self.drop_batch(&new_keys)),
+        }
+    }
+
+    /// `insert_keep_ttl` is like `insert`, but an existing key keeps its current expiration
+    /// instead of getting a new one. An absent key is inserted as by `insert`.
+    pub async fn insert_keep_ttl(&self, key: K, val: V, cost: i64) -> bool {
+        self.try_insert_keep_ttl(key, val, cost).await.unwrap()
+    }
+
+    /// `try_insert_keep_ttl` is the non-panicking version of [`insert_keep_ttl`](#method.insert_keep_ttl)
+    pub async fn try_insert_keep_ttl(&self, key: K, val: V, cost: i64) -> Result<bool, CacheError> {
+        let expiration = self.kept_expiration(&key);
+        self.try_insert_in(key, val, cost, expiration, false).await
+    }
+
+    /// `get_or_insert_with` returns a clone of the value for the key, or computes it
+    /// with `f` and inserts it with the given cost if the key is absent.
+    ///
+    /// Concurrent misses on the same key run `f` only once: the first caller runs the
+    /// loader and the others wait until its value is ready. The loaded value goes
+    /// through the same path as [`insert`](#method.insert), and it is returned even if
+    /// the insert is dropped or rejected by the policy. The loader's caller returns once
+    /// the policy decided about the value; until then, callers which miss the key get
+    /// the loaded value as well.
+    pub async fn get_or_insert_with<F, Fut>(&self, key: K, cost: i64, f: F) -> V
+    where
+        V: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        self.try_get_or_insert_with(key, cost, || f().map(Ok::<_, CacheError>))
             .await
+            .unwrap()
+    }
+
+    /// `try_get_or_insert_with` is the fallible version of [`get_or_insert_with`](#method.get_or_insert_with).
+    ///
+    /// If `f` returns an error, or the loading future is dropped, nothing is inserted and
+    /// callers waiting on that load will retry, with their own loader.
+    pub async fn try_get_or_insert_with<F, Fut, E>(&self, key: K, cost: i64, f: F) -> Result<V, E>
+    where
+        V: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+        E: From<CacheError>,
+    {
+        let (index, conflict) = self.key_to_hash.build_key(&key);
+        loop {
+            if let Some(v) = self.get(&key) {
+                return Ok(v.value().clone());
+            }
+
+            match self.loaders.join(index, conflict, stop_channel) {
+                Flight::Leader(mut leader) => {
+                    let val = f().await?;
+                    leader.complete(val.clone());
+                    // the flight is kept until the policy decided about the value, so that
+                    // the callers which miss the store meanwhile do not load it again.
+                    self.try_insert_and_wait(key, val.clone(), cost, Duration::ZERO)
+                        .await?;
+                    return Ok(val);
+                }
+                Flight::Follower(call) => {
+                    // the channel is never sent on, it is closed once the leader is done.
+                    let _ = call.done().recv().await;
+                    if let Some(val) = call.value() {
+                        return Ok(val);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `stream` returns a [`Stream`] over clones of the keys and values in the cache.
+    ///
+    /// See [`iter`](#method.iter) for when the keys are available and the snapshot semantics.
+    ///
+    /// [`Stream`]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
+    pub fn stream(&self) -> impl Stream<Item = (K, V)> + '_
+    where
+        K: Clone + 'static,
+        V: Clone,
+    {
+        futures::stream::iter(self.iter())
     }
 
     /// wait until the previous operations finished.
@@ -563,31 +1110,235 @@ where
         }
     }
 
+    /// `update_max_cost_and_wait` is like [`update_max_cost`](#method.update_max_cost), but it
+    /// waits until the items over the new max cost are evicted.
+    pub async fn update_max_cost_and_wait(&self, max_cost: i64) -> Result<(), CacheError> {
+        self.policy.update_max_cost(max_cost);
+        if self.is_closed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let wg = WaitGroup::new();
+        self.insert_buf_tx
+            .send(Item::Shrink(Some(wg.add(1))))
+            .await
+            .map_err(|e| CacheError::SendError(format!("cache set buf sender: {}", e)))?;
+        wg.wait().await;
+        Ok(())
+    }
+
+    /// `run_pending_tasks` settles the cache: the buffered reads are handed to the admission
+    /// policy, every operation pushed to the insert buffer before the call is applied and the
+    /// expired items are removed, then it returns what the cache did since the previous call.
+    ///
+    /// Unlike [`wait`](#method.wait), it waits until the insert buffer has room.
+    pub async fn run_pending_tasks(&self) -> Result<MaintenanceReport, CacheError> {
+        if self.is_closed.load(Ordering::SeqCst) {
+            return Ok(MaintenanceReport::default());
+        }
+
+        self.get_buf.flush();
+        let (tx, rx) = bounded(1);
+        self.insert_buf_tx
+            .send(Item::Maintain(tx))
+            .await
+            .map_err(|e| CacheError::SendError(format!("cache set buf sender: {}", e)))?;
+        rx.recv()
+            .await
+            .map_err(|e| CacheError::RecvError(format!("fail to receive the report: {}", e)))
+    }
+
+    /// `warm` loads the entries of the stream, `(key, value, cost)`, directly in the store
+    /// and the policy, until the Cache is full. See [`Cache::warm`] for the details.
+    ///
+    /// [`Cache::warm`]: struct.Cache.html#method.warm
+    pub async fn warm<St>(&self, entries: St) -> Result<WarmReport, CacheError>
+    where
+        St: Stream<Item = (K, V, i64)>,
+    {
+        self.warm_with_frequency(entries.map(|(key, val, cost)| (key, val, cost, 0)))
+            .await
+    }
+
+    /// `warm_with_frequency` is like [`warm`](#method.warm), and also counts `frequency`
+    /// reads of each loaded key, `(key, value, cost, frequency)`, in the admission policy.
+    pub async fn warm_with_frequency<St>(&self, entries: St) -> Result<WarmReport, CacheError>
+    where
+        St: Stream<Item = (K, V, i64, u8)>,
+    {
+        let mut report = WarmReport::default();
+        if self.is_closed.load(Ordering::SeqCst) {
+            return Ok(report);
+        }
+
+        futures::pin_mut!(entries);
+        while let Some((key, val, cost, frequency)) = entries.next().await {
+            if self.warm_in(key, val, cost, frequency)? {
+                report.loaded += 1;
+            } else {
+                report.skipped += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// `restore` admits the items of a snapshot or a journal directly, then sets the
+    /// admission back to `admission`.
+    #[cfg(feature = "serilization")]
+    async fn restore(
+        &self,
+        entries: Vec<SnapshotEntry<K, V>>,
+        admission: Admission,
+    ) -> Result<(), CacheError> {
+        self.policy.set_admission(Admission::Always);
+        for entry in entries {
+            if let Some(item) = self.restore_item(entry)? {
+                self.insert_buf_tx
+                    .send(item)
+                    .await
+                    .map_err(|e| CacheError::SendError(format!("cache set buf sender: {}", e)))?;
+            }
+        }
+        self.wait().await?;
+        self.policy.set_admission(admission);
+        Ok(())
+    }
+
     /// remove entry from Cache by key.
-    pub async fn remove(&self, k: &K) {
+    pub async fn remove<Q>(&self, k: &Q)
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: core::hash::Hash + Eq + ?Sized,
+    {
         self.try_remove(k).await.unwrap()
     }
 
     /// try to remove an entry from the Cache by key
-    pub async fn try_remove(&self, k: &K) -> Result<(), CacheError> {
+    pub async fn try_remove<Q>(&self, k: &Q) -> Result<(), CacheError>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: core::hash::Hash + Eq + ?Sized,
+    {
+        if let Some(prev) = self.try_remove_item(k).await? {
+            self.callback.on_exit(Some(prev.value.into_inner()));
+        }
+        Ok(())
+    }
+
+    /// `take` removes an entry from Cache by key, and returns its value.
+    ///
+    /// The value is handed over to the caller, so `on_exit` is called with `None` instead.
+    pub async fn take<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: core::hash::Hash + Eq + ?Sized,
+    {
+        self.try_take(k).await.unwrap()
+    }
+
+    /// `try_take` is the non-panicking version of [`take`](#method.take)
+    pub async fn try_take<Q>(&self, k: &Q) -> Result<Option<V>, CacheError>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: core::hash::Hash + Eq + ?Sized,
+    {
+        Ok(self
+            .try_remove_item(k)
+            .await?
+            .and_then(|prev| self.take_item(prev))
+            .map(|prev| prev.value.into_inner()))
+    }
+
+    /// `remove_entry` works like [`take`](#method.take), but also returns the original key,
+    /// if the keys are retained (see [`AsyncCacheBuilder::set_retain_keys`]).
+    ///
+    /// [`AsyncCacheBuilder::set_retain_keys`]: struct.AsyncCacheBuilder.html#method.set_retain_keys
+    pub async fn remove_entry<Q>(&self, k: &Q) -> Option<(Option<K>, V)>
+    where
+        K: core::borrow::Borrow<Q> + 'static,
+        Q: core::hash::Hash + Eq + ?Sized,
+    {
+        self.try_remove_entry(k).await.unwrap()
+    }
+
+    /// `try_remove_entry` is the non-panicking version of [`remove_entry`](#method.remove_entry)
+    pub async fn try_remove_entry<Q>(&self, k: &Q) -> Result<Option<(Option<K>, V)>, CacheError>
+    where
+        K: core::borrow::Borrow<Q> + 'static,
+        Q: core::hash::Hash + Eq + ?Sized,
+    {
+        Ok(self
+            .try_remove_item(k)
+            .await?
+            .and_then(|prev| self.take_item(prev))
+            .map(StoreItem::into_entry))
+    }
+
+    /// `try_remove_item` removes the item of the key from the store, and tells the policy.
+    async fn try_remove_item<Q>(&self, k: &Q) -> Result<Option<StoreItem<V>>, CacheError>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: core::hash::Hash + Eq + ?Sized,
+    {
         if self.is_closed.load(Ordering::SeqCst) {
-            return Ok(());
+            return Ok(None);
         }
 
         let (index, conflict) = self.key_to_hash.build_key(k);
         // delete immediately
         let prev = self.store.try_remove(&index, conflict)?;
 
-        if let Some(prev) = prev {
-            self.callback.on_exit(Some(prev.value.into_inner()));
-        }
         // If we've set an item, it would be applied slightly later.
         // So we must push the same item to `setBuf` with the deletion flag.
         // This ensures that if a set is followed by a delete, it will be
         // applied in the correct order.
-        let _ = self.insert_buf_tx.send(Item::delete(index, conflict)).await;
+        if let Err(e) = self.try_send_deletes(vec![(index, conflict)]) {
+            if let Some(prev) = prev {
+                self.callback.on_exit(Some(prev.value.into_inner()));
+            }
+            return Err(e);
+        }
 
-        Ok(())
+        Ok(prev)
+    }
+
+    /// `remove_many` is the batch version of [`remove`](#method.remove).
+    ///
+    /// The keys are grouped by store shard, so that each shard lock is taken only once,
+    /// and the deletions are pushed to the insert buffer in a single send.
+    pub async fn remove_many<'q, Q, I>(&self, keys: I)
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: core::hash::Hash + Eq + ?Sized + 'q,
+        I: IntoIterator<Item = &'q Q>,
+    {
+        self.try_remove_many(keys).await.unwrap()
+    }
+
+    /// `try_remove_many` is the non-panicking version of [`remove_many`](#method.remove_many)
+    pub async fn try_remove_many<'q, Q, I>(&self, keys: I) -> Result<(), CacheError>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: core::hash::Hash + Eq + ?Sized + 'q,
+        I: IntoIterator<Item = &'q Q>,
+    {
+        if self.is_closed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let keys = keys
+            .into_iter()
+            .map(|k| self.key_to_hash.build_key(k))
+            .collect::<Vec<_>>();
+        // delete immediately
+        self.store
+            .try_remove_many(&keys)?
+            .into_iter()
+            .flatten()
+            .for_each(|prev| self.callback.on_exit(Some(prev.value.into_inner())));
+
+        // As for `remove`, the deletions must follow the pending insertions of the keys.
+        self.try_send_deletes(keys)
     }
 
     /// `close` stops all threads and closes all channels.
@@ -597,6 +1348,10 @@ where
             return Ok(());
         }
 
+        // the journal is detached first, so that it keeps the items.
+        if let Some(journal) = self.journal.as_ref() {
+            journal.lock().close();
+        }
         self.clear().await?;
         // Block until processItems thread is returned
         self.stop_tx.send(()).await.map_err(|e| {
@@ -613,43 +1368,68 @@ where
         key: K,
         val: V,
         cost: i64,
-        ttl: Duration,
+        expiration: Time,
         only_update: bool,
     ) -> Result<bool, CacheError> {
         if self.is_closed.load(Ordering::SeqCst) {
             return Ok(false);
         }
 
-        if let Some((index, item)) = self.try_update(key, val, cost, ttl, only_update)? {
-            let is_update = item.is_update();
-            select! {
-                res = self.insert_buf_tx.send(item).fuse() => res.map_or_else(|_| {
-                   if is_update {
-                        // Return true if this was an update operation since we've already
-                        // updated the store. For all the other operations (set/delete), we
-                        // return false which means the item was not inserted.
-                        Ok(true)
-                    } else {
-                        self.metrics.add(MetricType::DropSets, index, 1);
-                        Ok(false)
-                    }
-                }, |_| Ok(true)),
-                default => {
-                    if is_update {
-                        // Return true if this was an update operation since we've already
-                        // updated the store. For all the other operations (set/delete), we
-                        // return false which means the item was not inserted.
-                        Ok(true)
-                    } else {
-                        self.metrics.add(MetricType::DropSets, index, 1);
-                        Ok(false)
-                    }
-                }
-            }
+        if let Some((index, item)) = self.try_update(key, val, cost, expiration, only_update)? {
+            Ok(self.try_send_insert(index, item, None).await.is_inserted())
         } else {
             Ok(false)
         }
     }
+
+    #[inline]
+    async fn try_insert_outcome(
+        &self,
+        key: K,
+        val: V,
+        cost: i64,
+        ttl: Duration,
+        admission: Option<Sender<bool>>,
+    ) -> Result<InsertOutcome, CacheError> {
+        if self.is_closed.load(Ordering::SeqCst) {
+            return Ok(InsertOutcome::Closed);
+        }
+
+        match self.try_update(key, val, cost, self.new_expiration(ttl), false)? {
+            Some((index, item)) => match self.reject_too_large(item) {
+                Some(item) => Ok(self.try_send_insert(index, item, admission).await),
+                None => Ok(InsertOutcome::TooLarge),
+            },
+            // `try_update` only returns `None` for the inserts which only update.
+            None => Err(CacheError::InsertError(
+                "no item to send for an insert which is not only an update".to_string(),
+            )),
+        }
+    }
+
+    /// `try_send_insert` sends the item returned by `try_update` to the insert buffer.
+    #[inline]
+    async fn try_send_insert(
+        &self,
+        index: u64,
+        item: Item<V>,
+        admission: Option<Sender<bool>>,
+    ) -> InsertOutcome {
+        let item = match admission {
+            Some(tx) => item.with_admission(tx),
+            None => item,
+        };
+
+        let is_update = item.is_update();
+        select! {
+            res = self.insert_buf_tx.send(item).fuse() => match res {
+                Ok(_) if is_update => InsertOutcome::Updated,
+                Ok(_) => InsertOutcome::Queued,
+                Err(_) => self.dropped(index, is_update, InsertOutcome::Closed),
+            },
+            default => self.dropped(index, is_update, InsertOutcome::Dropped(DropReason::BufferFull)),
+        }
+    }
 }
 
 impl<V, U, CB, S> CacheProcessor<V, U, CB, S>
@@ -670,6 +1450,8 @@ where
         clear_rx: Receiver<()>,
         metrics: Arc<Metrics>,
         callback: Arc<CB>,
+        journal: Option<Arc<Mutex<Journal<V>>>>,
+        dropped_deletes: Arc<Mutex<Vec<(u64, u64)>>>,
     ) -> Self {
         let item_size = store.item_size();
         let hasher = store.hasher();
@@ -686,6 +1468,9 @@ where
             ignore_internal_cost,
             item_size,
             cleanup_duration,
+            report: MaintenanceReport::default(),
+            journal,
+            dropped_deletes,
         }
     }
 
@@ -693,9 +1478,11 @@ where
     pub(crate) fn spawn(mut self, spawner: Box<dyn Fn(BoxFuture<'static, ()>) + Send + Sync>) {
         (spawner)(Box::pin(async move {
             let mut cleanup_timer = Timer::interval(self.cleanup_duration);
+            let mut clock_timer = Timer::interval(COARSE_RESOLUTION);
 
             loop {
                 select! {
+                    _ = clock_timer.next().fuse() => self.store.refresh_clock(),
                     item = self.insert_buf_rx.recv().fuse() => {
                         if let Err(_e) = self.handle_insert_event(item) {
                             return;
@@ -733,18 +1520,29 @@ where
         res: Result<Item<V>, RecvError>,
     ) -> Result<(), CacheError> {
         res.map_err(|_| CacheError::RecvError("fail to receive msg from insert buffer".to_string()))
-            .and_then(|item| self.handle_item(item))
+            .and_then(|item| {
+                self.store.refresh_clock();
+                self.handle_item(item)
+            })
     }
 
     #[inline]
     pub(crate) fn handle_cleanup_event(&mut self) -> Result<(), CacheError> {
-        self.store
-            .try_cleanup_async(self.policy.clone())?
-            .into_iter()
-            .for_each(|victim| {
-                self.prepare_evict(&victim);
-                self.callback.on_evict(victim);
-            });
+        self.cleanup_expired()
+    }
+
+    /// `cleanup_expired` removes the items which expired since the last cleanup.
+    #[inline]
+    pub(crate) fn cleanup_expired(&mut self) -> Result<(), CacheError> {
+        self.apply_dropped_deletes()?;
+        let items = self.store.try_cleanup_async(self.policy.clone())?;
+        self.report.expired += items.len() as u64;
+        items.into_iter().for_each(|victim| {
+            self.journal_remove(victim.index, victim.conflict);
+            self.prepare_evict(&victim);
+            self.callback.on_evict(victim);
+        });
+        self.maintain_journal();
         Ok(())
     }
 }
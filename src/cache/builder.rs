@@ -1,11 +1,17 @@
-use crate::cache::{DEFAULT_CLEANUP_DURATION, DEFAULT_INSERT_BUF_SIZE};
+use crate::cache::{
+    DEFAULT_BUFFER_ITEMS, DEFAULT_CLEANUP_DURATION, DEFAULT_EXPIRATION_RESOLUTION,
+    DEFAULT_INSERT_BUF_SIZE, MIN_EXPIRATION_RESOLUTION,
+};
+use crate::store::{retain_key, ReadExpiry, RetainedKey};
 use crate::{
-    CacheCallback, Coster, DefaultCacheCallback, DefaultCoster, DefaultKeyBuilder,
-    DefaultUpdateValidator, KeyBuilder, UpdateValidator,
+    Admission, CacheCallback, Clock, Coster, DefaultCacheCallback, DefaultCoster,
+    DefaultKeyBuilder, DefaultUpdateValidator, EvictionPolicy, Expiry, KeyBuilder, PolicyConfig,
+    UpdateValidator,
 };
 use std::collections::hash_map::RandomState;
 use std::hash::{BuildHasher, Hash};
 use std::marker::PhantomData;
+use std::sync::Arc;
 use std::time::Duration;
 
 pub struct CacheBuilderCore<
@@ -33,11 +39,12 @@ pub struct CacheBuilderCore<
 
     pub(crate) max_cost: i64,
 
-    // buffer_items determines the size of Get buffers.
-    //
-    // Unless you have a rare use case, using `64` as the BufferItems value
-    // results in good performance.
-    // buffer_items: usize,
+    /// `buffer_items` determines the size of Get buffers.
+    ///
+    /// Unless you have a rare use case, using `64` as the BufferItems value
+    /// results in good performance.
+    pub(crate) buffer_items: usize,
+
     /// `insert_buffer_size` determines the size of insert buffers.
     ///
     /// Default is 32 * 1024 (**TODO:** need to figure out the optimal size.).
@@ -48,6 +55,18 @@ pub struct CacheBuilderCore<
     /// Default is 500ms.
     pub(crate) cleanup_duration: Duration,
 
+    /// `expiration_resolution` is the granularity of the expirations, the expired
+    /// entries are reclaimed by the cleanup at most this long after their deadline.
+    ///
+    /// Default is 1s.
+    pub(crate) expiration_resolution: Duration,
+
+    /// `time_to_idle` is the duration after which the values inserted without TTL
+    /// expire if they are not read.
+    ///
+    /// Default is zero, which means the values never expire.
+    pub(crate) time_to_idle: Duration,
+
     /// key_to_hash is used to customize the key hashing algorithm.
     /// Each key will be hashed using the provided function. If keyToHash value
     /// is not set, the default keyToHash function is used.
@@ -58,6 +77,27 @@ pub struct CacheBuilderCore<
     /// param of 0.
     pub(crate) coster: Option<C>,
 
+    /// expiry computes the TTL of a value when it is created, updated or read,
+    /// instead of taking the one given at the call site.
+    pub(crate) expiry: Option<Arc<dyn Expiry<Key = K, Value = V>>>,
+
+    /// read_expiry is the read half of expiry, handed to the store which does not know the key.
+    pub(crate) read_expiry: Option<ReadExpiry<V>>,
+
+    /// clock is the source of time of the expirations.
+    pub(crate) clock: Option<Arc<dyn Clock>>,
+
+    /// eviction chooses the victims when the cache is full, the default is SampledLfu.
+    pub(crate) eviction: Option<Box<dyn EvictionPolicy>>,
+
+    /// admission decides whether a new key is worth evicting the victims when the cache is full.
+    ///
+    /// Default is strict TinyLFU.
+    pub(crate) admission: Admission,
+
+    /// policy_config tunes the sampling of the eviction and the sketch of the admission.
+    pub(crate) policy_config: PolicyConfig,
+
     /// update_validator is called when a value already exists in cache and is being updated.
     pub(crate) update_validator: Option<U>,
 
@@ -65,6 +105,10 @@ pub struct CacheBuilderCore<
 
     pub(crate) hasher: Option<S>,
 
+    /// retain_key keeps the original key alongside the value, it is only set when
+    /// the keys are retained, so that the cache can be iterated.
+    pub(crate) retain_key: Option<fn(K) -> RetainedKey>,
+
     marker_k: PhantomData<fn(K)>,
     marker_v: PhantomData<fn(V)>,
 }
@@ -76,15 +120,24 @@ impl<K: Hash + Eq, V: Send + Sync + 'static> CacheBuilderCore<K, V> {
         Self {
             num_counters,
             max_cost,
-            // buffer_items: DEFAULT_BUFFER_ITEMS,
+            buffer_items: DEFAULT_BUFFER_ITEMS,
             insert_buffer_size: DEFAULT_INSERT_BUF_SIZE,
             metrics: false,
             callback: Some(DefaultCacheCallback::default()),
             key_to_hash: DefaultKeyBuilder::<K>::default(),
             update_validator: Some(DefaultUpdateValidator::default()),
             coster: Some(DefaultCoster::default()),
+            expiry: None,
+            read_expiry: None,
+            clock: None,
+            eviction: None,
+            admission: Admission::TinyLfu,
+            policy_config: PolicyConfig::default(),
             ignore_internal_cost: false,
             cleanup_duration: DEFAULT_CLEANUP_DURATION,
+            expiration_resolution: DEFAULT_EXPIRATION_RESOLUTION,
+            time_to_idle: Duration::ZERO,
+            retain_key: None,
             marker_k: Default::default(),
             marker_v: Default::default(),
             hasher: Some(RandomState::default()),
@@ -99,15 +152,24 @@ impl<K: Hash + Eq, V: Send + Sync + 'static, KH: KeyBuilder<Key = K>> CacheBuild
         Self {
             num_counters,
             max_cost,
-            // buffer_items: DEFAULT_BUFFER_ITEMS,
+            buffer_items: DEFAULT_BUFFER_ITEMS,
             insert_buffer_size: DEFAULT_INSERT_BUF_SIZE,
             metrics: false,
             callback: Some(DefaultCacheCallback::default()),
             key_to_hash: index,
             update_validator: Some(DefaultUpdateValidator::default()),
             coster: Some(DefaultCoster::default()),
+            expiry: None,
+            read_expiry: None,
+            clock: None,
+            eviction: None,
+            admission: Admission::TinyLfu,
+            policy_config: PolicyConfig::default(),
             ignore_internal_cost: false,
             cleanup_duration: DEFAULT_CLEANUP_DURATION,
+            expiration_resolution: DEFAULT_EXPIRATION_RESOLUTION,
+            time_to_idle: Duration::ZERO,
+            retain_key: None,
             marker_k: Default::default(),
             marker_v: Default::default(),
             hasher: Some(RandomState::default()),
@@ -141,13 +203,23 @@ where
             num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
             hasher: self.hasher,
@@ -170,13 +242,23 @@ where
             num_counters: self.num_counters,
             max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -196,13 +278,59 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: sz,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set the size of Get buffers for the Cache.
+    ///
+    /// `buffer_items` determines the size of Get buffers. Reads are batched into
+    /// lossy, striped buffers of this size before they are handed to the admission policy.
+    ///
+    /// Unless you have a rare use case, using `64` as the `buffer_items` value
+    /// results in good performance.
+    #[inline]
+    pub fn set_buffer_items(self, sz: usize) -> Self {
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            buffer_items: sz,
+            insert_buffer_size: self.insert_buffer_size,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -219,13 +347,23 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: val,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -243,13 +381,59 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: val,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set whether the Cache retains the original keys or not.
+    ///
+    /// By default, the Cache only stores the hashes of the keys. When the keys are
+    /// retained, they are kept alongside the values, so that the Cache can be iterated.
+    #[inline]
+    pub fn set_retain_keys(self, val: bool) -> Self
+    where
+        K: Send + Sync + 'static,
+    {
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: if val { Some(retain_key::<K>) } else { None },
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -263,13 +447,87 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: d,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set the resolution of the expirations for Cache. The expired entries are kept in
+    /// a timer wheel whose slots span `d`, so an entry is reclaimed by the cleanup at most
+    /// `d` (and the cleanup duration) after its deadline. The resolution can not be finer
+    /// than one millisecond.
+    #[inline]
+    pub fn set_expiration_resolution(self, d: Duration) -> Self {
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: d.max(MIN_EXPIRATION_RESOLUTION),
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set the time-to-idle for the Cache, the values inserted without TTL expire once
+    /// they have not been read for `d`. A zero `d` means the values never expire.
+    #[inline]
+    pub fn set_time_to_idle(self, d: Duration) -> Self {
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: d,
+            retain_key: self.retain_key,
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -301,13 +559,23 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: index,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -334,13 +602,195 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: Some(coster),
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set the expiry for the Cache.
+    ///
+    /// [`Expiry`] computes the TTL of a value from the value itself when it is created,
+    /// updated or read, instead of taking the one given at the call site.
+    #[inline]
+    pub fn set_expiry<E: Expiry<Key = K, Value = V> + 'static>(self, expiry: E) -> Self {
+        let expiry = Arc::new(expiry);
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: Some(expiry.clone()),
+            read_expiry: Some(Box::new(move |val, now, ttl| {
+                expiry.expire_after_read(val, now, ttl)
+            })),
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set the clock of the Cache.
+    ///
+    /// The [`Clock`] measures all the expirations of the Cache, it defaults to the
+    /// [`DefaultClock`](crate::DefaultClock). A [`MockClock`](crate::MockClock) lets the
+    /// tests advance the time by hand.
+    #[inline]
+    pub fn set_clock<CL: Clock>(self, clock: CL) -> Self {
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: Some(Arc::new(clock)),
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set the eviction policy of the Cache.
+    ///
+    /// The [`EvictionPolicy`] chooses the keys to evict when the Cache is full, it defaults to
+    /// the [`SampledLfu`](crate::SampledLfu). The admission policy still rejects a new key
+    /// which is less popular than the victim, unless the eviction policy handles the admission.
+    #[inline]
+    pub fn set_eviction_policy<E: EvictionPolicy>(self, eviction: E) -> Self {
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: Some(Box::new(eviction)),
+            admission: self.admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set the admission of the Cache.
+    ///
+    /// The [`Admission`] decides whether a new key is worth evicting the victims when the Cache
+    /// is full, it defaults to strict TinyLFU. It is not used with an eviction policy which
+    /// handles the admission by itself.
+    #[inline]
+    pub fn set_admission(self, admission: Admission) -> Self {
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission,
+            policy_config: self.policy_config,
+            ignore_internal_cost: self.ignore_internal_cost,
+            cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
+            hasher: self.hasher,
+            marker_k: self.marker_k,
+            marker_v: self.marker_v,
+        }
+    }
+
+    /// Set the tuning of the policies of the Cache.
+    ///
+    /// The [`PolicyConfig`] sets the sampling of the default eviction policy, the false positive
+    /// ratio of the doorkeeper, the reset interval of TinyLFU and the depth of its count-min
+    /// sketch. It is validated when the Cache is built.
+    #[inline]
+    pub fn set_policy_config(self, policy_config: PolicyConfig) -> Self {
+        Self {
+            num_counters: self.num_counters,
+            max_cost: self.max_cost,
+            insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
+            metrics: self.metrics,
+            callback: self.callback,
+            key_to_hash: self.key_to_hash,
+            update_validator: self.update_validator,
+            coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -361,13 +811,23 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: Some(uv),
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -386,13 +846,23 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: Some(cb),
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             hasher: self.hasher,
             marker_k: self.marker_k,
             marker_v: self.marker_v,
@@ -410,13 +880,23 @@ where
             num_counters: self.num_counters,
             max_cost: self.max_cost,
             insert_buffer_size: self.insert_buffer_size,
+            buffer_items: self.buffer_items,
             metrics: self.metrics,
             callback: self.callback,
             key_to_hash: self.key_to_hash,
             update_validator: self.update_validator,
             coster: self.coster,
+            expiry: self.expiry,
+            read_expiry: self.read_expiry,
+            clock: self.clock,
+            eviction: self.eviction,
+            admission: self.admission,
+            policy_config: self.policy_config,
             ignore_internal_cost: self.ignore_internal_cost,
             cleanup_duration: self.cleanup_duration,
+            expiration_resolution: self.expiration_resolution,
+            time_to_idle: self.time_to_idle,
+            retain_key: self.retain_key,
             hasher: Some(hasher),
             marker_k: self.marker_k,
             marker_v: self.marker_v,
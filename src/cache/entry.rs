@@ -0,0 +1,289 @@
+use crate::store::{RetainedKey, StoreItem};
+use crate::ttl::Time;
+use crate::ValueRefMut;
+use parking_lot::RwLockWriteGuard;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+type Shard<V, S> = HashMap<u64, StoreItem<V>, S>;
+
+/// NewItem computes the expiration of the value of a vacant entry from the TTL given to
+/// `insert`, and retains its key. The key is only known to the cache.
+type NewItem<'a, V> = Box<dyn FnOnce(&V, Duration) -> (Time, Option<RetainedKey>) + 'a>;
+
+/// EntryBackend is implemented by the caches, it lets an entry talk to the
+/// insert buffer and the expiration map while the entry holds the shard lock.
+pub(crate) trait EntryBackend<V, S> {
+    /// `now` returns the current time of the clock of the cache.
+    fn now(&self) -> Instant;
+
+    /// `insert_new` puts the value of a vacant entry in its locked shard.
+    fn insert_new(
+        &self,
+        data: &mut Shard<V, S>,
+        index: u64,
+        conflict: u64,
+        val: V,
+        expiration: Time,
+        retained_key: Option<RetainedKey>,
+    );
+
+    /// `try_send_stored` sends an `Item::Stored` for a value about to be put in the store
+    /// by `insert_new`, returns false if it was dropped.
+    fn try_send_stored(&self, index: u64, conflict: u64, cost: i64, val: &V) -> bool;
+
+    /// `try_send_update` sends an `Item::Update` for the value which was just modified in place.
+    fn try_send_update(&self, index: u64, cost: i64, val: &V);
+
+    /// `try_send_delete` removes the expiration of an entry which was just removed from
+    /// the store, and sends an `Item::Delete` for it.
+    fn try_send_delete(&self, index: u64, conflict: u64, expiration: Time);
+}
+
+/// A view into a single entry of the [`Cache`] or [`AsyncCache`], which may either be vacant or occupied.
+///
+/// The entry holds the write lock of the store shard of its key, so nothing else can
+/// read or write the keys of that shard until the entry is dropped. Do not call into
+/// the cache while holding an entry, or it may deadlock.
+///
+/// [`Cache`]: struct.Cache.html
+/// [`AsyncCache`]: struct.AsyncCache.html
+pub enum Entry<'a, V, S = RandomState> {
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, V, S>),
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, V, S>),
+}
+
+impl<'a, V, S: BuildHasher> Entry<'a, V, S> {
+    pub(crate) fn new(
+        data: RwLockWriteGuard<'a, Shard<V, S>>,
+        index: u64,
+        conflict: u64,
+        new_item: NewItem<'a, V>,
+        backend: &'a dyn EntryBackend<V, S>,
+    ) -> Self {
+        let now = backend.now();
+        let occupied = data.get(&index).is_some_and(|item| {
+            (conflict == 0 || conflict == item.conflict) && !item.is_expired(now)
+        });
+
+        if occupied {
+            data[&index].touch(now);
+            Entry::Occupied(OccupiedEntry {
+                data,
+                index,
+                backend,
+            })
+        } else {
+            Entry::Vacant(VacantEntry {
+                data,
+                index,
+                conflict,
+                new_item,
+                backend,
+            })
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting `val` with the given cost and TTL if empty,
+    /// and returns a mutable reference to the value in the entry.
+    ///
+    /// Gives `val` back if the key was vacant and the insert was dropped.
+    /// See [`VacantEntry::insert`](struct.VacantEntry.html#method.insert).
+    pub fn or_insert(self, val: V, cost: i64, ttl: Duration) -> Result<ValueRefMut<'a, V, S>, V> {
+        self.or_insert_with(cost, ttl, || val)
+    }
+
+    /// Ensures a value is in the entry by inserting the result of `f` with the given cost
+    /// and TTL if empty, and returns a mutable reference to the value in the entry. `f` is
+    /// only called for a vacant entry.
+    ///
+    /// Gives the result of `f` back if the key was vacant and the insert was dropped.
+    /// See [`VacantEntry::insert`](struct.VacantEntry.html#method.insert).
+    pub fn or_insert_with<F: FnOnce() -> V>(
+        self,
+        cost: i64,
+        ttl: Duration,
+        f: F,
+    ) -> Result<ValueRefMut<'a, V, S>, V> {
+        match self {
+            Entry::Occupied(e) => Ok(e.into_mut()),
+            Entry::Vacant(e) => e.insert(f(), cost, ttl),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the default value with the given cost
+    /// and TTL if empty, and returns a mutable reference to the value in the entry.
+    ///
+    /// Gives the default value back if the key was vacant and the insert was dropped.
+    /// See [`VacantEntry::insert`](struct.VacantEntry.html#method.insert).
+    pub fn or_default(self, cost: i64, ttl: Duration) -> Result<ValueRefMut<'a, V, S>, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(cost, ttl, V::default)
+    }
+
+    /// Provides in-place mutable access to an occupied entry before any potential inserts.
+    ///
+    /// `cost` is the new cost of the value, as for [`insert`], a cost of 0 lets the
+    /// [`Coster`] evaluate it.
+    ///
+    /// [`insert`]: struct.Cache.html#method.insert
+    /// [`Coster`]: trait.Coster.html
+    pub fn and_modify<F: FnOnce(&mut V)>(self, cost: i64, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut e) => {
+                e.modify(cost, f);
+                Entry::Occupied(e)
+            }
+            Entry::Vacant(e) => Entry::Vacant(e),
+        }
+    }
+
+    /// Removes the value of an occupied entry from the cache and returns it.
+    /// Returns `None` for a vacant entry.
+    pub fn remove(self) -> Option<V> {
+        match self {
+            Entry::Occupied(e) => Some(e.remove()),
+            Entry::Vacant(_) => None,
+        }
+    }
+}
+
+/// A view into an occupied entry in the [`Cache`] or [`AsyncCache`]. It is part of the [`Entry`] enum.
+///
+/// [`Cache`]: struct.Cache.html
+/// [`AsyncCache`]: struct.AsyncCache.html
+/// [`Entry`]: enum.Entry.html
+pub struct OccupiedEntry<'a, V, S = RandomState> {
+    data: RwLockWriteGuard<'a, Shard<V, S>>,
+    index: u64,
+    backend: &'a dyn EntryBackend<V, S>,
+}
+
+impl<'a, V, S: BuildHasher> OccupiedEntry<'a, V, S> {
+    #[inline]
+    fn item(&self) -> &StoreItem<V> {
+        // the entry was occupied when it was created, and the shard lock is held since then.
+        self.data.get(&self.index).unwrap()
+    }
+
+    /// Get the reference of the value in the entry.
+    #[inline]
+    pub fn get(&self) -> &V {
+        self.item().value.get()
+    }
+
+    /// Get the mutable reference of the value in the entry.
+    ///
+    /// Unlike [`and_modify`](enum.Entry.html#method.and_modify), this does not update the cost of the value.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut V {
+        self.data.get_mut(&self.index).unwrap().value.get_mut()
+    }
+
+    /// Converts the entry into a mutable reference to its value, which keeps the shard lock.
+    ///
+    /// Like [`get_mut`](#method.get_mut), this does not update the cost of the value.
+    #[inline]
+    pub fn into_mut(self) -> ValueRefMut<'a, V, S> {
+        value_ref(self.data, self.index)
+    }
+
+    /// Get the expiration time of the value in the entry.
+    #[inline]
+    pub fn ttl(&self) -> Duration {
+        self.item().deadline().get_ttl(self.backend.now())
+    }
+
+    /// Modifies the value in place, and updates its cost in the policy.
+    pub fn modify<F: FnOnce(&mut V)>(&mut self, cost: i64, f: F) {
+        f(self.get_mut());
+        self.backend.try_send_update(self.index, cost, self.get());
+    }
+
+    /// Removes the value of the entry from the cache and returns it.
+    pub fn remove(mut self) -> V {
+        let item = self.data.remove(&self.index).unwrap();
+        self.backend
+            .try_send_delete(self.index, item.conflict, item.expiration);
+        item.value.into_inner()
+    }
+}
+
+/// A view into a vacant entry in the [`Cache`] or [`AsyncCache`]. It is part of the [`Entry`] enum.
+///
+/// [`Cache`]: struct.Cache.html
+/// [`AsyncCache`]: struct.AsyncCache.html
+/// [`Entry`]: enum.Entry.html
+pub struct VacantEntry<'a, V, S = RandomState> {
+    data: RwLockWriteGuard<'a, Shard<V, S>>,
+    index: u64,
+    conflict: u64,
+    new_item: NewItem<'a, V>,
+    backend: &'a dyn EntryBackend<V, S>,
+}
+
+impl<'a, V, S: BuildHasher> VacantEntry<'a, V, S> {
+    /// Inserts the value with the given cost and TTL (a zero TTL means the value never expires,
+    /// or idles out after the time-to-idle of the cache), and returns a mutable reference to it.
+    /// The [`Expiry`] of the cache, if any, takes precedence over `ttl`.
+    ///
+    /// Unlike [`insert`], the value is put in the store right away, under the shard lock held
+    /// by the entry, so the other `entry` calls on the key find it occupied. The admission
+    /// policy decides about it afterwards, and removes it from the cache if it rejects it,
+    /// as for a [`remove`] followed by [`on_reject`].
+    ///
+    /// Gives `val` back if the insert buffer is full, or if the key collides with the live
+    /// item of another key.
+    ///
+    /// [`insert`]: struct.Cache.html#method.insert
+    /// [`remove`]: struct.Cache.html#method.remove
+    /// [`on_reject`]: trait.CacheCallback.html#method.on_reject
+    /// [`Expiry`]: trait.Expiry.html
+    pub fn insert(mut self, val: V, cost: i64, ttl: Duration) -> Result<ValueRefMut<'a, V, S>, V> {
+        let now = self.backend.now();
+        if self
+            .data
+            .get(&self.index)
+            .is_some_and(|item| !item.is_expired(now))
+        {
+            return Err(val);
+        }
+
+        // the item is sent first, the processor cannot reach the store before it is put
+        // there since the shard lock is held.
+        if !self
+            .backend
+            .try_send_stored(self.index, self.conflict, cost, &val)
+        {
+            return Err(val);
+        }
+
+        let (expiration, retained_key) = (self.new_item)(&val, ttl);
+        self.backend.insert_new(
+            &mut self.data,
+            self.index,
+            self.conflict,
+            val,
+            expiration,
+            retained_key,
+        );
+        Ok(value_ref(self.data, self.index))
+    }
+}
+
+/// `value_ref` returns the reference to the value of the key, which is in the locked shard.
+#[inline]
+fn value_ref<V, S: BuildHasher>(
+    data: RwLockWriteGuard<'_, Shard<V, S>>,
+    index: u64,
+) -> ValueRefMut<'_, V, S> {
+    // the item stays in the shard as long as the lock is held, and the reference keeps it.
+    let val = unsafe { &mut *data[&index].value.as_ptr() };
+    ValueRefMut::new(data, val)
+}
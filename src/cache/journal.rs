@@ -0,0 +1,621 @@
+#[cfg(feature = "serilization")]
+use crate::cache::snapshot::SnapshotEntry;
+use crate::store::{ShardedMap, StoreItem};
+use crate::{CacheError, UpdateValidator};
+use parking_lot::Mutex;
+#[cfg(feature = "serilization")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::hash::BuildHasher;
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Instant;
+#[cfg(feature = "serilization")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh64::xxh64;
+
+/// The magic bytes and the version at the head of a journal file.
+const JOURNAL_HEADER: &[u8; 5] = b"STRJ\x01";
+
+/// A record is framed by the length of its body and the checksum of its body.
+const FRAME_HEADER_SIZE: usize = 4 + 8;
+
+/// The journal is compacted once it holds this many records more than the items written by
+/// the last compaction, at the least.
+const MIN_COMPACTION_RECORDS: usize = 1024;
+
+const TAG_PUT: u8 = 0;
+const TAG_REMOVE: u8 = 1;
+const TAG_CLEAR: u8 = 2;
+
+/// PutEncoder encodes the key, the value, the cost and the expiration of an item for a `Put`
+/// record, or returns `None` if its key is not retained. The cost is given by the caller.
+pub(crate) type PutEncoder<V> = fn(&StoreItem<V>, i64, Instant) -> Option<Vec<u8>>;
+
+/// Task is the disk work handed over to the worker thread of the journal, so that the
+/// processor does not wait for it.
+enum Task {
+    /// Sync flushes a handle of the journal file to the disk.
+    Sync(File),
+    /// Compact writes the framed records of the live items to a new file, which replaces
+    /// the journal once the records appended meanwhile are copied after them.
+    Compact { records: Vec<u8>, count: usize },
+}
+
+/// Journal is the append-only log of the changes of the items, written by the processor.
+///
+/// Each record is `len: u32 | xxh64(body): u64 | body`, in little-endian, with a body of
+/// - `0 | key hash: u64 | conflict: u64 | payload` for a new or updated item,
+/// - `1 | key hash: u64 | conflict: u64` for a removed item,
+/// - `2` when the cache is cleared.
+///
+/// A record torn by a crash fails its checksum, the replay stops there. The compaction
+/// rewrites the live items to a new file which replaces the journal atomically.
+///
+/// The records are buffered in memory, the processor writes them out at each cleanup. The
+/// syncs and the compactions run on a worker thread: a compaction writes a copy of the live
+/// items taken by the processor, while the new records go to the current file and to a tail
+/// which is copied to the new file before it replaces the current one.
+pub(crate) struct Journal<V> {
+    path: PathBuf,
+    /// file is `None` until the first compaction, and after a failed write: the records are
+    /// then dropped until the next compaction succeeds.
+    file: Option<BufWriter<File>>,
+    encode: PutEncoder<V>,
+    /// records is the number of records in the file.
+    records: usize,
+    /// live is the number of items written by the last compaction.
+    live: usize,
+    /// started is set by the first compaction, the processor does not compact the journal
+    /// before, so that it is not replaced while the cache is being restored from it.
+    started: bool,
+    /// worker takes the tasks of the worker thread, once it is spawned.
+    worker: Option<Sender<Task>>,
+    /// tail keeps the framed records appended while a compaction runs, and their number.
+    tail: Option<(Vec<u8>, usize)>,
+}
+
+impl<V: Send + Sync + 'static> Journal<V> {
+    pub(crate) fn new(path: PathBuf, encode: PutEncoder<V>) -> Self {
+        Self {
+            path,
+            file: None,
+            encode,
+            records: 0,
+            live: 0,
+            started: false,
+            worker: None,
+            tail: None,
+        }
+    }
+
+    /// `spawn_worker` starts the worker thread of the journal, it stops once the journal
+    /// is closed or dropped.
+    pub(crate) fn spawn_worker(journal: &Arc<Mutex<Self>>) {
+        let (tx, rx) = channel();
+        journal.lock().worker = Some(tx);
+        let tmp = journal.lock().compaction_path();
+        let journal = Arc::downgrade(journal);
+        thread::spawn(move || work(journal, rx, tmp));
+    }
+
+    /// `put` appends a record of the item.
+    pub(crate) fn put(&mut self, item: &StoreItem<V>, cost: i64, now: Instant) {
+        if self.file.is_none() && self.tail.is_none() {
+            return;
+        }
+        if let Some(body) = self.put_body(item, cost, now) {
+            self.append(&body);
+        }
+    }
+
+    /// `remove` appends a record of the removal of the key.
+    pub(crate) fn remove(&mut self, key: u64, conflict: u64) {
+        let mut body = Vec::with_capacity(1 + 16);
+        body.push(TAG_REMOVE);
+        body.extend_from_slice(&key.to_le_bytes());
+        body.extend_from_slice(&conflict.to_le_bytes());
+        self.append(&body);
+    }
+
+    /// `clear` appends a record of the clearing of the cache.
+    pub(crate) fn clear(&mut self) {
+        self.append(&[TAG_CLEAR]);
+    }
+
+    fn put_body(&self, item: &StoreItem<V>, cost: i64, now: Instant) -> Option<Vec<u8>> {
+        let payload = (self.encode)(item, cost, now)?;
+        let mut body = Vec::with_capacity(1 + 16 + payload.len());
+        body.push(TAG_PUT);
+        body.extend_from_slice(&item.key.to_le_bytes());
+        body.extend_from_slice(&item.conflict.to_le_bytes());
+        body.extend_from_slice(&payload);
+        Some(body)
+    }
+
+    fn append(&mut self, body: &[u8]) {
+        let record = frame(body);
+        if let Some((tail, count)) = self.tail.as_mut() {
+            tail.extend_from_slice(&record);
+            *count += 1;
+        }
+        if let Some(file) = self.file.as_mut() {
+            if file.write_all(&record).is_err() {
+                self.file = None;
+            } else {
+                self.records += 1;
+            }
+        }
+    }
+
+    /// `needs_compaction` returns whether the journal grew enough since the last compaction,
+    /// or must be rewritten after a failed write, and no compaction is running.
+    pub(crate) fn needs_compaction(&self) -> bool {
+        self.started
+            && self.tail.is_none()
+            && (self.file.is_none() || self.records >= MIN_COMPACTION_RECORDS.max(self.live * 2))
+    }
+
+    /// `sync` writes the buffered records out, and has the worker flush them to the disk.
+    pub(crate) fn sync(&mut self) {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return,
+        };
+        if file.flush().is_err() {
+            self.file = None;
+            return;
+        }
+
+        let sent = match (self.worker.as_ref(), file.get_ref().try_clone()) {
+            (Some(worker), Ok(handle)) => worker.send(Task::Sync(handle)).is_ok(),
+            _ => false,
+        };
+        if !sent {
+            self.sync_data();
+        }
+    }
+
+    fn sync_data(&mut self) {
+        let synced = self
+            .file
+            .as_mut()
+            .map_or(Ok(()), |file| file.flush().and(file.get_ref().sync_data()));
+        if synced.is_err() {
+            self.file = None;
+        }
+    }
+
+    /// `close` flushes the records to the disk and detaches the journal: the records are
+    /// dropped from then on, and so is a running compaction.
+    pub(crate) fn close(&mut self) {
+        self.sync_data();
+        self.file = None;
+        self.started = false;
+        self.worker = None;
+        self.tail = None;
+    }
+
+    /// `compact` rewrites the journal with a record for each live item of the store. `cost`
+    /// returns the cost of an item from its key hash.
+    pub(crate) fn compact<U, S, F>(
+        &mut self,
+        store: &ShardedMap<V, U, S, S>,
+        cost: F,
+    ) -> Result<(), CacheError>
+    where
+        U: UpdateValidator<Value = V>,
+        S: BuildHasher + Clone + 'static,
+        F: Fn(u64) -> i64,
+    {
+        self.file = None;
+        let (records, count) = self.live_records(store, cost);
+        let tmp = self.compaction_path();
+        let file = write_compaction(&tmp, &records).map_err(journal_error)?;
+        self.replace(&tmp, file, count).map_err(journal_error)
+    }
+
+    /// `start_compaction` copies the live items of the store, and has the worker rewrite the
+    /// journal with them, see [`compact`](#method.compact).
+    pub(crate) fn start_compaction<U, S, F>(&mut self, store: &ShardedMap<V, U, S, S>, cost: F)
+    where
+        U: UpdateValidator<Value = V>,
+        S: BuildHasher + Clone + 'static,
+        F: Fn(u64) -> i64,
+    {
+        let (records, count) = self.live_records(store, cost);
+        if let Some(worker) = self.worker.as_ref() {
+            if worker.send(Task::Compact { records, count }).is_ok() {
+                self.tail = Some((Vec::new(), 0));
+            }
+        }
+    }
+
+    /// `finish_compaction` copies the tail after the records written by the worker to `tmp`,
+    /// then replaces the journal with it. The current file is kept if it fails.
+    fn finish_compaction(&mut self, tmp: &Path, written: io::Result<File>, count: usize) {
+        let (tail, tail_count) = match self.tail.take() {
+            Some(tail) => tail,
+            // the journal was closed meanwhile.
+            None => {
+                let _ = fs::remove_file(tmp);
+                return;
+            }
+        };
+        let _ = written
+            .and_then(|mut file| {
+                file.write_all(&tail)?;
+                file.sync_data()?;
+                Ok(file)
+            })
+            .and_then(|file| self.replace(tmp, file, count + tail_count));
+    }
+
+    /// `live_records` returns the framed records of the live items of the store, and their number.
+    fn live_records<U, S, F>(&self, store: &ShardedMap<V, U, S, S>, cost: F) -> (Vec<u8>, usize)
+    where
+        U: UpdateValidator<Value = V>,
+        S: BuildHasher + Clone + 'static,
+        F: Fn(u64) -> i64,
+    {
+        let now = store.now();
+        let mut records = Vec::new();
+        let mut count = 0;
+        for shard in store.shards() {
+            for item in shard.values().filter(|item| !item.is_expired(now)) {
+                if let Some(body) = self.put_body(item, cost(item.key), now) {
+                    records.extend_from_slice(&frame(&body));
+                    count += 1;
+                }
+            }
+        }
+        (records, count)
+    }
+
+    /// `replace` moves the compacted file `tmp` holding `count` records over the journal.
+    fn replace(&mut self, tmp: &Path, file: File, count: usize) -> io::Result<()> {
+        drop(file);
+        fs::rename(tmp, &self.path)?;
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        self.file = Some(BufWriter::new(file));
+        self.records = count;
+        self.live = count;
+        self.started = true;
+        Ok(())
+    }
+
+    fn compaction_path(&self) -> PathBuf {
+        self.path.with_extension("compact")
+    }
+}
+
+/// `work` runs the tasks of the journal until it is closed or dropped.
+fn work<V: Send + Sync + 'static>(
+    journal: Weak<Mutex<Journal<V>>>,
+    tasks: Receiver<Task>,
+    tmp: PathBuf,
+) {
+    for task in tasks {
+        match task {
+            Task::Sync(file) => {
+                if file.sync_data().is_err() {
+                    if let Some(journal) = journal.upgrade() {
+                        journal.lock().file = None;
+                    }
+                }
+            }
+            Task::Compact { records, count } => {
+                let written = write_compaction(&tmp, &records);
+                match journal.upgrade() {
+                    Some(journal) => journal.lock().finish_compaction(&tmp, written, count),
+                    None => {
+                        let _ = fs::remove_file(&tmp);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `write_compaction` writes a new journal file at `tmp` with the framed `records`.
+fn write_compaction(tmp: &Path, records: &[u8]) -> io::Result<File> {
+    let mut file = BufWriter::new(File::create(tmp)?);
+    file.write_all(JOURNAL_HEADER)?;
+    file.write_all(records)?;
+    let file = file.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(file)
+}
+
+/// `replay` reads the journal at `path` and returns the payloads of the items which are live
+/// at its end, in the order they were written. A missing journal is empty, the records after
+/// a torn or corrupted one are ignored.
+pub(crate) fn replay(path: &Path) -> Result<Vec<Vec<u8>>, CacheError> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(journal_error(e)),
+    };
+    if buf.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !buf.starts_with(JOURNAL_HEADER) {
+        return Err(CacheError::JournalError(format!(
+            "{} is not a journal",
+            path.display()
+        )));
+    }
+
+    // the live items by key hash, with the sequence number of their last record.
+    let mut items = HashMap::<u64, (usize, u64, &[u8])>::new();
+    let records = Records {
+        buf: &buf[JOURNAL_HEADER.len()..],
+    };
+    for (seq, body) in records.enumerate() {
+        match body[0] {
+            TAG_PUT if body.len() >= 17 => {
+                let key = u64::from_le_bytes(body[1..9].try_into().unwrap());
+                let conflict = u64::from_le_bytes(body[9..17].try_into().unwrap());
+                items.insert(key, (seq, conflict, &body[17..]));
+            }
+            TAG_REMOVE if body.len() == 17 => {
+                let key = u64::from_le_bytes(body[1..9].try_into().unwrap());
+                let conflict = u64::from_le_bytes(body[9..17].try_into().unwrap());
+                if items
+                    .get(&key)
+                    .is_some_and(|(_, c, _)| conflict == 0 || *c == conflict)
+                {
+                    items.remove(&key);
+                }
+            }
+            TAG_CLEAR => items.clear(),
+            _ => break,
+        }
+    }
+
+    let mut items = items.into_values().collect::<Vec<_>>();
+    items.sort_unstable_by_key(|(seq, _, _)| *seq);
+    Ok(items
+        .into_iter()
+        .map(|(_, _, payload)| payload.to_vec())
+        .collect())
+}
+
+/// Records iterates over the bodies of the records, until the end of the journal or the
+/// first torn record.
+struct Records<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < FRAME_HEADER_SIZE {
+            return None;
+        }
+        let len = u32::from_le_bytes(self.buf[..4].try_into().unwrap()) as usize;
+        let checksum = u64::from_le_bytes(self.buf[4..FRAME_HEADER_SIZE].try_into().unwrap());
+        let body = self.buf.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len)?;
+        if body.is_empty() || xxh64(body, 0) != checksum {
+            return None;
+        }
+        self.buf = &self.buf[FRAME_HEADER_SIZE + len..];
+        Some(body)
+    }
+}
+
+/// JournalEntry is the payload of a `Put` record, `expires_at` is the deadline of the item in
+/// milliseconds since the UNIX epoch, so that the time spent down counts, and `tti` its
+/// time-to-idle. At most one of them is set.
+#[cfg(feature = "serilization")]
+#[derive(Serialize, Deserialize)]
+struct JournalEntry<K, V> {
+    key: K,
+    value: V,
+    cost: i64,
+    expires_at: Option<u64>,
+    tti: Option<Duration>,
+}
+
+/// `encode_put` is the [`PutEncoder`] of the caches whose keys and values are serialized
+/// with serde.
+#[cfg(feature = "serilization")]
+pub(crate) fn encode_put<K, V>(item: &StoreItem<V>, cost: i64, now: Instant) -> Option<Vec<u8>>
+where
+    K: Serialize + 'static,
+    V: Serialize,
+{
+    let (key, value) = item.retained::<K>(now)?;
+    let expiration = item.deadline();
+    let (expires_at, tti) = if expiration.is_zero() {
+        (None, None)
+    } else if expiration.is_idle() {
+        (
+            None,
+            Some(Duration::from_nanos(expiration.deadline_nanos())),
+        )
+    } else {
+        let at = SystemTime::now() + expiration.get_ttl(now);
+        (Some(unix_millis(at)), None)
+    };
+
+    serde_json::to_vec(&JournalEntry {
+        key,
+        value,
+        cost,
+        expires_at,
+        tti,
+    })
+    .ok()
+}
+
+/// `decode_put` decodes the payload of a `Put` record, it returns `None` if the item expired.
+#[cfg(feature = "serilization")]
+pub(crate) fn decode_put<K, V>(payload: &[u8]) -> Result<Option<SnapshotEntry<K, V>>, CacheError>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+{
+    let entry: JournalEntry<K, V> = serde_json::from_slice(payload)
+        .map_err(|e| CacheError::JournalError(format!("fail to decode a record: {}", e)))?;
+    let ttl = match entry.expires_at {
+        Some(at) => match at.checked_sub(unix_millis(SystemTime::now())) {
+            Some(ms) if ms > 0 => Some(Duration::from_millis(ms)),
+            _ => return Ok(None),
+        },
+        None => None,
+    };
+
+    Ok(Some(SnapshotEntry {
+        key: entry.key,
+        value: entry.value,
+        cost: entry.cost,
+        ttl,
+        tti: entry.tti,
+    }))
+}
+
+#[cfg(feature = "serilization")]
+fn unix_millis(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+fn frame(body: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(FRAME_HEADER_SIZE + body.len());
+    buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&xxh64(body, 0).to_le_bytes());
+    buf.extend_from_slice(body);
+    buf
+}
+
+fn journal_error(e: std::io::Error) -> CacheError {
+    CacheError::JournalError(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ttl::Time;
+    use std::time::Duration;
+
+    fn put(key: u64, conflict: u64, payload: &[u8]) -> Vec<u8> {
+        let mut body = vec![TAG_PUT];
+        body.extend_from_slice(&key.to_le_bytes());
+        body.extend_from_slice(&conflict.to_le_bytes());
+        body.extend_from_slice(payload);
+        frame(&body)
+    }
+
+    fn remove(key: u64, conflict: u64) -> Vec<u8> {
+        let mut body = vec![TAG_REMOVE];
+        body.extend_from_slice(&key.to_le_bytes());
+        body.extend_from_slice(&conflict.to_le_bytes());
+        frame(&body)
+    }
+
+    fn journal(name: &str, records: &[Vec<u8>]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("stretto-journal-{}-{}", name, std::process::id()));
+        let mut buf = JOURNAL_HEADER.to_vec();
+        records.iter().for_each(|r| buf.extend_from_slice(r));
+        fs::write(&path, buf).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_replay() {
+        let path = journal(
+            "replay",
+            &[
+                put(1, 10, b"a"),
+                put(2, 20, b"b"),
+                put(1, 10, b"c"),
+                remove(2, 21),
+                put(3, 30, b"d"),
+                remove(3, 0),
+            ],
+        );
+        assert_eq!(replay(&path).unwrap(), vec![b"b".to_vec(), b"c".to_vec()]);
+        fs::remove_file(&path).unwrap();
+
+        let path = journal(
+            "replay-clear",
+            &[put(1, 10, b"a"), frame(&[TAG_CLEAR]), put(2, 20, b"b")],
+        );
+        assert_eq!(replay(&path).unwrap(), vec![b"b".to_vec()]);
+        fs::remove_file(&path).unwrap();
+
+        let missing = std::env::temp_dir().join("stretto-journal-missing");
+        assert!(replay(&missing).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replay_torn() {
+        let mut torn = put(2, 20, b"b");
+        torn.truncate(torn.len() - 1);
+        let path = journal("torn", &[put(1, 10, b"a"), torn]);
+        assert_eq!(replay(&path).unwrap(), vec![b"a".to_vec()]);
+        fs::remove_file(&path).unwrap();
+
+        let mut corrupted = put(2, 20, b"b");
+        *corrupted.last_mut().unwrap() ^= 0xff;
+        let path = journal(
+            "corrupted",
+            &[put(1, 10, b"a"), corrupted, put(3, 30, b"c")],
+        );
+        assert_eq!(replay(&path).unwrap(), vec![b"a".to_vec()]);
+        fs::remove_file(&path).unwrap();
+
+        let path = std::env::temp_dir().join(format!("stretto-journal-bad-{}", std::process::id()));
+        fs::write(&path, b"not a journal").unwrap();
+        assert!(matches!(replay(&path), Err(CacheError::JournalError(_))));
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn encode(item: &StoreItem<u64>, _: i64, _: Instant) -> Option<Vec<u8>> {
+        Some(item.value.get().to_le_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_compaction_in_background() {
+        let path = journal("background", &[]);
+        let store: ShardedMap<u64> = ShardedMap::new();
+        for key in 1..=3 {
+            store
+                .try_insert(key, key * 10, 0, Time::never(Instant::now()), None)
+                .unwrap();
+        }
+
+        let journal = Arc::new(Mutex::new(Journal::new(path.clone(), encode)));
+        journal.lock().compact(&store, |_| 1).unwrap();
+        Journal::spawn_worker(&journal);
+
+        {
+            let mut journal = journal.lock();
+            journal.start_compaction(&store, |_| 1);
+            assert!(!journal.needs_compaction());
+            // appended meanwhile, copied after the compacted items.
+            journal.remove(1, 0);
+            journal.sync();
+        }
+
+        let mut polls = 0;
+        while journal.lock().tail.is_some() {
+            assert!(polls < 500, "the compaction did not finish");
+            polls += 1;
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(journal.lock().records, 4);
+
+        journal.lock().remove(2, 0);
+        journal.lock().close();
+        assert_eq!(replay(&path).unwrap(), vec![30u64.to_le_bytes().to_vec()]);
+        assert!(!path.with_extension("compact").exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+/// InsertOutcome tells what happened to a key-value item passed to the cache.
+///
+/// See [`Cache::insert_with_outcome`] and [`Cache::insert_and_wait`].
+///
+/// [`Cache::insert_with_outcome`]: struct.Cache.html#method.insert_with_outcome
+/// [`Cache::insert_and_wait`]: struct.Cache.html#method.insert_and_wait
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum InsertOutcome {
+    /// The new item was pushed to the insert buffer, the policy has not decided yet
+    /// whether it will be kept.
+    Queued,
+    /// The key was already in the cache, its value was updated in place.
+    Updated,
+    /// The item was dropped before reaching the policy.
+    Dropped(DropReason),
+    /// The cache is closed.
+    Closed,
+    /// The cost of the item, with the internal cost unless it is ignored, is bigger than
+    /// the max cost of the whole cache, so the policy would never admit it. The item is
+    /// passed to `on_reject`.
+    TooLarge,
+    /// The policy admitted the new item, it is in the cache. Only returned by `insert_and_wait`.
+    Admitted,
+    /// The policy rejected the new item, it is passed to `on_reject`. Only returned
+    /// by `insert_and_wait`.
+    Rejected,
+}
+
+impl InsertOutcome {
+    /// Returns true if the value is in the cache, or may be once the policy has
+    /// handled it, that is what the plain `insert` returns.
+    #[inline]
+    pub fn is_inserted(&self) -> bool {
+        matches!(
+            self,
+            InsertOutcome::Queued | InsertOutcome::Updated | InsertOutcome::Admitted
+        )
+    }
+}
+
+/// DropReason is the reason an item was [`Dropped`](enum.InsertOutcome.html#variant.Dropped).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum DropReason {
+    /// The insert buffer was full.
+    BufferFull,
+}
+
+/// MaintenanceReport tells what the cache settled since the previous call of
+/// [`Cache::run_pending_tasks`], including the work done in the background meanwhile.
+///
+/// [`Cache::run_pending_tasks`]: struct.Cache.html#method.run_pending_tasks
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MaintenanceReport {
+    /// The number of inserts, updates and removals applied from the insert buffer.
+    pub applied: u64,
+    /// The number of items evicted by the policy to make room for new ones.
+    pub evicted: u64,
+    /// The number of expired items removed by the cleanup.
+    pub expired: u64,
+}
+
+/// WarmReport tells how many entries [`Cache::warm`] loaded in the cache, and how many it
+/// skipped because they did not fit or their key was already in the cache.
+///
+/// [`Cache::warm`]: struct.Cache.html#method.warm
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct WarmReport {
+    /// The number of entries loaded in the cache.
+    pub loaded: u64,
+    /// The number of entries skipped.
+    pub skipped: u64,
+}
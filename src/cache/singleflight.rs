@@ -0,0 +1,177 @@
+//! Duplicate call suppression for the loader APIs of the Cache.
+//!
+//! Concurrent misses on the same key join one in-flight load: the first caller
+//! becomes the leader and runs the loader, the others wait on a channel which
+//! is closed by the leader when it is done. Closing instead of sending means a
+//! leader that fails, panics or (in async) is cancelled still wakes its waiters,
+//! which then simply retry.
+//!
+//! A completed load stays in the group until the leader is dropped, so that the
+//! callers which miss the store meanwhile get the loaded value instead of running
+//! the loader again.
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Call is a load which is in flight, or just completed.
+pub(crate) struct Call<V, R> {
+    value: Mutex<Option<V>>,
+    done: R,
+}
+
+impl<V: Clone, R> Call<V, R> {
+    /// `done` returns the receiver which is closed once the leader is finished.
+    #[inline]
+    pub(crate) fn done(&self) -> &R {
+        &self.done
+    }
+
+    /// `value` returns the loaded value, `None` if the leader did not load a value.
+    #[inline]
+    pub(crate) fn value(&self) -> Option<V> {
+        self.value.lock().clone()
+    }
+}
+
+/// Flight is the result of joining a load.
+pub(crate) enum Flight<'a, V, T, R> {
+    /// The caller has to run the loader.
+    Leader(Leader<'a, V, T, R>),
+    /// Another caller is running the loader.
+    Follower(Arc<Call<V, R>>),
+}
+
+/// Leader removes the in-flight call and wakes the followers when dropped.
+pub(crate) struct Leader<'a, V, T, R> {
+    group: &'a Group<V, R>,
+    key: (u64, u64),
+    call: Arc<Call<V, R>>,
+    done: Option<T>,
+}
+
+impl<'a, V, T, R> Leader<'a, V, T, R> {
+    /// `complete` hands the loaded value over to the followers and wakes them. The call
+    /// stays in the group, with its value, until the leader is dropped.
+    #[inline]
+    pub(crate) fn complete(&mut self, val: V) {
+        *self.call.value.lock() = Some(val);
+        self.done.take();
+    }
+}
+
+impl<'a, V, T, R> Drop for Leader<'a, V, T, R> {
+    fn drop(&mut self) {
+        let mut calls = self.group.calls.lock();
+        if calls
+            .get(&self.key)
+            .is_some_and(|call| Arc::ptr_eq(call, &self.call))
+        {
+            calls.remove(&self.key);
+        }
+        // `done` is dropped right after, if it is still there, which closes the channel.
+    }
+}
+
+/// Group keeps track of the loads in flight, keyed by `(index, conflict)`.
+pub(crate) struct Group<V, R> {
+    calls: Mutex<HashMap<(u64, u64), Arc<Call<V, R>>>>,
+}
+
+impl<V, R> Group<V, R> {
+    pub(crate) fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `join` returns the in-flight call for the key, or registers a new one,
+    /// built from the channel returned by `chan`, with the caller as its leader.
+    pub(crate) fn join<T>(
+        &self,
+        index: u64,
+        conflict: u64,
+        chan: impl FnOnce() -> (T, R),
+    ) -> Flight<'_, V, T, R> {
+        let key = (index, conflict);
+        let mut calls = self.calls.lock();
+        if let Some(call) = calls.get(&key) {
+            return Flight::Follower(call.clone());
+        }
+
+        let (tx, rx) = chan();
+        let call = Arc::new(Call {
+            value: Mutex::new(None),
+            done: rx,
+        });
+        calls.insert(key, call.clone());
+        Flight::Leader(Leader {
+            group: self,
+            key,
+            call,
+            done: Some(tx),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cache::singleflight::{Flight, Group};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    fn join(g: &Group<u64, Receiver<()>>) -> Flight<'_, u64, Sender<()>, Receiver<()>> {
+        g.join(1, 0, channel)
+    }
+
+    #[test]
+    fn test_group_leader_complete() {
+        let g = Group::new();
+        let mut leader = match join(&g) {
+            Flight::Leader(leader) => leader,
+            Flight::Follower(_) => panic!("expected a leader"),
+        };
+
+        let call = match join(&g) {
+            Flight::Follower(call) => call,
+            Flight::Leader(_) => panic!("expected a follower"),
+        };
+        assert!(call.value().is_none());
+
+        leader.complete(7);
+        assert!(call.done().recv().is_err());
+        assert_eq!(call.value(), Some(7));
+
+        // the completed flight is kept until the leader is dropped.
+        let call = match join(&g) {
+            Flight::Follower(call) => call,
+            Flight::Leader(_) => panic!("expected a follower"),
+        };
+        assert!(call.done().recv().is_err());
+        assert_eq!(call.value(), Some(7));
+
+        // the flight is over, the next caller leads a new one.
+        drop(leader);
+        assert!(matches!(join(&g), Flight::Leader(_)));
+    }
+
+    #[test]
+    fn test_group_leader_dropped() {
+        let g = Group::new();
+        let leader = join(&g);
+        let call = match join(&g) {
+            Flight::Follower(call) => call,
+            Flight::Leader(_) => panic!("expected a follower"),
+        };
+
+        drop(leader);
+        assert!(call.done().recv().is_err());
+        assert!(call.value().is_none());
+    }
+
+    #[test]
+    fn test_group_keys() {
+        let g: Group<u64, Receiver<()>> = Group::new();
+        let _a = g.join(1, 0, channel::<()>);
+        assert!(matches!(g.join(1, 1, channel::<()>), Flight::Leader(_)));
+        assert!(matches!(g.join(2, 0, channel::<()>), Flight::Leader(_)));
+    }
+}
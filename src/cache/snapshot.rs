@@ -0,0 +1,141 @@
+use crate::store::{ShardedMap, StoreItem};
+use crate::{CacheError, UpdateValidator};
+use serde::de::DeserializeOwned;
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Serialize, Serializer};
+use std::hash::BuildHasher;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+/// The version of the snapshot format, bumped on every incompatible change.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// SnapshotEntry is an item of a snapshot, `ttl` is the remaining time to live of the item
+/// and `tti` its time-to-idle, at most one of them is set.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SnapshotEntry<K, V> {
+    pub(crate) key: K,
+    pub(crate) value: V,
+    pub(crate) cost: i64,
+    pub(crate) ttl: Option<Duration>,
+    pub(crate) tti: Option<Duration>,
+}
+
+/// Snapshot is a snapshot read back by `load_snapshot`.
+#[derive(Deserialize)]
+pub(crate) struct Snapshot<K, V> {
+    version: u32,
+    pub(crate) entries: Vec<SnapshotEntry<K, V>>,
+}
+
+impl<K: DeserializeOwned, V: DeserializeOwned> Snapshot<K, V> {
+    pub(crate) fn from_reader<R: Read>(reader: R) -> Result<Self, CacheError> {
+        let snapshot: Self = serde_json::from_reader(reader)
+            .map_err(|e| CacheError::SnapshotError(format!("fail to read snapshot: {}", e)))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(CacheError::SnapshotError(format!(
+                "unsupported snapshot version {}",
+                snapshot.version
+            )));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// SnapshotWriter serializes the live items of the store, one shard at a time under its read
+/// lock, so that the keys and the values are not cloned.
+pub(crate) struct SnapshotWriter<'a, K, V, U, S, F> {
+    store: &'a ShardedMap<V, U, S, S>,
+    cost: F,
+    _marker: PhantomData<fn(&K)>,
+}
+
+impl<'a, K, V, U, S, F> SnapshotWriter<'a, K, V, U, S, F>
+where
+    K: Serialize + 'static,
+    V: Serialize + Send + Sync + 'static,
+    U: UpdateValidator<Value = V>,
+    S: BuildHasher + Clone + 'static,
+    F: Fn(u64) -> i64,
+{
+    /// `new` returns a writer of the items of `store`, `cost` returns the cost given to the
+    /// key hash when it was inserted.
+    pub(crate) fn new(store: &'a ShardedMap<V, U, S, S>, cost: F) -> Self {
+        Self {
+            store,
+            cost,
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn write<W: Write>(&self, writer: W) -> Result<(), CacheError> {
+        serde_json::to_writer(writer, self)
+            .map_err(|e| CacheError::SnapshotError(format!("fail to write snapshot: {}", e)))
+    }
+
+    fn entry<'b>(
+        &self,
+        item: &'b StoreItem<V>,
+        now: Instant,
+    ) -> Option<SnapshotEntry<&'b K, &'b V>> {
+        let (key, value) = item.retained::<K>(now)?;
+        let expiration = item.deadline();
+        let (ttl, tti) = if expiration.is_zero() {
+            (None, None)
+        } else if expiration.is_idle() {
+            (
+                None,
+                Some(Duration::from_nanos(expiration.deadline_nanos())),
+            )
+        } else {
+            (Some(expiration.get_ttl(now)), None)
+        };
+
+        Some(SnapshotEntry {
+            key,
+            value,
+            cost: (self.cost)(item.key).max(0),
+            ttl,
+            tti,
+        })
+    }
+}
+
+impl<'a, K, V, U, S, F> Serialize for SnapshotWriter<'a, K, V, U, S, F>
+where
+    K: Serialize + 'static,
+    V: Serialize + Send + Sync + 'static,
+    U: UpdateValidator<Value = V>,
+    S: BuildHasher + Clone + 'static,
+    F: Fn(u64) -> i64,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let mut s = serializer.serialize_struct("Snapshot", 2)?;
+        s.serialize_field("version", &SNAPSHOT_VERSION)?;
+        s.serialize_field("entries", &SnapshotEntries(self))?;
+        s.end()
+    }
+}
+
+struct SnapshotEntries<'w, 'a, K, V, U, S, F>(&'w SnapshotWriter<'a, K, V, U, S, F>);
+
+impl<'w, 'a, K, V, U, S, F> Serialize for SnapshotEntries<'w, 'a, K, V, U, S, F>
+where
+    K: Serialize + 'static,
+    V: Serialize + Send + Sync + 'static,
+    U: UpdateValidator<Value = V>,
+    S: BuildHasher + Clone + 'static,
+    F: Fn(u64) -> i64,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let now = self.0.store.now();
+        let mut seq = serializer.serialize_seq(None)?;
+        for shard in self.0.store.shards() {
+            for entry in shard.values().filter_map(|item| self.0.entry(item, now)) {
+                seq.serialize_element(&entry)?;
+            }
+        }
+        seq.end()
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{CacheCallback, Coster, Item as CrateItem, KeyBuilder, TransparentHasher};
+use crate::{
+    Admission, CacheCallback, Coster, Expiry, Item as CrateItem, KeyBuilder, Lru,
+    MaintenanceReport, MockClock, PolicyConfig, TransparentHasher, WTinyLfu, WarmReport,
+};
 use parking_lot::Mutex;
 use rand::rngs::OsRng;
 use rand::Rng;
@@ -6,11 +9,12 @@ use std::collections::HashSet;
 use std::hash::Hasher;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 static CHARSET: &[u8] = "abcdefghijklmnopqrstuvwxyz0123456789".as_bytes();
 
 fn get_key() -> [u8; 2] {
-    let mut rng = OsRng::default();
+    let mut rng = OsRng;
     let k1 = CHARSET[rng.gen::<usize>() % CHARSET.len()];
     let k2 = CHARSET[rng.gen::<usize>() % CHARSET.len()];
     [k1, k2]
@@ -72,6 +76,38 @@ impl Coster for TestCoster {
     type Value = u64;
 }
 
+/// TestExpiry gives the values a TTL of their own number of milliseconds (none for zero),
+/// keeps the TTL on update and resets it on read.
+struct TestExpiry;
+
+impl Expiry for TestExpiry {
+    type Key = u64;
+    type Value = u64;
+
+    fn expire_after_create(&self, _key: &u64, val: &u64, _now: Instant) -> Option<Duration> {
+        (*val > 0).then(|| Duration::from_millis(*val))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &u64,
+        _val: &u64,
+        _now: Instant,
+        current_ttl: Option<Duration>,
+    ) -> Option<Duration> {
+        current_ttl
+    }
+
+    fn expire_after_read(
+        &self,
+        val: &u64,
+        _now: Instant,
+        _current_ttl: Option<Duration>,
+    ) -> Option<Duration> {
+        (*val > 0).then(|| Duration::from_millis(*val))
+    }
+}
+
 impl CacheCallback for TestCallback {
     fn on_exit(&self, _val: Option<u64>) {}
 
@@ -84,6 +120,36 @@ impl CacheCallback for TestCallback {
     type Value = u64;
 }
 
+/// GateCallback blocks the processor on a rejection while the gate is locked.
+struct GateCallback {
+    gate: Arc<Mutex<()>>,
+}
+
+impl CacheCallback for GateCallback {
+    fn on_exit(&self, _val: Option<u64>) {}
+
+    fn on_reject(&self, _item: CrateItem<u64>) {
+        drop(self.gate.lock());
+    }
+
+    type Value = u64;
+}
+
+/// RejectCallback records the rejected values.
+struct RejectCallback {
+    rejected: Arc<Mutex<Vec<u64>>>,
+}
+
+impl CacheCallback for RejectCallback {
+    fn on_exit(&self, _val: Option<u64>) {}
+
+    fn on_reject(&self, item: CrateItem<u64>) {
+        self.rejected.lock().extend(item.val);
+    }
+
+    type Value = u64;
+}
+
 struct TestCallbackDropUpdates {
     set: Arc<Mutex<HashSet<u64>>>,
 }
@@ -114,9 +180,9 @@ mod sync_test {
     use crate::cache::sync::Item;
     use crate::cache::test::{KHTest, TestCallback, TestCallbackDropUpdates, TestCoster};
     use crate::{
-        Cache, CacheBuilder, CacheCallback, Coster, DefaultCacheCallback, DefaultCoster,
-        DefaultKeyBuilder, DefaultUpdateValidator, KeyBuilder, TransparentKeyBuilder,
-        UpdateValidator,
+        Cache, CacheBuilder, CacheCallback, CacheError, Coster, DefaultCacheCallback,
+        DefaultCoster, DefaultKeyBuilder, DefaultUpdateValidator, Entry, InsertOutcome, KeyBuilder,
+        TransparentKeyBuilder, UpdateValidator,
     };
     use crossbeam_channel::{bounded, select};
     use parking_lot::Mutex;
@@ -168,6 +234,7 @@ mod sync_test {
                 .set_max_cost(100)
                 .set_cleanup_duration(Duration::from_secs(1))
                 .set_buffer_size(1000)
+                .set_buffer_items(32)
                 .set_key_builder(DefaultKeyBuilder::default())
                 .set_hasher(RandomState::default())
                 .finalize()
@@ -215,20 +282,16 @@ mod sync_test {
                     recv(rx) -> _ => return,
                     default => {
                         let k = get_key();
-                        match tc.get(&k) {
-                            None => {
-                                let mut rng = OsRng::default();
-                                let rv = rng.gen::<usize>() % 100;
-                                let val: String;
-                                if rv < 10 {
-                                    val = "test".to_string();
-                                } else {
-                                    val = vec!["a"; 1000].join("");
-                                }
-                                let cost = val.len() + 2;
-                                tc.insert(get_key(), val, cost as i64);
-                            },
-                            Some(_) => {},
+                        if tc.get(&k).is_none() {
+                            let mut rng = OsRng;
+                            let rv = rng.gen::<usize>() % 100;
+                            let val = if rv < 10 {
+                                "test".to_string()
+                            } else {
+                                vec!["a"; 1000].join("")
+                            };
+                            let cost = val.len() + 2;
+                            tc.insert(get_key(), val, cost as i64);
                         }
                     }
                 }
@@ -388,6 +451,32 @@ mod sync_test {
         assert_eq!(c.get_mut(&1).unwrap().read(), 10);
     }
 
+    #[test]
+    fn test_cache_get_buffer() {
+        let c: Cache<u64, u64, TransparentKeyBuilder<u64>> = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_buffer_items(4)
+            .set_metrics(true)
+            .finalize()
+            .unwrap();
+
+        (0..8).for_each(|_| {
+            assert!(c.get(&1).is_none());
+        });
+        c.policy.flush();
+
+        assert_eq!(c.metrics.get_gets_kept().unwrap(), 8);
+        assert!(c.policy.inner.lock().admit.estimate(1) > 0);
+    }
+
+    #[test]
+    fn test_cache_invalid_buffer_items() {
+        let rst = Cache::<u64, u64>::builder(100, 10)
+            .set_buffer_items(0)
+            .finalize();
+        assert!(matches!(rst, Err(CacheError::InvalidBufferItems)));
+    }
+
     #[test]
     fn test_cache_set() {
         let c = Cache::builder(100, 10)
@@ -412,605 +501,2383 @@ mod sync_test {
     }
 
     #[test]
-    fn test_cache_internal_cost() {
+    fn test_cache_get_or_insert_with() {
         let c = Cache::builder(100, 10)
             .set_key_builder(TransparentKeyBuilder::default())
-            .set_metrics(true)
+            .set_ignore_internal_cost(true)
             .finalize()
             .unwrap();
+        let loads = Arc::new(AtomicU64::new(0));
+
+        let handles = (0..8)
+            .map(|_| {
+                let c = c.clone();
+                let loads = loads.clone();
+                spawn(move || {
+                    c.get_or_insert_with(1, 1, || {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        sleep(Duration::from_millis(100));
+                        7
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 7);
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
 
-        // Get should return None because the cache's cost is too small to store the item
-        // when accounting for the internal cost.
-        c.insert_with_ttl(1, 1, 1, Duration::ZERO);
-        sleep(Duration::from_millis(100));
-        assert!(c.get(&1).is_none())
+        c.wait().unwrap();
+        assert_eq!(c.get(&1).unwrap().read(), 7);
+        assert_eq!(c.get_or_insert_with(1, 1, || unreachable!()), 7);
     }
 
     #[test]
-    fn test_recache_with_ttl() {
+    fn test_cache_try_get_or_insert_with() {
         let c = Cache::builder(100, 10)
             .set_key_builder(TransparentKeyBuilder::default())
             .set_ignore_internal_cost(true)
-            .set_metrics(true)
             .finalize()
             .unwrap();
 
-        // Set initial value for key = 1
-        assert!(c.insert_with_ttl(1, 1, 1, Duration::from_secs(5)));
-
-        sleep(Duration::from_secs(2));
-
-        // Get value from cache for key = 1
-        assert_eq!(c.get(&1).unwrap().read(), 1);
-
-        // wait for expiration
-        sleep(Duration::from_secs(5));
-
-        // The cached value for key = 1 should be gone
+        let rst = c.try_get_or_insert_with(1, 1, || {
+            Err(CacheError::SendError("fail to load".to_string()))
+        });
+        assert!(rst.is_err());
+        c.wait().unwrap();
         assert!(c.get(&1).is_none());
 
-        // set new value for key = 1
-        assert!(c.insert_with_ttl(1, 2, 1, Duration::from_secs(5)));
-
-        sleep(Duration::from_secs(2));
-        // get value from cache for key = 1;
+        let rst = c.try_get_or_insert_with(1, 1, || Ok::<_, CacheError>(2));
+        assert_eq!(rst.unwrap(), 2);
+        c.wait().unwrap();
         assert_eq!(c.get(&1).unwrap().read(), 2);
     }
 
     #[test]
-    fn test_cache_set_with_ttl() {
-        let cb = Arc::new(Mutex::new(HashSet::new()));
-        let c = Cache::builder(100, 10)
+    fn test_cache_entry() {
+        let c = Cache::<u64, u64>::builder(100, 10)
             .set_key_builder(TransparentKeyBuilder::default())
-            .set_callback(TestCallback::new(cb.clone()))
             .set_ignore_internal_cost(true)
             .finalize()
             .unwrap();
 
-        retry_set(c.clone(), 1, 1, 1, Duration::from_secs(1));
-
-        // Sleep to make sure the item has expired after execution resumes.
-        sleep(Duration::from_secs(2));
-        assert!(c.get(&1).is_none());
-
-        // Sleep to ensure that the bucket where the item was stored has been cleared
-        // from the expiration map.
-        sleep(Duration::from_secs(5));
-        assert_eq!(cb.lock().len(), 1);
+        assert_eq!(
+            c.entry(1).or_insert(1, 1, Duration::ZERO).unwrap().read(),
+            1
+        );
+        // the value is in the store before the policy sees it.
+        assert_eq!(c.get(&1).unwrap().read(), 1);
+        assert!(matches!(c.entry(1), Entry::Occupied(_)));
+        c.wait().unwrap();
+        assert!(c.policy.contains(&1));
 
-        // Verify that expiration times are overwritten.
-        retry_set(c.clone(), 2, 1, 1, Duration::from_secs(1));
-        retry_set(c.clone(), 2, 2, 1, Duration::from_secs(100));
-        sleep(Duration::from_secs(3));
-        assert_eq!(c.get(&2).unwrap().read(), 2);
+        let v = c
+            .entry(1)
+            .and_modify(2, |v| *v += 1)
+            .or_insert_with(1, Duration::ZERO, || unreachable!())
+            .unwrap();
+        assert_eq!(v.read(), 2);
+        c.wait().unwrap();
+        assert_eq!(c.policy.cost(&1), 2);
 
-        // Verify that entries with no expiration are overwritten.
-        retry_set(c.clone(), 3, 1, 1, Duration::ZERO);
-        retry_set(c.clone(), 3, 1, 1, Duration::from_secs(1));
-        sleep(Duration::from_secs(3));
-        assert!(c.get(&3).is_none());
-    }
+        assert!(c
+            .entry(2)
+            .and_modify(1, |_| unreachable!())
+            .or_default(1, Duration::ZERO)
+            .is_ok());
+        c.wait().unwrap();
+        assert_eq!(c.get(&2).unwrap().read(), 0);
 
-    #[test]
-    fn test_cache_remove() {
-        let c = new_test_cache(TransparentKeyBuilder::default());
+        // the policy rejects a value costlier than the cache, the entry insert is rolled back.
+        assert!(c.entry(4).or_insert(4, 100, Duration::ZERO).is_ok());
+        c.wait().unwrap();
+        assert!(c.get(&4).is_none());
+        assert!(!c.policy.contains(&4));
 
-        c.insert(1, 1, 1);
-        c.remove(&1);
+        match c.entry(2) {
+            Entry::Occupied(e) => assert_eq!(e.get(), &0),
+            Entry::Vacant(_) => panic!("expected an occupied entry"),
+        }
 
-        // The deletes and sets are pushed through the setbuf. It might be possible
-        // that the delete is not processed before the following get is called. So
-        // wait for a millisecond for things to be processed.
-        sleep(Duration::from_millis(1));
+        assert_eq!(c.entry(1).remove(), Some(2));
         assert!(c.get(&1).is_none());
+        assert_eq!(c.entry(3).remove(), None);
+        c.wait().unwrap();
+        assert!(!c.policy.contains(&1));
     }
 
     #[test]
-    fn test_cache_remove_with_ttl() {
+    fn test_cache_entry_and_modify_concurrent() {
         let c = Cache::builder(100, 10)
             .set_key_builder(TransparentKeyBuilder::default())
             .set_ignore_internal_cost(true)
             .finalize()
             .unwrap();
+        c.insert(1, 0, 1);
+        c.wait().unwrap();
 
-        retry_set(c.clone(), 3, 1, 1, Duration::from_secs(10));
-        sleep(Duration::from_secs(1));
-
-        // remove the item
-        c.remove(&3);
+        let handles = (0..8)
+            .map(|_| {
+                let c = c.clone();
+                spawn(move || {
+                    (0..100).for_each(|_| {
+                        c.entry(1).and_modify(1, |v| *v += 1);
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
 
-        // ensure the key is deleted
-        assert!(c.get(&3).is_none());
+        assert_eq!(c.get(&1).unwrap().read(), 800);
     }
 
     #[test]
-    fn test_cache_get_ttl() {
-        let c = Cache::builder(100, 10)
-            .set_key_builder(TransparentKeyBuilder::default())
-            .set_metrics(true)
+    fn test_cache_retain_keys() {
+        let c = Cache::builder(100, 100)
             .set_ignore_internal_cost(true)
+            .set_retain_keys(true)
             .finalize()
             .unwrap();
 
-        // try expiration with valid ttl item
-        {
-            let expiration = Duration::from_secs(5);
-            retry_set(c.clone(), 1, 1, 1, expiration);
+        (0..4).for_each(|i| {
+            assert!(c.insert(format!("key{}", i), i, 1));
+        });
+        assert!(c
+            .entry("key4".to_string())
+            .or_insert(4, 1, Duration::ZERO)
+            .is_ok());
+        assert!(c.insert_with_ttl("key5".to_string(), 5, 1, Duration::from_millis(100)));
+        c.wait().unwrap();
+        sleep(Duration::from_millis(200));
+        c.remove(&"key0".to_string());
 
-            assert_eq!(c.get(&1).unwrap().read(), 1);
-            assert!(c.get_ttl(&1).unwrap() < expiration);
+        let mut keys = c.keys().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["key1", "key2", "key3", "key4"]);
 
-            c.remove(&1);
+        let mut items = c.iter().collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(
+            items,
+            (1..5).map(|i| (format!("key{}", i), i)).collect::<Vec<_>>()
+        );
 
-            assert!(c.get_ttl(&1).is_none());
-        }
+        let mut sum = 0;
+        c.for_each(|k, v| {
+            assert_eq!(k, &format!("key{}", v));
+            sum += *v;
+        });
+        assert_eq!(sum, 10);
 
-        // try expiration with no ttl
-        {
-            retry_set(c.clone(), 2, 2, 1, Duration::ZERO);
-            assert_eq!(c.get(&2).unwrap().read(), 2);
-            assert_eq!(c.get_ttl(&2).unwrap(), Duration::MAX);
-        }
+        // without retained keys, there is nothing to iterate over.
+        let c: Cache<String, u64> = Cache::new(100, 100).unwrap();
+        c.insert("key".to_string(), 1, 1);
+        c.wait().unwrap();
+        assert_eq!(c.iter().count(), 0);
+    }
 
-        // try expiration with missing item
-        {
-            assert!(c.get_ttl(&3).is_none());
+    #[test]
+    fn test_cache_batch() {
+        let c = Cache::builder(1000, 1000)
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+
+        assert!(c.insert_many((0..100u64).map(|i| (i, i, 1))));
+        c.wait().unwrap();
+        for i in 0..100 {
+            assert_eq!(c.get(&i).unwrap().read(), i);
         }
 
-        // try expiration with expired item
-        {
-            let expiration = Duration::from_secs(1);
-            retry_set(c.clone(), 3, 3, 1, expiration);
+        // updates of existing keys are applied to the store right away.
+        assert!(c.insert_many((0..10u64).map(|i| (i, i * 10, 1))));
+        assert_eq!(c.get(&5).unwrap().read(), 50);
 
-            assert_eq!(c.get(&3).unwrap().read(), 3);
-            sleep(Duration::from_secs(1));
-            assert!(c.get_ttl(&3).is_none());
-        }
+        let vals = c.get_many(&[0, 42, 200]);
+        assert_eq!(vals, vec![Some(0), Some(42), None]);
+
+        c.remove_many(&[1, 2, 300]);
+        c.wait().unwrap();
+        assert_eq!(c.get_many(&[1, 2, 3]), vec![None, None, Some(30)]);
+        assert_eq!(c.len(), 98);
     }
 
     #[test]
-    fn test_cache_blockon_clear() {
-        let c: Cache<u64, u64, TransparentKeyBuilder<u64>> = Cache::builder(100, 10)
+    fn test_cache_insert_outcome_internal_cost() {
+        let c = Cache::builder(100, 10_000)
             .set_key_builder(TransparentKeyBuilder::default())
-            .set_ignore_internal_cost(true)
             .finalize()
             .unwrap();
+        let room = 10_000 - c.store.item_size() as i64;
 
-        let (stop_tx, stop_rx) = bounded(1);
-
-        let tc = c.clone();
-        spawn(move || {
-            for _ in 0..10 {
-                let tc = tc.clone();
-                tc.wait().unwrap();
-            }
-            stop_tx.send(()).unwrap();
-        });
-
-        for _ in 0..10 {
-            c.clear().unwrap();
-        }
-
-        select! {
-            recv(stop_rx) -> _  => {},
-            default(Duration::from_secs(1)) => {
-                panic!("timed out while waiting on cache")
-            }
-        }
+        assert_eq!(
+            c.insert_with_outcome(1, 1, room + 1, Duration::ZERO),
+            InsertOutcome::TooLarge
+        );
+        assert_eq!(
+            c.insert_and_wait(1, 1, room, Duration::ZERO),
+            InsertOutcome::Admitted
+        );
     }
 
     #[test]
-    fn test_cache_clear() {
+    fn test_cache_insert_outcome() {
         let c = Cache::builder(100, 10)
             .set_key_builder(TransparentKeyBuilder::default())
-            .set_metrics(true)
             .set_ignore_internal_cost(true)
+            .set_buffer_items(1)
             .finalize()
             .unwrap();
 
-        (0..10).for_each(|i| {
-            c.insert(i, i, 1);
+        assert_eq!(
+            c.insert_with_outcome(1, 1, 1, Duration::ZERO),
+            InsertOutcome::Queued
+        );
+        c.wait().unwrap();
+        assert_eq!(
+            c.insert_with_outcome(1, 2, 1, Duration::ZERO),
+            InsertOutcome::Updated
+        );
+        assert_eq!(
+            c.insert_with_outcome(2, 2, 11, Duration::ZERO),
+            InsertOutcome::TooLarge
+        );
+        // the plain insert leaves it to the policy.
+        assert!(c.insert(2, 2, 11));
+        assert_eq!(
+            c.insert_and_wait(3, 3, 8, Duration::ZERO),
+            InsertOutcome::Admitted
+        );
+        assert_eq!(c.get(&3).unwrap().read(), 3);
+
+        // make the keys in the cache hot, so that a cold key is rejected.
+        (0..10).for_each(|_| {
+            c.get(&1);
+            c.get(&3);
         });
-        sleep(Duration::from_millis(100));
-        assert_eq!(c.metrics.get_keys_added(), Some(10));
-        c.clear().unwrap();
-        assert_eq!(c.metrics.get_keys_added(), Some(0));
+        sleep(Duration::from_millis(10));
+        assert_eq!(
+            c.insert_and_wait(4, 4, 5, Duration::ZERO),
+            InsertOutcome::Rejected
+        );
+        assert!(c.get(&4).is_none());
 
-        (0..10).for_each(|i| {
-            assert!(c.get(&i).is_none());
-        })
+        c.close().unwrap();
+        assert_eq!(
+            c.insert_and_wait(5, 5, 1, Duration::ZERO),
+            InsertOutcome::Closed
+        );
     }
 
     #[test]
-    fn test_cache_metrics_clear() {
+    fn test_cache_take() {
         let c = Cache::builder(100, 10)
-            .set_key_builder(TransparentKeyBuilder::default())
-            .set_metrics(true)
+            .set_ignore_internal_cost(true)
+            .set_retain_keys(true)
             .finalize()
             .unwrap();
 
-        c.insert(1, 1, 1);
+        c.insert("a".to_string(), 1, 1);
+        c.insert("b".to_string(), 2, 1);
+        c.insert("c".to_string(), 3, 1);
+        c.wait().unwrap();
 
-        let (stop_tx, stop_rx) = bounded(0);
-        let tc = c.clone();
-        spawn(move || loop {
-            select! {
-                recv(stop_rx) -> _ => return,
-                default => {
-                    tc.get(&1);
-                }
-            }
-        });
+        // borrowed keys, like `get`.
+        assert_eq!(c.take("a"), Some(1));
+        assert_eq!(c.take("a"), None);
+        assert_eq!(c.remove_entry("b"), Some((Some("b".to_string()), 2)));
+        c.remove("c");
+        c.wait().unwrap();
+        assert!(c.get("c").is_none());
+        assert_eq!(c.len(), 0);
 
-        sleep(Duration::from_millis(100));
-        let _ = c.clear();
-        stop_tx.send(()).unwrap();
-        c.metrics.clear();
+        // without retained keys, only the value is returned.
+        let c: Cache<String, u64> = Cache::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+        c.insert("a".to_string(), 1, 1);
+        c.wait().unwrap();
+        assert_eq!(c.remove_entry("a"), Some((None, 1)));
     }
 
-    // Regression test for bug https://github.com/dgraph-io/ristretto/issues/167
     #[test]
-    fn test_cache_drop_updates() {
-        fn test() {
-            let set = Arc::new(Mutex::new(HashSet::new()));
-            let c = Cache::builder(100, 10)
-                .set_callback(TestCallbackDropUpdates { set: set.clone() })
-                .set_metrics(true)
-                // This is important. The race condition shows up only when the insert buf
-                // is full and that's why we reduce the buf size here. The test will
-                // try to fill up the insert buf to it's capacity and then perform an
-                // update on a key.
-                .set_buffer_size(10)
-                .finalize()
-                .unwrap();
+    fn test_cache_take_full_buffer() {
+        let gate = Arc::new(Mutex::new(()));
+        let c = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .set_buffer_size(1)
+            .set_cleanup_duration(Duration::from_millis(10))
+            .set_callback(GateCallback { gate: gate.clone() })
+            .finalize()
+            .unwrap();
+        assert_eq!(
+            c.insert_and_wait(1, 1, 1, Duration::ZERO),
+            InsertOutcome::Admitted
+        );
 
-            for i in 0..50 {
-                let v = format!("{:0100}", i);
-                // We're updating the same key.
-                if !c.insert(0, v, 1) {
-                    // The race condition doesn't show up without this sleep.
-                    sleep(Duration::from_millis(1));
-                    set.lock().insert(i);
-                }
-            }
+        // the processor blocks on the rejection of a too costly item, the buffer fills up.
+        let guard = gate.lock();
+        assert!(c.insert(2, 2, 100));
+        while c.insert(3, 3, 1) {}
 
-            // Wait for all the items to be processed.
-            sleep(Duration::from_millis(5));
-            // This will cause eviction from the cache.
-            assert!(c.insert(1, "0".to_string(), 10));
-            let _ = c.close();
-        }
+        // the removal does not wait for the processor, the deletion is left to the cleanup.
+        assert!(matches!(c.try_take(&1), Err(CacheError::ChannelError(_))));
+        assert!(c.get(&1).is_none());
+        drop(guard);
+        sleep(Duration::from_millis(100));
+        assert!(!c.policy.contains(&1));
+    }
 
-        // Run the test 100 times since it's not reliable.
-        (0..100).for_each(|_| test())
+    #[test]
+    fn test_cache_set_ttl() {
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+
+        assert!(c.insert_with_ttl(1, 1, 1, Duration::from_millis(100)));
+        assert!(c.insert(2, 2, 1));
+        c.wait().unwrap();
+
+        assert!(c.persist(&1));
+        assert_eq!(c.get_ttl(&1), Some(Duration::MAX));
+        assert!(c.set_ttl(&2, Duration::from_secs(10)));
+        assert!(c.get_ttl(&2).unwrap() > Duration::from_secs(9));
+        assert!(c.extend_ttl(&2, Duration::from_secs(10)));
+        assert!(c.get_ttl(&2).unwrap() > Duration::from_secs(19));
+        assert!(!c.set_ttl(&3, Duration::from_secs(1)));
+
+        // the value is replaced, the expiration is kept.
+        assert!(c.insert_keep_ttl(2, 20, 1));
+        assert_eq!(c.get(&2).unwrap().read(), 20);
+        assert!(c.get_ttl(&2).unwrap() > Duration::from_secs(19));
+
+        assert!(c.set_ttl(&1, Duration::from_millis(50)));
+        sleep(Duration::from_millis(100));
+        assert!(c.get(&1).is_none());
+        assert!(!c.persist(&1));
     }
 
     #[test]
-    fn test_cache_with_ttl() {
-        let mut process_win = 0;
-        let mut clean_win = 0;
+    fn test_cache_insert_at() {
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_callback(RejectCallback {
+                rejected: rejected.clone(),
+            })
+            .finalize()
+            .unwrap();
 
-        for _ in 0..10 {
-            let c = Cache::builder(100, 1000)
-                .set_key_builder(TransparentKeyBuilder::default())
-                .set_metrics(true)
-                .finalize()
-                .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert!(c.insert_at(1, 1, 1, deadline));
+        assert!(c.insert_at(2, 2, 1, SystemTime::now() + Duration::from_millis(100)));
+        // a past deadline is rejected, it is not charged to the policy.
+        assert!(!c.insert_at(3, 3, 1, SystemTime::now() - Duration::from_secs(1)));
+        assert!(c.insert(4, 4, 1));
+        c.wait().unwrap();
+        assert_eq!(*rejected.lock(), vec![3]);
+        assert!(!c.policy.contains(&3));
 
-            // Set initial value for key = 1
-            assert!(c.insert_with_ttl(1, 1, 0, Duration::from_millis(800)));
+        assert_eq!(c.expires_at(&1), Some(deadline));
+        assert!(c.get_ttl(&2).unwrap() <= Duration::from_millis(100));
+        assert!(c.get(&3).is_none());
+        assert_eq!(c.expires_at(&4), None);
+        assert_eq!(c.get_ttl(&4), Some(Duration::MAX));
 
-            sleep(Duration::from_millis(100));
+        assert!(c.expire_at(&4, deadline));
+        assert_eq!(c.expires_at(&4), Some(deadline));
+        assert!(c.expire_at(&1, Instant::now() + Duration::from_millis(50)));
+        assert!(!c.expire_at(&5, deadline));
 
-            // Get value from cache for key = 1
-            match c.get(&1) {
-                None => {
-                    clean_win += 1;
-                }
-                Some(_) => {
-                    process_win += 1;
-                }
-            }
-            // assert_eq!(c.get(&1).unwrap().read(), 1);
+        sleep(Duration::from_millis(150));
+        assert!(c.get(&1).is_none());
+        assert!(c.get(&2).is_none());
+        assert!(c.expires_at(&2).is_none());
+        assert_eq!(c.get(&4).unwrap().read(), 4);
+    }
 
-            sleep(Duration::from_millis(1200));
-            assert!(c.get(&1).is_none());
+    #[test]
+    fn test_cache_mock_clock() {
+        let clock = MockClock::new();
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_cleanup_duration(Duration::from_millis(10))
+            .set_clock(clock.clone())
+            .finalize()
+            .unwrap();
+
+        assert!(c.insert_with_ttl(1, 1, 1, Duration::from_secs(10)));
+        assert!(c.insert_with_ttl(2, 2, 1, Duration::from_secs(3600)));
+        assert!(c.insert_with_tti(3, 3, 1, Duration::from_secs(10)));
+        c.wait().unwrap();
+
+        // the time does not move on its own.
+        sleep(Duration::from_millis(50));
+        assert_eq!(c.get_ttl(&1), Some(Duration::from_secs(10)));
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(c.get(&3).unwrap().read(), 3);
+        clock.advance(Duration::from_secs(7));
+        assert!(c.get(&1).is_none());
+        assert_eq!(c.get(&3).unwrap().read(), 3);
+        assert_eq!(c.get_ttl(&2), Some(Duration::from_secs(3589)));
+
+        // the cleanup removes the expired items of all the seconds the clock jumped over.
+        clock.advance(Duration::from_secs(10));
+        sleep(Duration::from_millis(50));
+        assert_eq!(c.len(), 1);
+        assert_eq!(c.get(&2).unwrap().read(), 2);
+    }
+
+    #[test]
+    fn test_cache_expiration_resolution() {
+        let clock = MockClock::new();
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_cleanup_duration(Duration::from_millis(10))
+            .set_expiration_resolution(Duration::from_millis(10))
+            .set_clock(clock.clone())
+            .finalize()
+            .unwrap();
+
+        assert!(c.insert_with_ttl(1, 1, 1, Duration::from_millis(100)));
+        assert!(c.insert_with_ttl(2, 2, 1, Duration::from_millis(300)));
+        c.wait().unwrap();
+
+        // the expired items are reclaimed within a tick of their deadline, without being read.
+        clock.advance(Duration::from_millis(110));
+        sleep(Duration::from_millis(50));
+        assert_eq!(c.len(), 1);
+
+        clock.advance(Duration::from_millis(200));
+        sleep(Duration::from_millis(50));
+        assert_eq!(c.len(), 0);
+    }
+
+    #[test]
+    fn test_cache_run_pending_tasks() {
+        let clock = MockClock::new();
+        let c = Cache::<u64, u64>::builder(100, 3)
+            .set_ignore_internal_cost(true)
+            .set_clock(clock.clone())
+            .finalize()
+            .unwrap();
+
+        for i in 0..3 {
+            assert!(c.insert_with_ttl(i, i, 1, Duration::from_secs(1)));
         }
-        eprintln!("process: {} cleanup: {}", process_win, clean_win);
+        // the fourth item evicts one of the others.
+        assert!(c.insert(3, 3, 1));
+        c.remove(&3);
+        assert_eq!(
+            c.run_pending_tasks().unwrap(),
+            MaintenanceReport {
+                applied: 5,
+                evicted: 1,
+                expired: 0,
+            }
+        );
+        assert_eq!(c.len(), 2);
+
+        // the expired items are removed without waiting for the cleanup ticker.
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(
+            c.run_pending_tasks().unwrap(),
+            MaintenanceReport {
+                applied: 0,
+                evicted: 0,
+                expired: 2,
+            }
+        );
+        assert_eq!(c.len(), 0);
     }
 
     #[test]
-    fn test_valueref_ttl() {
-        let ttl = Duration::from_secs(1);
-        let c = Cache::builder(100, 1000)
+    fn test_cache_shrink_max_cost() {
+        let cb = Arc::new(Mutex::new(HashSet::new()));
+        let c = Cache::builder(100, 10)
             .set_key_builder(TransparentKeyBuilder::default())
+            .set_callback(TestCallback::new(cb.clone()))
+            .set_ignore_internal_cost(true)
             .set_metrics(true)
             .finalize()
             .unwrap();
-        c.try_insert_with_ttl(1, 1, 1, ttl).unwrap();
+
+        for i in 0..10 {
+            assert!(c.insert(i, i, 1));
+        }
         c.wait().unwrap();
-        let val = c.get(&1).unwrap();
-        assert!(val.ttl() > Duration::from_millis(900));
+        assert_eq!(c.len(), 10);
+
+        c.update_max_cost_and_wait(4).unwrap();
+        assert_eq!(c.max_cost(), 4);
+        assert_eq!(c.len(), 4);
+        assert_eq!(cb.lock().len(), 6);
+        assert_eq!(c.metrics.get_keys_evicted(), Some(6));
+        assert_eq!(c.metrics.get_cost_evicted(), Some(6));
+
+        // the plain update evicts through the processor too.
+        c.update_max_cost(2);
+        c.wait().unwrap();
+        assert_eq!(c.len(), 2);
+        assert_eq!(cb.lock().len(), 8);
+
+        // raising the budget evicts nothing.
+        c.update_max_cost_and_wait(10).unwrap();
+        assert_eq!(c.len(), 2);
     }
-}
 
-#[cfg(feature = "async")]
-mod async_test {
-    use super::*;
-    use crate::cache::axync::Item;
-    use crate::{
-        AsyncCache, AsyncCacheBuilder, CacheCallback, Coster, DefaultCacheCallback, DefaultCoster,
-        DefaultKeyBuilder, DefaultUpdateValidator, KeyBuilder, TransparentKeyBuilder,
-        UpdateValidator,
-    };
-    use parking_lot::Mutex;
-    use rand::rngs::OsRng;
-    use rand::Rng;
-    use std::collections::hash_map::RandomState;
-    use std::collections::HashSet;
-    use std::hash::Hash;
-    use std::sync::atomic::{AtomicU64, Ordering};
-    use std::sync::Arc;
-    use std::time::Duration;
-    use tokio::sync::mpsc::channel;
-    use tokio::task::spawn;
-    use tokio::time::sleep;
+    #[test]
+    fn test_cache_eviction_policy() {
+        let cb = Arc::new(Mutex::new(HashSet::new()));
+        let c = Cache::builder(100, 3)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_callback(TestCallback::new(cb.clone()))
+            .set_ignore_internal_cost(true)
+            .set_eviction_policy(Lru::new())
+            .finalize()
+            .unwrap();
 
-    async fn new_test_cache<K: Hash + Eq, V: Send + Sync + 'static, KH: KeyBuilder<Key = K>>(
-        kh: KH,
-    ) -> AsyncCache<K, V, KH> {
-        AsyncCache::new_with_key_builder(100, 10, kh, tokio::spawn).unwrap()
+        for i in 1..=3 {
+            assert!(c.insert(i, i, 1));
+        }
+        c.wait().unwrap();
+        assert!(c.get(&1).is_some());
+        c.run_pending_tasks().unwrap();
+
+        // 2 is the least recently used key.
+        assert!(c.insert(4, 4, 1));
+        c.wait().unwrap();
+        assert_eq!(cb.lock().iter().copied().collect::<Vec<_>>(), vec![2]);
+        assert!(c.get(&1).is_some());
+        assert!(c.get(&4).is_some());
     }
 
-    async fn retry_set<
-        C: Coster<Value = u64>,
-        U: UpdateValidator<Value = u64>,
-        CB: CacheCallback<Value = u64>,
-    >(
-        c: AsyncCache<u64, u64, TransparentKeyBuilder<u64>, C, U, CB>,
-        key: u64,
-        val: u64,
-        cost: i64,
-        ttl: Duration,
-    ) {
-        loop {
-            let insert = c.insert_with_ttl(key, val, cost, ttl).await;
-            if !insert {
-                sleep(Duration::from_millis(100)).await;
-                continue;
-            }
-            sleep(Duration::from_millis(100)).await;
-            assert_eq!(c.get(&key).unwrap().read(), val);
-            return;
+    #[test]
+    fn test_cache_window_tinylfu() {
+        let cb = Arc::new(Mutex::new(HashSet::new()));
+        let c = Cache::builder(100, 3)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_callback(TestCallback::new(cb.clone()))
+            .set_ignore_internal_cost(true)
+            .set_eviction_policy(WTinyLfu::new())
+            .finalize()
+            .unwrap();
+
+        for i in 1..=3 {
+            assert!(c.insert(i, i, 1));
         }
+        c.wait().unwrap();
+        for _ in 0..2 {
+            (1..=3).for_each(|i| assert!(c.get(&i).is_some()));
+        }
+        c.run_pending_tasks().unwrap();
+
+        // the new key is admitted into the window although it was never read.
+        assert!(c.insert(4, 4, 1));
+        c.wait().unwrap();
+        assert!(c.get(&4).is_some());
+        assert_eq!(c.len(), 3);
+        assert_eq!(cb.lock().len(), 1);
     }
 
-    #[tokio::test]
-    async fn test_cache_builder() {
-        let _: AsyncCache<u64, u64, DefaultKeyBuilder<u64>> =
-            AsyncCacheBuilder::new_with_key_builder(100, 10, TransparentKeyBuilder::default())
-                .set_coster(DefaultCoster::default())
-                .set_update_validator(DefaultUpdateValidator::default())
-                .set_callback(DefaultCacheCallback::default())
-                .set_num_counters(200)
-                .set_max_cost(100)
-                .set_cleanup_duration(Duration::from_secs(1))
-                .set_buffer_size(1000)
-                .set_key_builder(DefaultKeyBuilder::default())
-                .set_hasher(RandomState::default())
-                .finalize(tokio::spawn)
-                .unwrap();
+    #[test]
+    fn test_cache_admission() {
+        let c = Cache::builder(100, 3)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .set_metrics(true)
+            .finalize()
+            .unwrap();
+        for i in 1..=3 {
+            assert!(c.insert(i, i, 1));
+        }
+        c.wait().unwrap();
+        (1..=3).for_each(|i| assert!(c.get(&i).is_some()));
+        c.run_pending_tasks().unwrap();
+
+        // TinyLFU rejects a key never read, the cost above the max cost is rejected too.
+        assert!(c.insert(4, 4, 1));
+        assert!(c.insert(5, 5, 4));
+        c.wait().unwrap();
+        assert!(c.get(&4).is_none());
+        assert_eq!(c.metrics.get_sets_rejected_frequency(), Some(1));
+        assert_eq!(c.metrics.get_sets_rejected_cost(), Some(1));
+
+        let c = Cache::builder(100, 3)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .set_admission(Admission::Always)
+            .finalize()
+            .unwrap();
+        for i in 1..=3 {
+            assert!(c.insert(i, i, 1));
+        }
+        c.wait().unwrap();
+        (1..=3).for_each(|i| assert!(c.get(&i).is_some()));
+        c.run_pending_tasks().unwrap();
+
+        assert!(c.insert(4, 4, 1));
+        c.wait().unwrap();
+        assert!(c.get(&4).is_some());
+        assert_eq!(c.len(), 3);
+    }
+
+    #[cfg(feature = "serilization")]
+    #[test]
+    fn test_cache_snapshot() {
+        let clock = MockClock::new();
+        let c = Cache::<String, u64>::builder(100, 1000)
+            .set_retain_keys(true)
+            .set_clock(clock.clone())
+            .finalize()
+            .unwrap();
+        assert!(c.insert_with_ttl("a".to_string(), 1, 2, Duration::from_secs(10)));
+        assert!(c.insert_with_tti("b".to_string(), 2, 3, Duration::from_secs(5)));
+        assert!(c.insert("c".to_string(), 3, 4));
+        c.wait().unwrap();
+        clock.advance(Duration::from_secs(4));
+
+        let mut snapshot = Vec::new();
+        c.save_snapshot(&mut snapshot).unwrap();
+
+        let restored = Cache::<String, u64>::builder(100, 1000)
+            .set_retain_keys(true)
+            .set_clock(clock.clone())
+            .load_snapshot(snapshot.as_slice())
+            .unwrap();
+        assert_eq!(restored.len(), 3);
+        assert_eq!(restored.get("a").unwrap().read(), 1);
+        assert_eq!(restored.get_ttl("a"), Some(Duration::from_secs(6)));
+        assert_eq!(restored.get_ttl("b"), Some(Duration::from_secs(5)));
+        assert_eq!(restored.get_ttl("c"), Some(Duration::MAX));
+        for (key, cost) in [("a", 2), ("b", 3), ("c", 4)] {
+            let (index, _) = restored.key_to_hash.build_key(key);
+            assert_eq!(restored.policy.cost(&index), c.policy.cost(&index));
+            assert_eq!(
+                restored.policy.cost(&index),
+                cost + restored.store.item_size() as i64
+            );
+        }
+
+        let c = Cache::<String, u64>::builder(100, 1000).finalize().unwrap();
+        assert!(matches!(
+            c.save_snapshot(Vec::new()),
+            Err(CacheError::SnapshotError(_))
+        ));
+        let c = Cache::<String, u64>::builder(100, 1000)
+            .load_snapshot(br#"{"version":2,"entries":[]}"#.as_slice());
+        assert!(matches!(c, Err(CacheError::SnapshotError(_))));
+    }
+
+    #[cfg(feature = "serilization")]
+    #[test]
+    fn test_cache_journal() {
+        let path =
+            std::env::temp_dir().join(format!("stretto-cache-journal-sync-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let c = Cache::<String, u64>::builder(100, 1000)
+            .open_journal(&path)
+            .unwrap();
+        assert!(c.insert_with_ttl("a".to_string(), 1, 2, Duration::from_secs(60)));
+        assert!(c.insert("b".to_string(), 2, 3));
+        assert!(c.insert("c".to_string(), 3, 4));
+        c.wait().unwrap();
+        c.remove("c");
+        assert!(c.insert("b".to_string(), 4, 3));
+        c.wait().unwrap();
+        c.close().unwrap();
+
+        // a record torn by a crash.
+        let mut journal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut journal, &[16, 0, 0, 0, 1, 2, 3]).unwrap();
+
+        let c = Cache::<String, u64>::builder(100, 1000)
+            .open_journal(&path)
+            .unwrap();
+        assert_eq!(c.len(), 2);
+        assert_eq!(c.get("a").unwrap().read(), 1);
+        assert_eq!(c.get("b").unwrap().read(), 4);
+        assert!(c.get("c").is_none());
+        let ttl = c.get_ttl("a").unwrap();
+        assert!(ttl <= Duration::from_secs(60) && ttl > Duration::from_secs(50));
+        let (index, _) = c.key_to_hash.build_key("b");
+        assert_eq!(c.policy.cost(&index), 3 + c.store.item_size() as i64);
+
+        c.clear().unwrap();
+        c.wait().unwrap();
+        c.close().unwrap();
+        let c = Cache::<String, u64>::builder(100, 1000)
+            .open_journal(&path)
+            .unwrap();
+        assert!(c.is_empty());
+        c.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cache_warm() {
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_metrics(true)
+            .finalize()
+            .unwrap();
+        let report = c.warm((0..20).map(|i| (i, i, 1))).unwrap();
+        assert_eq!(
+            report,
+            WarmReport {
+                loaded: 10,
+                skipped: 10
+            }
+        );
+        assert_eq!(c.len(), 10);
+        assert_eq!(c.get(&9).unwrap().read(), 9);
+        assert!(c.get(&10).is_none());
+        assert_eq!(c.metrics.get_keys_added(), Some(10));
+
+        // the loaded keys are not replaced by new keys read less often.
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+        let report = c
+            .warm_with_frequency([(1, 1, 5, 3), (2, 2, 5, 3), (1, 3, 1, 3)])
+            .unwrap();
+        assert_eq!(report.loaded, 2);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(c.get(&1).unwrap().read(), 1);
+        assert!(c.insert(3, 3, 5));
+        c.wait().unwrap();
+        assert!(c.get(&3).is_none());
+        assert_eq!(c.len(), 2);
+    }
+
+    #[test]
+    fn test_cache_warm_reject() {
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_callback(RejectCallback {
+                rejected: rejected.clone(),
+            })
+            .finalize()
+            .unwrap();
+        // the key 1 is already loaded, the key 3 does not fit.
+        let report = c
+            .warm([(1, 1, 5), (2, 2, 5), (1, 3, 1), (3, 4, 5)])
+            .unwrap();
+        assert_eq!(report.loaded, 2);
+        assert_eq!(report.skipped, 2);
+        assert_eq!(*rejected.lock(), vec![3, 4]);
+        assert_eq!(c.get(&1).unwrap().read(), 1);
+        assert!(c.get(&3).is_none());
+        assert_eq!(c.len(), 2);
+    }
+
+    #[test]
+    fn test_cache_policy_state() {
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+        for i in 0..5 {
+            assert!(c.insert(i, i, 1));
+        }
+        c.wait().unwrap();
+        for _ in 0..3 {
+            assert!(c.get(&1).is_some());
+        }
+        let state = c.export_policy_state();
+
+        let restored = Cache::<u64, u64>::builder(100, 10).finalize().unwrap();
+        restored.import_policy_state(&state).unwrap();
+        assert_eq!(restored.export_policy_state(), state);
+        assert!(restored.get(&1).is_none());
+
+        let other = Cache::<u64, u64>::builder(1000, 10).finalize().unwrap();
+        assert!(matches!(
+            other.import_policy_state(&state),
+            Err(CacheError::InvalidPolicyState(_))
+        ));
+        assert!(matches!(
+            other.import_policy_state(b"garbage"),
+            Err(CacheError::InvalidPolicyState(_))
+        ));
+    }
+
+    #[test]
+    fn test_cache_policy_config() {
+        let config = PolicyConfig::new().set_sketch_depth(0);
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_policy_config(config)
+            .finalize();
+        assert!(matches!(c, Err(CacheError::InvalidSketchDepth(0))));
+
+        let config = PolicyConfig::new()
+            .set_samples(2)
+            .set_false_positive_ratio(0.001)
+            .set_reset_interval(1000)
+            .set_sketch_depth(8);
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_policy_config(config)
+            .finalize()
+            .unwrap();
+        assert!(c.insert(1, 1, 1));
+        c.wait().unwrap();
+        assert_eq!(c.get(&1).unwrap().read(), 1);
+    }
+
+    #[test]
+    fn test_cache_time_to_idle() {
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_time_to_idle(Duration::from_millis(300))
+            .finalize()
+            .unwrap();
+
+        assert!(c.insert(1, 1, 1));
+        assert!(c.insert_with_ttl(2, 2, 1, Duration::from_secs(10)));
+        assert!(c.insert_with_tti(3, 3, 1, Duration::ZERO));
+        c.wait().unwrap();
+
+        // every read resets the time-to-idle.
+        for _ in 0..4 {
+            sleep(Duration::from_millis(150));
+            assert_eq!(c.get(&1).unwrap().read(), 1);
+        }
+        assert!(c.get_ttl(&1).unwrap() > Duration::from_millis(200));
+
+        sleep(Duration::from_millis(400));
+        assert!(c.get(&1).is_none());
+        assert_eq!(c.get(&2).unwrap().read(), 2);
+        assert_eq!(c.get(&3).unwrap().read(), 3);
+    }
+
+    #[test]
+    fn test_cache_expiry() {
+        let c = Cache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_expiry(TestExpiry)
+            .finalize()
+            .unwrap();
+
+        assert!(c.insert(1, 300, 1));
+        assert!(c.insert(2, 10_000, 1));
+        assert!(c.insert_with_ttl(3, 0, 1, Duration::from_secs(10)));
+        c.wait().unwrap();
+        assert!(c.get_ttl(&1).unwrap() <= Duration::from_millis(300));
+        assert!(c.get_ttl(&3).unwrap() > Duration::from_secs(5));
+
+        // the update keeps the current TTL instead of the one of the new value.
+        assert!(c.insert(2, 100, 1));
+        c.wait().unwrap();
+        assert!(c.get_ttl(&2).unwrap() > Duration::from_secs(5));
+        assert_eq!(c.get(&2).unwrap().read(), 100);
+
+        // the read resets the TTL, `get_ttl` does not count as a read.
+        sleep(Duration::from_millis(150));
+        assert_eq!(c.get(&1).unwrap().read(), 300);
+        sleep(Duration::from_millis(225));
+        assert!(c.get_ttl(&1).is_some());
+        sleep(Duration::from_millis(175));
+        assert!(c.get(&1).is_none());
+    }
+
+    #[test]
+    fn test_cache_internal_cost() {
+        let c = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_metrics(true)
+            .finalize()
+            .unwrap();
+
+        // Get should return None because the cache's cost is too small to store the item
+        // when accounting for the internal cost.
+        c.insert_with_ttl(1, 1, 1, Duration::ZERO);
+        sleep(Duration::from_millis(100));
+        assert!(c.get(&1).is_none())
+    }
+
+    #[test]
+    fn test_recache_with_ttl() {
+        let c = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .set_metrics(true)
+            .finalize()
+            .unwrap();
+
+        // Set initial value for key = 1
+        assert!(c.insert_with_ttl(1, 1, 1, Duration::from_secs(5)));
+
+        sleep(Duration::from_secs(2));
+
+        // Get value from cache for key = 1
+        assert_eq!(c.get(&1).unwrap().read(), 1);
+
+        // wait for expiration
+        sleep(Duration::from_secs(5));
+
+        // The cached value for key = 1 should be gone
+        assert!(c.get(&1).is_none());
+
+        // set new value for key = 1
+        assert!(c.insert_with_ttl(1, 2, 1, Duration::from_secs(5)));
+
+        sleep(Duration::from_secs(2));
+        // get value from cache for key = 1;
+        assert_eq!(c.get(&1).unwrap().read(), 2);
+    }
+
+    #[test]
+    fn test_cache_set_with_ttl() {
+        let cb = Arc::new(Mutex::new(HashSet::new()));
+        let c = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_callback(TestCallback::new(cb.clone()))
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+
+        retry_set(c.clone(), 1, 1, 1, Duration::from_secs(1));
+
+        // Sleep to make sure the item has expired after execution resumes.
+        sleep(Duration::from_secs(2));
+        assert!(c.get(&1).is_none());
+
+        // Sleep to ensure that the bucket where the item was stored has been cleared
+        // from the expiration map.
+        sleep(Duration::from_secs(5));
+        assert_eq!(cb.lock().len(), 1);
+
+        // Verify that expiration times are overwritten.
+        retry_set(c.clone(), 2, 1, 1, Duration::from_secs(1));
+        retry_set(c.clone(), 2, 2, 1, Duration::from_secs(100));
+        sleep(Duration::from_secs(3));
+        assert_eq!(c.get(&2).unwrap().read(), 2);
+
+        // Verify that entries with no expiration are overwritten.
+        retry_set(c.clone(), 3, 1, 1, Duration::ZERO);
+        retry_set(c.clone(), 3, 1, 1, Duration::from_secs(1));
+        sleep(Duration::from_secs(3));
+        assert!(c.get(&3).is_none());
+    }
+
+    #[test]
+    fn test_cache_remove() {
+        let c = new_test_cache(TransparentKeyBuilder::default());
+
+        c.insert(1, 1, 1);
+        c.remove(&1);
+
+        // The deletes and sets are pushed through the setbuf. It might be possible
+        // that the delete is not processed before the following get is called. So
+        // wait for a millisecond for things to be processed.
+        sleep(Duration::from_millis(1));
+        assert!(c.get(&1).is_none());
+    }
+
+    #[test]
+    fn test_cache_remove_with_ttl() {
+        let c = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+
+        retry_set(c.clone(), 3, 1, 1, Duration::from_secs(10));
+        sleep(Duration::from_secs(1));
+
+        // remove the item
+        c.remove(&3);
+
+        // ensure the key is deleted
+        assert!(c.get(&3).is_none());
+    }
+
+    #[test]
+    fn test_cache_get_ttl() {
+        let c = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_metrics(true)
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+
+        // try expiration with valid ttl item
+        {
+            let expiration = Duration::from_secs(5);
+            retry_set(c.clone(), 1, 1, 1, expiration);
+
+            assert_eq!(c.get(&1).unwrap().read(), 1);
+            assert!(c.get_ttl(&1).unwrap() < expiration);
+
+            c.remove(&1);
+
+            assert!(c.get_ttl(&1).is_none());
+        }
+
+        // try expiration with no ttl
+        {
+            retry_set(c.clone(), 2, 2, 1, Duration::ZERO);
+            assert_eq!(c.get(&2).unwrap().read(), 2);
+            assert_eq!(c.get_ttl(&2).unwrap(), Duration::MAX);
+        }
+
+        // try expiration with missing item
+        {
+            assert!(c.get_ttl(&3).is_none());
+        }
+
+        // try expiration with expired item
+        {
+            let expiration = Duration::from_secs(1);
+            retry_set(c.clone(), 3, 3, 1, expiration);
+
+            assert_eq!(c.get(&3).unwrap().read(), 3);
+            sleep(Duration::from_secs(1));
+            assert!(c.get_ttl(&3).is_none());
+        }
+    }
+
+    #[test]
+    fn test_cache_blockon_clear() {
+        let c: Cache<u64, u64, TransparentKeyBuilder<u64>> = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+
+        let (stop_tx, stop_rx) = bounded(1);
+
+        let tc = c.clone();
+        spawn(move || {
+            for _ in 0..10 {
+                let tc = tc.clone();
+                tc.wait().unwrap();
+            }
+            stop_tx.send(()).unwrap();
+        });
+
+        for _ in 0..10 {
+            c.clear().unwrap();
+        }
+
+        select! {
+            recv(stop_rx) -> _  => {},
+            default(Duration::from_secs(1)) => {
+                panic!("timed out while waiting on cache")
+            }
+        }
+    }
+
+    #[test]
+    fn test_cache_clear() {
+        let c = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_metrics(true)
+            .set_ignore_internal_cost(true)
+            .finalize()
+            .unwrap();
+
+        (0..10).for_each(|i| {
+            c.insert(i, i, 1);
+        });
+        sleep(Duration::from_millis(100));
+        assert_eq!(c.metrics.get_keys_added(), Some(10));
+        c.clear().unwrap();
+        assert_eq!(c.metrics.get_keys_added(), Some(0));
+
+        (0..10).for_each(|i| {
+            assert!(c.get(&i).is_none());
+        })
+    }
+
+    #[test]
+    fn test_cache_metrics_clear() {
+        let c = Cache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_metrics(true)
+            .finalize()
+            .unwrap();
+
+        c.insert(1, 1, 1);
+
+        let (stop_tx, stop_rx) = bounded(0);
+        let tc = c.clone();
+        spawn(move || loop {
+            select! {
+                recv(stop_rx) -> _ => return,
+                default => {
+                    tc.get(&1);
+                }
+            }
+        });
+
+        sleep(Duration::from_millis(100));
+        let _ = c.clear();
+        stop_tx.send(()).unwrap();
+        c.metrics.clear();
+    }
+
+    // Regression test for bug https://github.com/dgraph-io/ristretto/issues/167
+    #[test]
+    fn test_cache_drop_updates() {
+        fn test() {
+            let set = Arc::new(Mutex::new(HashSet::new()));
+            let c = Cache::builder(100, 10)
+                .set_callback(TestCallbackDropUpdates { set: set.clone() })
+                .set_metrics(true)
+                // This is important. The race condition shows up only when the insert buf
+                // is full and that's why we reduce the buf size here. The test will
+                // try to fill up the insert buf to it's capacity and then perform an
+                // update on a key.
+                .set_buffer_size(10)
+                .finalize()
+                .unwrap();
+
+            for i in 0..50 {
+                let v = format!("{:0100}", i);
+                // We're updating the same key.
+                if !c.insert(0, v, 1) {
+                    // The race condition doesn't show up without this sleep.
+                    sleep(Duration::from_millis(1));
+                    set.lock().insert(i);
+                }
+            }
+
+            // Wait for all the items to be processed.
+            sleep(Duration::from_millis(5));
+            // This will cause eviction from the cache.
+            assert!(c.insert(1, "0".to_string(), 10));
+            let _ = c.close();
+        }
+
+        // Run the test 100 times since it's not reliable.
+        (0..100).for_each(|_| test())
+    }
+
+    #[test]
+    fn test_cache_with_ttl() {
+        let mut process_win = 0;
+        let mut clean_win = 0;
+
+        for _ in 0..10 {
+            let c = Cache::builder(100, 1000)
+                .set_key_builder(TransparentKeyBuilder::default())
+                .set_metrics(true)
+                .finalize()
+                .unwrap();
+
+            // Set initial value for key = 1
+            assert!(c.insert_with_ttl(1, 1, 0, Duration::from_millis(800)));
+
+            sleep(Duration::from_millis(100));
+
+            // Get value from cache for key = 1
+            match c.get(&1) {
+                None => {
+                    clean_win += 1;
+                }
+                Some(_) => {
+                    process_win += 1;
+                }
+            }
+            // assert_eq!(c.get(&1).unwrap().read(), 1);
+
+            sleep(Duration::from_millis(1200));
+            assert!(c.get(&1).is_none());
+        }
+        eprintln!("process: {} cleanup: {}", process_win, clean_win);
+    }
+
+    #[test]
+    fn test_valueref_ttl() {
+        let ttl = Duration::from_secs(1);
+        let c = Cache::builder(100, 1000)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_metrics(true)
+            .finalize()
+            .unwrap();
+        c.try_insert_with_ttl(1, 1, 1, ttl).unwrap();
+        c.wait().unwrap();
+        let val = c.get(&1).unwrap();
+        assert!(val.ttl() > Duration::from_millis(900));
+    }
+}
+
+#[cfg(feature = "async")]
+mod async_test {
+    use super::*;
+    use crate::cache::axync::Item;
+    use crate::{
+        AsyncCache, AsyncCacheBuilder, CacheCallback, CacheError, Coster, DefaultCacheCallback,
+        DefaultCoster, DefaultKeyBuilder, DefaultUpdateValidator, Entry, InsertOutcome, KeyBuilder,
+        TransparentKeyBuilder, UpdateValidator,
+    };
+    use parking_lot::Mutex;
+    use rand::rngs::OsRng;
+    use rand::Rng;
+    use std::collections::hash_map::RandomState;
+    use std::collections::HashSet;
+    use std::hash::Hash;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc::channel;
+    use tokio::task::spawn;
+    use tokio::time::sleep;
+
+    async fn new_test_cache<K: Hash + Eq, V: Send + Sync + 'static, KH: KeyBuilder<Key = K>>(
+        kh: KH,
+    ) -> AsyncCache<K, V, KH> {
+        AsyncCache::new_with_key_builder(100, 10, kh, tokio::spawn).unwrap()
+    }
+
+    async fn retry_set<
+        C: Coster<Value = u64>,
+        U: UpdateValidator<Value = u64>,
+        CB: CacheCallback<Value = u64>,
+    >(
+        c: AsyncCache<u64, u64, TransparentKeyBuilder<u64>, C, U, CB>,
+        key: u64,
+        val: u64,
+        cost: i64,
+        ttl: Duration,
+    ) {
+        loop {
+            let insert = c.insert_with_ttl(key, val, cost, ttl).await;
+            if !insert {
+                sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+            sleep(Duration::from_millis(100)).await;
+            assert_eq!(c.get(&key).unwrap().read(), val);
+            return;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_builder() {
+        let _: AsyncCache<u64, u64, DefaultKeyBuilder<u64>> =
+            AsyncCacheBuilder::new_with_key_builder(100, 10, TransparentKeyBuilder::default())
+                .set_coster(DefaultCoster::default())
+                .set_update_validator(DefaultUpdateValidator::default())
+                .set_callback(DefaultCacheCallback::default())
+                .set_num_counters(200)
+                .set_max_cost(100)
+                .set_cleanup_duration(Duration::from_secs(1))
+                .set_buffer_size(1000)
+                .set_buffer_items(32)
+                .set_key_builder(DefaultKeyBuilder::default())
+                .set_hasher(RandomState::default())
+                .finalize(tokio::spawn)
+                .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let max_cost = 10_000;
+        let lru = AsyncCacheBuilder::new(max_cost * 10, max_cost as i64)
+            .set_ignore_internal_cost(true)
+            .finalize(tokio::spawn)
+            .expect("failed to create cache");
+
+        for i in 0..10_000 {
+            println!("i = {i}, len before insert = {}", lru.len());
+
+            let value = 123;
+            let cost = 1;
+            lru.insert(i, value, cost).await;
+            lru.wait().await.unwrap(); // <-- freezes here
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_key_to_hash() {
+        let ctr = Arc::new(AtomicU64::new(0));
+
+        let c: AsyncCache<u64, u64, KHTest> =
+            AsyncCache::new_with_key_builder(10, 1000, KHTest { ctr: ctr.clone() }, tokio::spawn)
+                .unwrap();
+
+        assert!(c.insert(1, 1, 1).await);
+        sleep(Duration::from_millis(10)).await;
+
+        loop {
+            match c.get(&1) {
+                None => continue,
+                Some(val) => {
+                    assert_eq!(val.read(), 1);
+                    c.remove(&1).await;
+                    assert_eq!(3, ctr.load(Ordering::SeqCst));
+                    break;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_update_max_cost() {
+        let c = AsyncCache::builder(10, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .finalize(tokio::spawn)
+            .unwrap();
+
+        assert_eq!(c.max_cost(), 10);
+        assert!(c.insert(1, 1, 1).await);
+
+        sleep(Duration::from_secs(1)).await;
+        // Set is rejected because the cost of the entry is too high
+        // when accounting for the internal cost of storing the entry.
+        assert!(c.get(&1).is_none());
+
+        // Update the max cost of the cache and retry.
+        c.update_max_cost(1000);
+        assert_eq!(c.max_cost(), 1000);
+        assert!(c.insert(1, 1, 1).await);
+
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(c.get(&1).unwrap().read(), 1);
+        c.remove(&1).await;
+    }
+
+    #[tokio::test]
+    async fn test_cache_multiple_close() {
+        let c: AsyncCache<i64, i64, TransparentKeyBuilder<i64>> = AsyncCache::new_with_key_builder(
+            100,
+            10,
+            TransparentKeyBuilder::default(),
+            tokio::spawn,
+        )
+        .unwrap();
+
+        let _ = c.close().await;
+        let _ = c.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_cache_insert_after_close() {
+        let c = new_test_cache::<u64, u64, TransparentKeyBuilder<u64>>(
+            TransparentKeyBuilder::default(),
+        )
+        .await;
+        let _ = c.close().await;
+        assert!(!c.insert(1, 1, 1).await);
+    }
+
+    #[tokio::test]
+    async fn test_cache_clear_after_close() {
+        let c = new_test_cache::<u64, u64, TransparentKeyBuilder<u64>>(
+            TransparentKeyBuilder::default(),
+        )
+        .await;
+        let _ = c.close().await;
+        let _ = c.clear().await;
+    }
+
+    #[tokio::test]
+    async fn test_cache_get_after_close() {
+        let c = new_test_cache::<u64, u64, TransparentKeyBuilder<u64>>(
+            TransparentKeyBuilder::default(),
+        )
+        .await;
+        assert!(c.insert(1, 1, 1).await);
+        let _ = c.close().await;
+
+        assert!(c.get(&1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_remove_after_close() {
+        let c = new_test_cache::<u64, u64, TransparentKeyBuilder<u64>>(
+            TransparentKeyBuilder::default(),
+        )
+        .await;
+        assert!(c.insert(1, 1, 1).await);
+        let _ = c.close().await;
+
+        c.remove(&1).await;
+    }
+
+    #[tokio::test]
+    async fn test_cache_process_items() {
+        let cb = Arc::new(Mutex::new(HashSet::new()));
+        let c = AsyncCache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_coster(TestCoster::default())
+            .set_callback(TestCallback::new(cb.clone()))
+            .set_ignore_internal_cost(true)
+            .finalize(tokio::spawn)
+            .unwrap();
+
+        assert!(c.insert(1, 1, 0).await);
+
+        sleep(Duration::from_secs(1)).await;
+        assert!(c.policy.contains(&1));
+        assert_eq!(c.policy.cost(&1), 1);
+
+        let _ = c.insert_if_present(1, 2, 0).await;
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(c.policy.cost(&1), 2);
+
+        c.remove(&1).await;
+        sleep(Duration::from_secs(1)).await;
+        assert!(c.store.get(&1, 0).is_none());
+        assert!(!c.policy.contains(&1));
+
+        c.insert(2, 2, 3).await;
+        c.insert(3, 3, 3).await;
+        c.insert(4, 3, 3).await;
+        c.insert(5, 3, 5).await;
+        sleep(Duration::from_secs(1)).await;
+        assert_ne!(cb.lock().len(), 0);
+
+        let _ = c.close().await;
+        assert!(!c.insert(1, 1, 1).await);
+    }
+
+    #[tokio::test]
+    async fn test_cache_get() {
+        let c = AsyncCache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .set_metrics(true)
+            .finalize(tokio::spawn)
+            .unwrap();
+
+        c.insert(1, 1, 0).await;
+        sleep(Duration::from_secs(1)).await;
+        match c.get_mut(&1) {
+            None => {}
+            Some(mut val) => {
+                val.write(10);
+            }
+        }
+
+        assert!(c.get_mut(&2).is_none());
+
+        // 0.5 and not 1.0 because we tried Getting each item twice
+        assert_eq!(c.metrics.ratio().unwrap(), 0.5);
+
+        assert_eq!(c.get_mut(&1).unwrap().read(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_cache_get_buffer() {
+        let c: AsyncCache<u64, u64, TransparentKeyBuilder<u64>> = AsyncCache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_buffer_items(4)
+            .set_metrics(true)
+            .finalize(tokio::spawn)
+            .unwrap();
+
+        (0..8).for_each(|_| {
+            assert!(c.get(&1).is_none());
+        });
+        c.policy.flush();
+
+        assert_eq!(c.metrics.get_gets_kept().unwrap(), 8);
+        assert!(c.policy.inner.lock().admit.estimate(1) > 0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_invalid_buffer_items() {
+        let rst = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_buffer_items(0)
+            .finalize(tokio::spawn);
+        assert!(matches!(rst, Err(CacheError::InvalidBufferItems)));
+    }
+
+    #[tokio::test]
+    async fn test_cache_set() {
+        let c = AsyncCache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .set_metrics(true)
+            .finalize(tokio::spawn)
+            .unwrap();
+
+        retry_set(c.clone(), 1, 1, 1, Duration::ZERO).await;
+
+        c.insert(1, 2, 2).await;
+        assert_eq!(c.get(&1).unwrap().read(), 2);
+
+        assert!(c.stop_tx.send(()).await.is_ok());
+        for _ in 0..32768 {
+            let _ = c.insert_buf_tx.send(Item::update(1, 1, 0)).await;
+        }
+
+        assert!(!c.insert(2, 2, 1).await);
+        assert_eq!(c.metrics.get_sets_dropped().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_get_or_insert_with() {
+        let c = AsyncCache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+        let loads = Arc::new(AtomicU64::new(0));
+
+        let handles = (0..8)
+            .map(|_| {
+                let c = c.clone();
+                let loads = loads.clone();
+                spawn(async move {
+                    c.get_or_insert_with(1, 1, || async move {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        sleep(Duration::from_millis(100)).await;
+                        7
+                    })
+                    .await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for h in handles {
+            assert_eq!(h.await.unwrap(), 7);
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        c.wait().await.unwrap();
+        assert_eq!(c.get(&1).unwrap().read(), 7);
+        assert_eq!(
+            c.get_or_insert_with(1, 1, || async { unreachable!() })
+                .await,
+            7
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_try_get_or_insert_with() {
+        let c = AsyncCache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+
+        let rst = c
+            .try_get_or_insert_with(1, 1, || async {
+                Err(CacheError::SendError("fail to load".to_string()))
+            })
+            .await;
+        assert!(rst.is_err());
+        c.wait().await.unwrap();
+        assert!(c.get(&1).is_none());
+
+        let rst = c
+            .try_get_or_insert_with(1, 1, || async { Ok::<_, CacheError>(2) })
+            .await;
+        assert_eq!(rst.unwrap(), 2);
+        c.wait().await.unwrap();
+        assert_eq!(c.get(&1).unwrap().read(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cache_entry() {
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+
+        assert_eq!(
+            c.entry(1).or_insert(1, 1, Duration::ZERO).unwrap().read(),
+            1
+        );
+        // the value is in the store before the policy sees it.
+        assert_eq!(c.get(&1).unwrap().read(), 1);
+        assert!(matches!(c.entry(1), Entry::Occupied(_)));
+        c.wait().await.unwrap();
+        assert!(c.policy.contains(&1));
+
+        let v = c
+            .entry(1)
+            .and_modify(2, |v| *v += 1)
+            .or_insert_with(1, Duration::ZERO, || unreachable!())
+            .unwrap();
+        assert_eq!(v.read(), 2);
+        c.wait().await.unwrap();
+        assert_eq!(c.policy.cost(&1), 2);
+
+        assert!(c
+            .entry(2)
+            .and_modify(1, |_| unreachable!())
+            .or_default(1, Duration::ZERO)
+            .is_ok());
+        c.wait().await.unwrap();
+        assert_eq!(c.get(&2).unwrap().read(), 0);
+
+        // the policy rejects a value costlier than the cache, the entry insert is rolled back.
+        assert!(c.entry(4).or_insert(4, 100, Duration::ZERO).is_ok());
+        c.wait().await.unwrap();
+        assert!(c.get(&4).is_none());
+        assert!(!c.policy.contains(&4));
+
+        match c.entry(2) {
+            Entry::Occupied(e) => assert_eq!(e.get(), &0),
+            Entry::Vacant(_) => panic!("expected an occupied entry"),
+        }
+
+        assert_eq!(c.entry(1).remove(), Some(2));
+        assert!(c.get(&1).is_none());
+        assert_eq!(c.entry(3).remove(), None);
+        c.wait().await.unwrap();
+        assert!(!c.policy.contains(&1));
+    }
+
+    #[tokio::test]
+    async fn test_cache_retain_keys() {
+        use futures::StreamExt;
+
+        let c = AsyncCache::builder(100, 100)
+            .set_ignore_internal_cost(true)
+            .set_retain_keys(true)
+            .finalize(spawn)
+            .unwrap();
+
+        for i in 0..4 {
+            assert!(c.insert(format!("key{}", i), i, 1).await);
+        }
+        c.wait().await.unwrap();
+        c.remove(&"key0".to_string()).await;
+
+        let mut keys = c.keys().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["key1", "key2", "key3"]);
+
+        let mut items = c.stream().collect::<Vec<_>>().await;
+        items.sort();
+        assert_eq!(
+            items,
+            (1..4).map(|i| (format!("key{}", i), i)).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_batch() {
+        let c = AsyncCache::builder(1000, 1000)
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+
+        assert!(c.insert_many((0..100u64).map(|i| (i, i, 1))).await);
+        c.wait().await.unwrap();
+        for i in 0..100 {
+            assert_eq!(c.get(&i).unwrap().read(), i);
+        }
+
+        // updates of existing keys are applied to the store right away.
+        assert!(c.insert_many((0..10u64).map(|i| (i, i * 10, 1))).await);
+        assert_eq!(c.get(&5).unwrap().read(), 50);
+
+        let vals = c.get_many(&[0, 42, 200]);
+        assert_eq!(vals, vec![Some(0), Some(42), None]);
+
+        c.remove_many(&[1, 2, 300]).await;
+        c.wait().await.unwrap();
+        assert_eq!(c.get_many(&[1, 2, 3]), vec![None, None, Some(30)]);
+        assert_eq!(c.len(), 98);
+    }
+
+    #[tokio::test]
+    async fn test_cache_insert_outcome_internal_cost() {
+        let c = AsyncCache::builder(100, 10_000)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .finalize(spawn)
+            .unwrap();
+        let room = 10_000 - c.store.item_size() as i64;
+
+        assert_eq!(
+            c.insert_with_outcome(1, 1, room + 1, Duration::ZERO).await,
+            InsertOutcome::TooLarge
+        );
+        assert_eq!(
+            c.insert_and_wait(1, 1, room, Duration::ZERO).await,
+            InsertOutcome::Admitted
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_insert_outcome() {
+        let c = AsyncCache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .set_buffer_items(1)
+            .finalize(spawn)
+            .unwrap();
+
+        assert_eq!(
+            c.insert_with_outcome(1, 1, 1, Duration::ZERO).await,
+            InsertOutcome::Queued
+        );
+        c.wait().await.unwrap();
+        assert_eq!(
+            c.insert_with_outcome(1, 2, 1, Duration::ZERO).await,
+            InsertOutcome::Updated
+        );
+        assert_eq!(
+            c.insert_with_outcome(2, 2, 11, Duration::ZERO).await,
+            InsertOutcome::TooLarge
+        );
+        // the plain insert leaves it to the policy.
+        assert!(c.insert(2, 2, 11).await);
+        assert_eq!(
+            c.insert_and_wait(3, 3, 8, Duration::ZERO).await,
+            InsertOutcome::Admitted
+        );
+        assert_eq!(c.get(&3).unwrap().read(), 3);
+
+        // make the keys in the cache hot, so that a cold key is rejected.
+        for _ in 0..10 {
+            c.get(&1);
+            c.get(&3);
+        }
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            c.insert_and_wait(4, 4, 5, Duration::ZERO).await,
+            InsertOutcome::Rejected
+        );
+        assert!(c.get(&4).is_none());
+
+        c.close().await.unwrap();
+        assert_eq!(
+            c.insert_and_wait(5, 5, 1, Duration::ZERO).await,
+            InsertOutcome::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_take() {
+        let c = AsyncCache::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_retain_keys(true)
+            .finalize(spawn)
+            .unwrap();
+
+        c.insert("a".to_string(), 1, 1).await;
+        c.insert("b".to_string(), 2, 1).await;
+        c.insert("c".to_string(), 3, 1).await;
+        c.wait().await.unwrap();
+
+        // borrowed keys, like `get`.
+        assert_eq!(c.take("a").await, Some(1));
+        assert_eq!(c.take("a").await, None);
+        assert_eq!(c.remove_entry("b").await, Some((Some("b".to_string()), 2)));
+        c.remove("c").await;
+        c.wait().await.unwrap();
+        assert!(c.get("c").is_none());
+        assert_eq!(c.len(), 0);
+
+        // without retained keys, only the value is returned.
+        let c: AsyncCache<String, u64> = AsyncCache::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+        c.insert("a".to_string(), 1, 1).await;
+        c.wait().await.unwrap();
+        assert_eq!(c.remove_entry("a").await, Some((None, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_cache_set_ttl() {
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+
+        assert!(c.insert_with_ttl(1, 1, 1, Duration::from_millis(100)).await);
+        assert!(c.insert(2, 2, 1).await);
+        c.wait().await.unwrap();
+
+        assert!(c.persist(&1));
+        assert_eq!(c.get_ttl(&1), Some(Duration::MAX));
+        assert!(c.set_ttl(&2, Duration::from_secs(10)));
+        assert!(c.get_ttl(&2).unwrap() > Duration::from_secs(9));
+        assert!(c.extend_ttl(&2, Duration::from_secs(10)));
+        assert!(c.get_ttl(&2).unwrap() > Duration::from_secs(19));
+        assert!(!c.set_ttl(&3, Duration::from_secs(1)));
+
+        // the value is replaced, the expiration is kept.
+        assert!(c.insert_keep_ttl(2, 20, 1).await);
+        assert_eq!(c.get(&2).unwrap().read(), 20);
+        assert!(c.get_ttl(&2).unwrap() > Duration::from_secs(19));
+
+        assert!(c.set_ttl(&1, Duration::from_millis(50)));
+        sleep(Duration::from_millis(100)).await;
+        assert!(c.get(&1).is_none());
+        assert!(!c.persist(&1));
     }
 
     #[tokio::test]
-    async fn test_wait() {
-        let max_cost = 10_000;
-        let lru = AsyncCacheBuilder::new(max_cost * 10, max_cost as i64)
+    async fn test_cache_insert_at() {
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
             .set_ignore_internal_cost(true)
-            .finalize(tokio::spawn)
-            .expect("failed to create cache");
+            .set_callback(RejectCallback {
+                rejected: rejected.clone(),
+            })
+            .finalize(spawn)
+            .unwrap();
 
-        for i in 0..10_000 {
-            println!("i = {i}, len before insert = {}", lru.len());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert!(c.insert_at(1, 1, 1, deadline).await);
+        assert!(
+            c.insert_at(2, 2, 1, SystemTime::now() + Duration::from_millis(100))
+                .await
+        );
+        assert!(
+            !c.insert_at(3, 3, 1, SystemTime::now() - Duration::from_secs(1))
+                .await
+        );
+        assert!(c.insert(4, 4, 1).await);
+        c.wait().await.unwrap();
+        assert_eq!(*rejected.lock(), vec![3]);
 
-            let value = 123;
-            let cost = 1;
-            lru.insert(i, value, cost).await;
-            lru.wait().await.unwrap(); // <-- freezes here
-        }
+        assert_eq!(c.expires_at(&1), Some(deadline));
+        assert!(c.get_ttl(&2).unwrap() <= Duration::from_millis(100));
+        assert!(c.get(&3).is_none());
+        assert_eq!(c.expires_at(&4), None);
+        assert_eq!(c.get_ttl(&4), Some(Duration::MAX));
+
+        assert!(c.expire_at(&4, deadline));
+        assert_eq!(c.expires_at(&4), Some(deadline));
+        assert!(c.expire_at(&1, Instant::now() + Duration::from_millis(50)));
+        assert!(!c.expire_at(&5, deadline));
+
+        sleep(Duration::from_millis(150)).await;
+        assert!(c.get(&1).is_none());
+        assert!(c.get(&2).is_none());
+        assert!(c.expires_at(&2).is_none());
+        assert_eq!(c.get(&4).unwrap().read(), 4);
     }
 
     #[tokio::test]
-    async fn test_cache_key_to_hash() {
-        let ctr = Arc::new(AtomicU64::new(0));
+    async fn test_cache_mock_clock() {
+        let clock = MockClock::new();
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_cleanup_duration(Duration::from_millis(10))
+            .set_clock(clock.clone())
+            .finalize(spawn)
+            .unwrap();
 
-        let c: AsyncCache<u64, u64, KHTest> =
-            AsyncCache::new_with_key_builder(10, 1000, KHTest { ctr: ctr.clone() }, tokio::spawn)
-                .unwrap();
+        assert!(c.insert_with_ttl(1, 1, 1, Duration::from_secs(10)).await);
+        assert!(c.insert_with_ttl(2, 2, 1, Duration::from_secs(3600)).await);
+        assert!(c.insert_with_tti(3, 3, 1, Duration::from_secs(10)).await);
+        c.wait().await.unwrap();
 
-        assert!(c.insert(1, 1, 1).await);
-        sleep(Duration::from_millis(10)).await;
+        // the time does not move on its own.
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(c.get_ttl(&1), Some(Duration::from_secs(10)));
 
-        loop {
-            match c.get(&1) {
-                None => continue,
-                Some(val) => {
-                    assert_eq!(val.read(), 1);
-                    c.remove(&1).await;
-                    assert_eq!(3, ctr.load(Ordering::SeqCst));
-                    break;
-                }
-            }
-        }
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(c.get(&3).unwrap().read(), 3);
+        clock.advance(Duration::from_secs(7));
+        assert!(c.get(&1).is_none());
+        assert_eq!(c.get(&3).unwrap().read(), 3);
+        assert_eq!(c.get_ttl(&2), Some(Duration::from_secs(3589)));
+
+        // the cleanup removes the expired items of all the seconds the clock jumped over.
+        clock.advance(Duration::from_secs(10));
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(c.len(), 1);
+        assert_eq!(c.get(&2).unwrap().read(), 2);
     }
 
     #[tokio::test]
-    async fn test_cache_update_max_cost() {
-        let c = AsyncCache::builder(10, 10)
-            .set_key_builder(TransparentKeyBuilder::default())
-            .finalize(tokio::spawn)
+    async fn test_cache_expiration_resolution() {
+        let clock = MockClock::new();
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_cleanup_duration(Duration::from_millis(10))
+            .set_expiration_resolution(Duration::from_millis(10))
+            .set_clock(clock.clone())
+            .finalize(spawn)
             .unwrap();
 
-        assert_eq!(c.max_cost(), 10);
-        assert!(c.insert(1, 1, 1).await);
-
-        sleep(Duration::from_secs(1)).await;
-        // Set is rejected because the cost of the entry is too high
-        // when accounting for the internal cost of storing the entry.
-        assert!(c.get(&1).is_none());
+        assert!(c.insert_with_ttl(1, 1, 1, Duration::from_millis(100)).await);
+        assert!(c.insert_with_ttl(2, 2, 1, Duration::from_millis(300)).await);
+        c.wait().await.unwrap();
 
-        // Update the max cost of the cache and retry.
-        c.update_max_cost(1000);
-        assert_eq!(c.max_cost(), 1000);
-        assert!(c.insert(1, 1, 1).await);
+        // the expired items are reclaimed within a tick of their deadline, without being read.
+        clock.advance(Duration::from_millis(110));
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(c.len(), 1);
 
-        sleep(Duration::from_millis(200)).await;
-        assert_eq!(c.get(&1).unwrap().read(), 1);
-        c.remove(&1).await;
+        clock.advance(Duration::from_millis(200));
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(c.len(), 0);
     }
 
     #[tokio::test]
-    async fn test_cache_multiple_close() {
-        let c: AsyncCache<i64, i64, TransparentKeyBuilder<i64>> = AsyncCache::new_with_key_builder(
-            100,
-            10,
-            TransparentKeyBuilder::default(),
-            tokio::spawn,
-        )
-        .unwrap();
+    async fn test_cache_run_pending_tasks() {
+        let clock = MockClock::new();
+        let c = AsyncCache::<u64, u64>::builder(100, 3)
+            .set_ignore_internal_cost(true)
+            .set_clock(clock.clone())
+            .finalize(spawn)
+            .unwrap();
 
-        let _ = c.close().await;
-        let _ = c.close().await;
+        for i in 0..3 {
+            assert!(c.insert_with_ttl(i, i, 1, Duration::from_secs(1)).await);
+        }
+        // the fourth item evicts one of the others.
+        assert!(c.insert(3, 3, 1).await);
+        c.remove(&3).await;
+        assert_eq!(
+            c.run_pending_tasks().await.unwrap(),
+            MaintenanceReport {
+                applied: 5,
+                evicted: 1,
+                expired: 0,
+            }
+        );
+        assert_eq!(c.len(), 2);
+
+        // the expired items are removed without waiting for the cleanup ticker.
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(
+            c.run_pending_tasks().await.unwrap(),
+            MaintenanceReport {
+                applied: 0,
+                evicted: 0,
+                expired: 2,
+            }
+        );
+        assert_eq!(c.len(), 0);
     }
 
     #[tokio::test]
-    async fn test_cache_insert_after_close() {
-        let c = new_test_cache::<u64, u64, TransparentKeyBuilder<u64>>(
-            TransparentKeyBuilder::default(),
-        )
-        .await;
-        let _ = c.close().await;
-        assert!(!c.insert(1, 1, 1).await);
-    }
+    async fn test_cache_shrink_max_cost() {
+        let cb = Arc::new(Mutex::new(HashSet::new()));
+        let c = AsyncCache::builder(100, 10)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_callback(TestCallback::new(cb.clone()))
+            .set_ignore_internal_cost(true)
+            .set_metrics(true)
+            .finalize(spawn)
+            .unwrap();
 
-    #[tokio::test]
-    async fn test_cache_clear_after_close() {
-        let c = new_test_cache::<u64, u64, TransparentKeyBuilder<u64>>(
-            TransparentKeyBuilder::default(),
-        )
-        .await;
-        let _ = c.close().await;
-        let _ = c.clear();
+        for i in 0..10 {
+            assert!(c.insert(i, i, 1).await);
+        }
+        c.wait().await.unwrap();
+        assert_eq!(c.len(), 10);
+
+        c.update_max_cost_and_wait(4).await.unwrap();
+        assert_eq!(c.max_cost(), 4);
+        assert_eq!(c.len(), 4);
+        assert_eq!(cb.lock().len(), 6);
+        assert_eq!(c.metrics.get_keys_evicted(), Some(6));
+        assert_eq!(c.metrics.get_cost_evicted(), Some(6));
+
+        // the plain update evicts through the processor too.
+        c.update_max_cost(2);
+        c.wait().await.unwrap();
+        assert_eq!(c.len(), 2);
+        assert_eq!(cb.lock().len(), 8);
+
+        // raising the budget evicts nothing.
+        c.update_max_cost_and_wait(10).await.unwrap();
+        assert_eq!(c.len(), 2);
     }
 
     #[tokio::test]
-    async fn test_cache_get_after_close() {
-        let c = new_test_cache::<u64, u64, TransparentKeyBuilder<u64>>(
-            TransparentKeyBuilder::default(),
-        )
-        .await;
-        assert!(c.insert(1, 1, 1).await);
-        let _ = c.close().await;
+    async fn test_cache_eviction_policy() {
+        let cb = Arc::new(Mutex::new(HashSet::new()));
+        let c = AsyncCache::builder(100, 3)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_callback(TestCallback::new(cb.clone()))
+            .set_ignore_internal_cost(true)
+            .set_eviction_policy(Lru::new())
+            .finalize(spawn)
+            .unwrap();
 
-        assert!(c.get(&1).is_none());
+        for i in 1..=3 {
+            assert!(c.insert(i, i, 1).await);
+        }
+        c.wait().await.unwrap();
+        assert!(c.get(&1).is_some());
+        c.run_pending_tasks().await.unwrap();
+
+        // 2 is the least recently used key.
+        assert!(c.insert(4, 4, 1).await);
+        c.wait().await.unwrap();
+        assert_eq!(cb.lock().iter().copied().collect::<Vec<_>>(), vec![2]);
+        assert!(c.get(&1).is_some());
+        assert!(c.get(&4).is_some());
     }
 
     #[tokio::test]
-    async fn test_cache_remove_after_close() {
-        let c = new_test_cache::<u64, u64, TransparentKeyBuilder<u64>>(
-            TransparentKeyBuilder::default(),
-        )
-        .await;
-        assert!(c.insert(1, 1, 1).await);
-        let _ = c.close().await;
+    async fn test_cache_window_tinylfu() {
+        let cb = Arc::new(Mutex::new(HashSet::new()));
+        let c = AsyncCache::builder(100, 3)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_callback(TestCallback::new(cb.clone()))
+            .set_ignore_internal_cost(true)
+            .set_eviction_policy(WTinyLfu::new())
+            .finalize(spawn)
+            .unwrap();
 
-        c.remove(&1).await;
+        for i in 1..=3 {
+            assert!(c.insert(i, i, 1).await);
+        }
+        c.wait().await.unwrap();
+        for _ in 0..2 {
+            (1..=3).for_each(|i| assert!(c.get(&i).is_some()));
+        }
+        c.run_pending_tasks().await.unwrap();
+
+        // the new key is admitted into the window although it was never read.
+        assert!(c.insert(4, 4, 1).await);
+        c.wait().await.unwrap();
+        assert!(c.get(&4).is_some());
+        assert_eq!(c.len(), 3);
+        assert_eq!(cb.lock().len(), 1);
     }
 
     #[tokio::test]
-    async fn test_cache_process_items() {
-        let cb = Arc::new(Mutex::new(HashSet::new()));
-        let c = AsyncCache::builder(100, 10)
+    async fn test_cache_admission() {
+        let c = AsyncCache::builder(100, 3)
             .set_key_builder(TransparentKeyBuilder::default())
-            .set_coster(TestCoster::default())
-            .set_callback(TestCallback::new(cb.clone()))
             .set_ignore_internal_cost(true)
-            .finalize(tokio::spawn)
+            .set_metrics(true)
+            .finalize(spawn)
+            .unwrap();
+        for i in 1..=3 {
+            assert!(c.insert(i, i, 1).await);
+        }
+        c.wait().await.unwrap();
+        (1..=3).for_each(|i| assert!(c.get(&i).is_some()));
+        c.run_pending_tasks().await.unwrap();
+
+        // TinyLFU rejects a key never read, the cost above the max cost is rejected too.
+        assert!(c.insert(4, 4, 1).await);
+        assert!(c.insert(5, 5, 4).await);
+        c.wait().await.unwrap();
+        assert!(c.get(&4).is_none());
+        assert_eq!(c.metrics.get_sets_rejected_frequency(), Some(1));
+        assert_eq!(c.metrics.get_sets_rejected_cost(), Some(1));
+
+        let c = AsyncCache::builder(100, 3)
+            .set_key_builder(TransparentKeyBuilder::default())
+            .set_ignore_internal_cost(true)
+            .set_admission(Admission::Always)
+            .finalize(spawn)
             .unwrap();
+        for i in 1..=3 {
+            assert!(c.insert(i, i, 1).await);
+        }
+        c.wait().await.unwrap();
+        (1..=3).for_each(|i| assert!(c.get(&i).is_some()));
+        c.run_pending_tasks().await.unwrap();
+
+        assert!(c.insert(4, 4, 1).await);
+        c.wait().await.unwrap();
+        assert!(c.get(&4).is_some());
+        assert_eq!(c.len(), 3);
+    }
 
-        assert!(c.insert(1, 1, 0).await);
+    #[cfg(feature = "serilization")]
+    #[tokio::test]
+    async fn test_cache_snapshot() {
+        let clock = MockClock::new();
+        let c = AsyncCache::<String, u64>::builder(100, 1000)
+            .set_retain_keys(true)
+            .set_clock(clock.clone())
+            .finalize(spawn)
+            .unwrap();
+        assert!(
+            c.insert_with_ttl("a".to_string(), 1, 2, Duration::from_secs(10))
+                .await
+        );
+        assert!(
+            c.insert_with_tti("b".to_string(), 2, 3, Duration::from_secs(5))
+                .await
+        );
+        assert!(c.insert("c".to_string(), 3, 4).await);
+        c.wait().await.unwrap();
+        clock.advance(Duration::from_secs(4));
+
+        let mut snapshot = Vec::new();
+        c.save_snapshot(&mut snapshot).unwrap();
+
+        let restored = AsyncCache::<String, u64>::builder(100, 1000)
+            .set_retain_keys(true)
+            .set_clock(clock.clone())
+            .load_snapshot(snapshot.as_slice(), spawn)
+            .await
+            .unwrap();
+        assert_eq!(restored.len(), 3);
+        assert_eq!(restored.get("a").unwrap().read(), 1);
+        assert_eq!(restored.get_ttl("a"), Some(Duration::from_secs(6)));
+        assert_eq!(restored.get_ttl("b"), Some(Duration::from_secs(5)));
+        assert_eq!(restored.get_ttl("c"), Some(Duration::MAX));
+        for (key, cost) in [("a", 2), ("b", 3), ("c", 4)] {
+            let (index, _) = restored.key_to_hash.build_key(key);
+            assert_eq!(restored.policy.cost(&index), c.policy.cost(&index));
+            assert_eq!(
+                restored.policy.cost(&index),
+                cost + restored.store.item_size() as i64
+            );
+        }
 
-        sleep(Duration::from_secs(1)).await;
-        assert!(c.policy.contains(&1));
-        assert_eq!(c.policy.cost(&1), 1);
+        let c = AsyncCache::<String, u64>::builder(100, 1000)
+            .finalize(spawn)
+            .unwrap();
+        assert!(matches!(
+            c.save_snapshot(Vec::new()),
+            Err(CacheError::SnapshotError(_))
+        ));
+        let c = AsyncCache::<String, u64>::builder(100, 1000)
+            .load_snapshot(br#"{"version":2,"entries":[]}"#.as_slice(), spawn)
+            .await;
+        assert!(matches!(c, Err(CacheError::SnapshotError(_))));
+    }
 
-        let _ = c.insert_if_present(1, 2, 0).await;
-        sleep(Duration::from_secs(1)).await;
-        assert_eq!(c.policy.cost(&1), 2);
+    #[cfg(feature = "serilization")]
+    #[tokio::test]
+    async fn test_cache_journal() {
+        let path = std::env::temp_dir().join(format!(
+            "stretto-cache-journal-async-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let c = AsyncCache::<String, u64>::builder(100, 1000)
+            .open_journal(&path, spawn)
+            .await
+            .unwrap();
+        assert!(
+            c.insert_with_ttl("a".to_string(), 1, 2, Duration::from_secs(60))
+                .await
+        );
+        assert!(c.insert("b".to_string(), 2, 3).await);
+        assert!(c.insert("c".to_string(), 3, 4).await);
+        c.wait().await.unwrap();
+        c.remove("c").await;
+        assert!(c.insert("b".to_string(), 4, 3).await);
+        c.wait().await.unwrap();
+        c.close().await.unwrap();
+
+        // a record torn by a crash.
+        let mut journal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut journal, &[16, 0, 0, 0, 1, 2, 3]).unwrap();
 
-        c.remove(&1).await;
-        sleep(Duration::from_secs(1)).await;
-        assert!(c.store.get(&1, 0).is_none());
-        assert!(!c.policy.contains(&1));
+        let c = AsyncCache::<String, u64>::builder(100, 1000)
+            .open_journal(&path, spawn)
+            .await
+            .unwrap();
+        assert_eq!(c.len(), 2);
+        assert_eq!(c.get("a").unwrap().read(), 1);
+        assert_eq!(c.get("b").unwrap().read(), 4);
+        assert!(c.get("c").is_none());
+        let ttl = c.get_ttl("a").unwrap();
+        assert!(ttl <= Duration::from_secs(60) && ttl > Duration::from_secs(50));
 
-        c.insert(2, 2, 3).await;
-        c.insert(3, 3, 3).await;
-        c.insert(4, 3, 3).await;
-        c.insert(5, 3, 5).await;
-        sleep(Duration::from_secs(1)).await;
-        assert_ne!(cb.lock().len(), 0);
+        c.clear().await.unwrap();
+        c.wait().await.unwrap();
+        c.close().await.unwrap();
+        let c = AsyncCache::<String, u64>::builder(100, 1000)
+            .open_journal(&path, spawn)
+            .await
+            .unwrap();
+        assert!(c.is_empty());
+        c.close().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
 
-        let _ = c.close().await;
-        assert!(!c.insert(1, 1, 1).await);
+    #[tokio::test]
+    async fn test_cache_warm() {
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+        let report = c
+            .warm(futures::stream::iter((0..20).map(|i| (i, i, 1))))
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            WarmReport {
+                loaded: 10,
+                skipped: 10
+            }
+        );
+        assert_eq!(c.len(), 10);
+        assert_eq!(c.get(&9).unwrap().read(), 9);
+        assert!(c.get(&10).is_none());
+
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+        let report = c
+            .warm_with_frequency(futures::stream::iter([(1, 1, 5, 3), (2, 2, 5, 3)]))
+            .await
+            .unwrap();
+        assert_eq!(report.loaded, 2);
+        assert!(c.insert(3, 3, 5).await);
+        c.wait().await.unwrap();
+        assert!(c.get(&3).is_none());
+        assert_eq!(c.len(), 2);
     }
 
     #[tokio::test]
-    async fn test_cache_get() {
-        let c = AsyncCache::builder(100, 10)
-            .set_key_builder(TransparentKeyBuilder::default())
+    async fn test_cache_warm_reject() {
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
             .set_ignore_internal_cost(true)
-            .set_metrics(true)
-            .finalize(tokio::spawn)
+            .set_callback(RejectCallback {
+                rejected: rejected.clone(),
+            })
+            .finalize(spawn)
             .unwrap();
+        let report = c
+            .warm(futures::stream::iter([
+                (1, 1, 5),
+                (2, 2, 5),
+                (1, 3, 1),
+                (3, 4, 5),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(report.loaded, 2);
+        assert_eq!(report.skipped, 2);
+        assert_eq!(*rejected.lock(), vec![3, 4]);
+        assert!(c.get(&3).is_none());
+        assert_eq!(c.len(), 2);
+    }
 
-        c.insert(1, 1, 0).await;
-        sleep(Duration::from_secs(1)).await;
-        match c.get_mut(&1) {
-            None => {}
-            Some(mut val) => {
-                val.write(10);
-            }
+    #[tokio::test]
+    async fn test_cache_policy_state() {
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .finalize(spawn)
+            .unwrap();
+        for i in 0..5 {
+            assert!(c.insert(i, i, 1).await);
+        }
+        c.wait().await.unwrap();
+        for _ in 0..3 {
+            assert!(c.get(&1).is_some());
         }
+        let state = c.export_policy_state();
 
-        assert!(c.get_mut(&2).is_none());
+        let restored = AsyncCache::<u64, u64>::builder(100, 10)
+            .finalize(spawn)
+            .unwrap();
+        restored.import_policy_state(&state).unwrap();
+        assert_eq!(restored.export_policy_state(), state);
+        assert!(restored.get(&1).is_none());
 
-        // 0.5 and not 1.0 because we tried Getting each item twice
-        assert_eq!(c.metrics.ratio().unwrap(), 0.5);
+        let other = AsyncCache::<u64, u64>::builder(1000, 10)
+            .finalize(spawn)
+            .unwrap();
+        assert!(matches!(
+            other.import_policy_state(&state),
+            Err(CacheError::InvalidPolicyState(_))
+        ));
+        assert!(matches!(
+            other.import_policy_state(b"garbage"),
+            Err(CacheError::InvalidPolicyState(_))
+        ));
+    }
 
-        assert_eq!(c.get_mut(&1).unwrap().read(), 10);
+    #[tokio::test]
+    async fn test_cache_policy_config() {
+        let config = PolicyConfig::new().set_sketch_depth(0);
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_policy_config(config)
+            .finalize(spawn);
+        assert!(matches!(c, Err(CacheError::InvalidSketchDepth(0))));
+
+        let config = PolicyConfig::new()
+            .set_samples(2)
+            .set_false_positive_ratio(0.001)
+            .set_reset_interval(1000)
+            .set_sketch_depth(8);
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_policy_config(config)
+            .finalize(spawn)
+            .unwrap();
+        assert!(c.insert(1, 1, 1).await);
+        c.wait().await.unwrap();
+        assert_eq!(c.get(&1).unwrap().read(), 1);
     }
 
     #[tokio::test]
-    async fn test_cache_set() {
-        let c = AsyncCache::builder(100, 10)
-            .set_key_builder(TransparentKeyBuilder::default())
+    async fn test_cache_expiry() {
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
             .set_ignore_internal_cost(true)
-            .set_metrics(true)
-            .finalize(tokio::spawn)
+            .set_expiry(TestExpiry)
+            .finalize(spawn)
             .unwrap();
 
-        retry_set(c.clone(), 1, 1, 1, Duration::ZERO).await;
+        assert!(c.insert(1, 300, 1).await);
+        assert!(c.insert(2, 10_000, 1).await);
+        assert!(c.insert_with_ttl(3, 0, 1, Duration::from_secs(10)).await);
+        c.wait().await.unwrap();
+        assert!(c.get_ttl(&1).unwrap() <= Duration::from_millis(300));
+        assert!(c.get_ttl(&3).unwrap() > Duration::from_secs(5));
+
+        // the update keeps the current TTL instead of the one of the new value.
+        assert!(c.insert(2, 100, 1).await);
+        c.wait().await.unwrap();
+        assert!(c.get_ttl(&2).unwrap() > Duration::from_secs(5));
+        assert_eq!(c.get(&2).unwrap().read(), 100);
+
+        // the read resets the TTL, `get_ttl` does not count as a read.
+        sleep(Duration::from_millis(150)).await;
+        assert_eq!(c.get(&1).unwrap().read(), 300);
+        sleep(Duration::from_millis(225)).await;
+        assert!(c.get_ttl(&1).is_some());
+        sleep(Duration::from_millis(175)).await;
+        assert!(c.get(&1).is_none());
+    }
 
-        c.insert(1, 2, 2).await;
-        assert_eq!(c.get(&1).unwrap().read(), 2);
+    #[tokio::test]
+    async fn test_cache_time_to_idle() {
+        let c = AsyncCache::<u64, u64>::builder(100, 10)
+            .set_ignore_internal_cost(true)
+            .set_time_to_idle(Duration::from_millis(300))
+            .finalize(spawn)
+            .unwrap();
 
-        assert!(c.stop_tx.send(()).await.is_ok());
-        for _ in 0..32768 {
-            let _ = c.insert_buf_tx.send(Item::update(1, 1, 0)).await;
+        assert!(c.insert(1, 1, 1).await);
+        assert!(c.insert_with_ttl(2, 2, 1, Duration::from_secs(10)).await);
+        assert!(c.insert_with_tti(3, 3, 1, Duration::ZERO).await);
+        c.wait().await.unwrap();
+
+        // every read resets the time-to-idle.
+        for _ in 0..4 {
+            sleep(Duration::from_millis(150)).await;
+            assert_eq!(c.get(&1).unwrap().read(), 1);
         }
+        assert!(c.get_ttl(&1).unwrap() > Duration::from_millis(200));
 
-        assert!(!c.insert(2, 2, 1).await);
-        assert_eq!(c.metrics.get_sets_dropped().unwrap(), 1);
+        sleep(Duration::from_millis(400)).await;
+        assert!(c.get(&1).is_none());
+        assert_eq!(c.get(&2).unwrap().read(), 2);
+        assert_eq!(c.get(&3).unwrap().read(), 3);
     }
 
     #[tokio::test]
@@ -1216,7 +3083,7 @@ mod async_test {
         });
 
         sleep(Duration::from_millis(100)).await;
-        let _ = c.clear();
+        let _ = c.clear().await;
         stop_tx.send(()).await.unwrap();
         c.metrics.clear();
     }
@@ -1336,7 +3203,7 @@ mod async_test {
                     Err(_) => {
                         let k = get_key();
                         if c.get(&k).is_none() {
-                            let mut rng = OsRng::default();
+                            let mut rng = OsRng;
                             let rv = rng.gen::<usize>() % 100;
                             let val = if rv < 10 {
                                 "test".to_string()
@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The resolution of the [`DefaultClock`]: the processor of the Cache refreshes its time at
+/// this pace at the least.
+pub(crate) const COARSE_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// Clock is a trait you can pass to the CacheBuilder in order to change the source of time
+/// of the Cache. All the expirations (TTL, time-to-idle, deadlines and the cleanup of the
+/// expired items) and the eviction statistics are measured with it.
+///
+/// The time must be monotonic, it must never go backwards.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Refreshes the time of a clock which caches it. The processor of the Cache calls it
+    /// every millisecond, and at each item it handles. It does nothing by default.
+    fn refresh(&self) {}
+}
+
+/// DefaultClock is the default [`Clock`] of the Cache. It is monotonic and coarse: the time
+/// is cached, so that reading it is a single atomic load instead of a system call. There is
+/// no thread of its own keeping it up to date: the processor of the Cache refreshes it every
+/// millisecond, and at each item it handles. The clones of a DefaultClock share its time.
+///
+/// [`Clock`]: trait.Clock.html
+#[derive(Clone, Debug)]
+pub struct DefaultClock {
+    origin: Instant,
+    elapsed: Arc<AtomicU64>,
+}
+
+impl Default for DefaultClock {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+            elapsed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl DefaultClock {
+    /// Returns the default clock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Clock for DefaultClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        self.origin + Duration::from_nanos(self.elapsed.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    fn refresh(&self) {
+        let elapsed = self.origin.elapsed().as_nanos() as u64;
+        self.elapsed.fetch_max(elapsed, Ordering::Relaxed);
+    }
+}
+
+/// MockClock is a [`Clock`] which only moves when it is advanced by hand, to test the
+/// expiration of the values without sleeping. The clones of a MockClock share its time.
+///
+/// [`Clock`]: trait.Clock.html
+#[derive(Clone)]
+pub struct MockClock {
+    origin: Instant,
+    elapsed: Arc<AtomicU64>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+            elapsed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl MockClock {
+    /// Returns a mock clock, stopped at the current time.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward by `d`.
+    pub fn advance(&self, d: Duration) {
+        self.elapsed
+            .fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        self.origin + Duration::from_nanos(self.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_default_clock() {
+        let clock = DefaultClock::new();
+        let start = clock.now();
+        thread::sleep(Duration::from_millis(20));
+        // the time is cached until it is refreshed.
+        assert_eq!(clock.now(), start);
+
+        clock.clone().refresh();
+        let now = clock.now();
+        assert!(now >= start + Duration::from_millis(20));
+        assert!(now <= Instant::now());
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.now(), start);
+
+        clock.clone().advance(Duration::from_secs(10));
+        assert_eq!(clock.now(), start + Duration::from_secs(10));
+    }
+}
@@ -2,17 +2,29 @@
 #[derive(thiserror::Error, Debug)]
 pub enum CacheError {
     /// Count Min sketch with wrong width.
-    #[error("invalid number of samples: {0}")]
+    #[error("invalid count min sketch width: {0}")]
     InvalidCountMinWidth(u64),
 
-    /// Invalid Samples value for TinyLFU.
-    #[error("invalid count main sketch width: {0}")]
+    /// Count Min sketch with wrong depth.
+    #[error("invalid count min sketch depth: {0}, which should be in range [1, 16]")]
+    InvalidSketchDepth(usize),
+
+    /// Invalid Samples value for the SampledLfu.
+    #[error("invalid number of samples: {0}")]
     InvalidSamples(usize),
 
     /// Invalid false positive ratio for TinyLFU.
     #[error("invalid false positive ratio: {0}, which should be in range (0.0, 1.0)")]
     InvalidFalsePositiveRatio(f64),
 
+    /// Invalid reset interval for TinyLFU.
+    #[error("invalid reset interval: {0}")]
+    InvalidResetInterval(usize),
+
+    /// Invalid policy state, it is corrupted or was exported by an incompatible Cache.
+    #[error("invalid policy state: {0}")]
+    InvalidPolicyState(String),
+
     /// Invalid number of counters for the Cache.
     #[error("num_counters can't be zero")]
     InvalidNumCounters,
@@ -25,6 +37,10 @@ pub enum CacheError {
     #[error("buffer_size can't be zero")]
     InvalidBufferSize,
 
+    /// Invalid get buffer size for the Cache.
+    #[error("buffer_items can't be zero")]
+    InvalidBufferItems,
+
     /// Error when send msg between threads.
     #[error("fail to send msg to channel: {0}")]
     SendError(String),
@@ -49,6 +65,14 @@ pub enum CacheError {
     #[error("cleanup error: {0}")]
     CleanupError(String),
 
+    /// Error when saving or loading a snapshot of the Cache
+    #[error("snapshot error: {0}")]
+    SnapshotError(String),
+
+    /// Error when reading or writing the journal of the Cache
+    #[error("journal error: {0}")]
+    JournalError(String),
+
     /// Channel send error
     #[error("channel error: {0}")]
     ChannelError(String),
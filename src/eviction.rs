@@ -0,0 +1,1140 @@
+//! The eviction policies of the Cache.
+//!
+//! The Cache keeps the costs of the keys by itself, an [`EvictionPolicy`] only decides in
+//! which order the keys leave the Cache when it is full. Once a victim is chosen, the
+//! admission policy (TinyLFU) still compares its frequency with the one of the incoming key,
+//! so a new key which is less popular than the victim is rejected instead.
+//!
+//! [`EvictionPolicy`]: trait.EvictionPolicy.html
+use std::collections::HashMap;
+
+/// DEFAULT_SAMPLES is the number of items to sample when looking at eviction
+/// candidates. 5 seems to be the most optimal number [citation needed].
+pub(crate) const DEFAULT_SAMPLES: usize = 5;
+
+/// DEFAULT_PROTECTED_RATIO is the share of the keys kept in the protected segment of [`Slru`].
+const DEFAULT_PROTECTED_RATIO: f64 = 0.8;
+
+/// DEFAULT_SMALL_RATIO is the share of the keys kept in the small queue of [`S3Fifo`].
+const DEFAULT_SMALL_RATIO: f64 = 0.1;
+
+/// MAX_FREQUENCY caps the frequency counted by [`S3Fifo`].
+const MAX_FREQUENCY: u8 = 3;
+
+/// DEFAULT_WINDOW_RATIO is the initial share of the keys kept in the window of [`WTinyLfu`].
+const DEFAULT_WINDOW_RATIO: f64 = 0.01;
+
+/// MAX_WINDOW_RATIO is the largest share of the keys the hill climber gives to the window.
+const MAX_WINDOW_RATIO: f64 = 0.8;
+
+/// CLIMBER_STEP is the first step of the hill climber, as a share of the keys.
+const CLIMBER_STEP: f64 = 0.0625;
+
+/// CLIMBER_RESTART_THRESHOLD is the change of the hit rate which restarts the hill climber
+/// with a full step, e.g. when the workload changes.
+const CLIMBER_RESTART_THRESHOLD: f64 = 0.05;
+
+/// CLIMBER_STEP_DECAY is applied to the step of the hill climber after each sample, so that it
+/// converges.
+const CLIMBER_STEP_DECAY: f64 = 0.98;
+
+/// MIN_CLIMBER_SAMPLE is the fewest reads a sample of the hill climber is made of.
+const MIN_CLIMBER_SAMPLE: u64 = 128;
+
+/// EvictionPolicy is a trait you can pass to the CacheBuilder in order to change the order in
+/// which the keys are evicted. All the methods are called by the policy thread of the Cache,
+/// with the hashed keys.
+///
+/// The keys are inserted with [`insert`] and stay tracked until [`remove`] is called, even when
+/// they are returned by [`victim`]. The reads are batched and reported by [`access`] after they
+/// happened, some of them may be dropped under contention.
+///
+/// [`insert`]: trait.EvictionPolicy.html#tymethod.insert
+/// [`remove`]: trait.EvictionPolicy.html#tymethod.remove
+/// [`victim`]: trait.EvictionPolicy.html#tymethod.victim
+/// [`access`]: trait.EvictionPolicy.html#method.access
+pub trait EvictionPolicy: Send + 'static {
+    /// Tracks a new key.
+    fn insert(&mut self, key: u64, cost: i64);
+
+    /// Reports the new cost of a tracked key.
+    fn update(&mut self, _key: u64, _cost: i64) {}
+
+    /// Reports a read of a tracked key.
+    fn access(&mut self, _key: u64) {}
+
+    /// Reports a read of a key which is not tracked.
+    fn miss(&mut self, _key: u64) {}
+
+    /// Stops tracking a key, the key may be unknown.
+    fn remove(&mut self, key: u64);
+
+    /// Returns the next key to evict, or `None` if there is no key to evict.
+    ///
+    /// `keys` iterates over the keys of the Cache in an arbitrary order and `frequency`
+    /// estimates how often a key was read, for the policies which sample the keys.
+    fn victim(
+        &mut self,
+        keys: &mut dyn Iterator<Item = u64>,
+        frequency: &dyn Fn(u64) -> i64,
+    ) -> Option<u64>;
+
+    /// Stops tracking all the keys.
+    fn clear(&mut self);
+
+    /// Returns true if the policy decides the admission by itself, with the frequencies given
+    /// to [`victim`]. The Cache then admits all the new keys and evicts the victims without
+    /// comparing their frequencies with the ones of the new keys.
+    ///
+    /// [`victim`]: trait.EvictionPolicy.html#tymethod.victim
+    fn handles_admission(&self) -> bool {
+        false
+    }
+}
+
+/// SampledLfu is the default [`EvictionPolicy`] of the Cache. It samples a few keys and
+/// evicts the one with the lowest estimated frequency, on par with exact LRU and better on
+/// Search and Database traces.
+///
+/// [`EvictionPolicy`]: trait.EvictionPolicy.html
+pub struct SampledLfu {
+    samples: usize,
+    pool: Vec<u64>,
+}
+
+impl Default for SampledLfu {
+    fn default() -> Self {
+        Self::with_samples(DEFAULT_SAMPLES)
+    }
+}
+
+impl SampledLfu {
+    /// Returns a SampledLfu which samples 5 keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a SampledLfu which samples `samples` keys, at least one.
+    pub fn with_samples(samples: usize) -> Self {
+        let samples = samples.max(1);
+        Self {
+            samples,
+            pool: Vec::with_capacity(samples),
+        }
+    }
+}
+
+impl EvictionPolicy for SampledLfu {
+    #[inline]
+    fn insert(&mut self, _key: u64, _cost: i64) {}
+
+    #[inline]
+    fn remove(&mut self, key: u64) {
+        if let Some(idx) = self.pool.iter().position(|k| *k == key) {
+            self.pool.swap_remove(idx);
+        }
+    }
+
+    fn victim(
+        &mut self,
+        keys: &mut dyn Iterator<Item = u64>,
+        frequency: &dyn Fn(u64) -> i64,
+    ) -> Option<u64> {
+        // fill up empty slots in the pool
+        for key in keys {
+            if self.pool.len() >= self.samples {
+                break;
+            }
+            if !self.pool.contains(&key) {
+                self.pool.push(key);
+            }
+        }
+
+        // find minimally used item in the pool
+        self.pool.iter().copied().min_by_key(|k| frequency(*k))
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.pool.clear();
+    }
+}
+
+/// Lru is an [`EvictionPolicy`] which evicts the least recently used key.
+///
+/// [`EvictionPolicy`]: trait.EvictionPolicy.html
+#[derive(Default)]
+pub struct Lru {
+    list: List,
+    index: HashMap<u64, usize>,
+}
+
+impl Lru {
+    /// Returns an empty Lru.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EvictionPolicy for Lru {
+    fn insert(&mut self, key: u64, _cost: i64) {
+        match self.index.get(&key) {
+            Some(idx) => self.list.move_to_front(*idx),
+            None => {
+                let idx = self.list.push_front(key);
+                self.index.insert(key, idx);
+            }
+        }
+    }
+
+    #[inline]
+    fn access(&mut self, key: u64) {
+        if let Some(idx) = self.index.get(&key) {
+            self.list.move_to_front(*idx);
+        }
+    }
+
+    #[inline]
+    fn remove(&mut self, key: u64) {
+        if let Some(idx) = self.index.remove(&key) {
+            self.list.unlink(idx);
+        }
+    }
+
+    #[inline]
+    fn victim(
+        &mut self,
+        _keys: &mut dyn Iterator<Item = u64>,
+        _frequency: &dyn Fn(u64) -> i64,
+    ) -> Option<u64> {
+        self.list.back().map(|idx| self.list.key(idx))
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.list.clear();
+        self.index.clear();
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Probation,
+    Protected,
+}
+
+/// Slru is the segmented LRU [`EvictionPolicy`]. The new keys enter a probation segment and
+/// move to a protected segment once they are read again, the victims are taken from the
+/// probation segment first. When the protected segment is full, its least recently used key
+/// is moved back to the probation segment.
+///
+/// [`EvictionPolicy`]: trait.EvictionPolicy.html
+pub struct Slru {
+    probation: List,
+    protected: List,
+    index: HashMap<u64, (Segment, usize)>,
+    protected_ratio: f64,
+}
+
+impl Default for Slru {
+    fn default() -> Self {
+        Self::with_protected_ratio(DEFAULT_PROTECTED_RATIO)
+    }
+}
+
+impl Slru {
+    /// Returns an empty Slru which protects up to 80% of the keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an empty Slru which protects up to `ratio` of the keys, clamped to `0.0..=1.0`.
+    pub fn with_protected_ratio(ratio: f64) -> Self {
+        Self {
+            probation: List::default(),
+            protected: List::default(),
+            index: HashMap::new(),
+            protected_ratio: ratio.clamp(0.0, 1.0),
+        }
+    }
+
+    #[inline]
+    fn segment(&mut self, segment: Segment) -> &mut List {
+        match segment {
+            Segment::Probation => &mut self.probation,
+            Segment::Protected => &mut self.protected,
+        }
+    }
+}
+
+impl EvictionPolicy for Slru {
+    fn insert(&mut self, key: u64, _cost: i64) {
+        if self.index.contains_key(&key) {
+            return;
+        }
+        let idx = self.probation.push_front(key);
+        self.index.insert(key, (Segment::Probation, idx));
+    }
+
+    fn access(&mut self, key: u64) {
+        let (segment, idx) = match self.index.get(&key) {
+            Some(pos) => *pos,
+            None => return,
+        };
+        if segment == Segment::Protected {
+            return self.protected.move_to_front(idx);
+        }
+
+        self.probation.unlink(idx);
+        let idx = self.protected.push_front(key);
+        self.index.insert(key, (Segment::Protected, idx));
+
+        // demote the least recently used protected key if the segment is full.
+        let max_protected = ((self.index.len() as f64 * self.protected_ratio) as usize).max(1);
+        if self.protected.len() > max_protected {
+            if let Some(idx) = self.protected.back() {
+                let demoted = self.protected.unlink(idx);
+                let idx = self.probation.push_front(demoted);
+                self.index.insert(demoted, (Segment::Probation, idx));
+            }
+        }
+    }
+
+    #[inline]
+    fn remove(&mut self, key: u64) {
+        if let Some((segment, idx)) = self.index.remove(&key) {
+            self.segment(segment).unlink(idx);
+        }
+    }
+
+    #[inline]
+    fn victim(
+        &mut self,
+        _keys: &mut dyn Iterator<Item = u64>,
+        _frequency: &dyn Fn(u64) -> i64,
+    ) -> Option<u64> {
+        self.probation
+            .back()
+            .map(|idx| self.probation.key(idx))
+            .or_else(|| self.protected.back().map(|idx| self.protected.key(idx)))
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.probation.clear();
+        self.protected.clear();
+        self.index.clear();
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Queue {
+    Small,
+    Main,
+}
+
+/// S3Fifo is the [S3-FIFO] [`EvictionPolicy`]. The new keys enter a small FIFO queue, the keys
+/// read while they are in it move to the main FIFO queue, the others are evicted and
+/// remembered by a ghost queue. The keys found in the ghost queue are inserted straight into
+/// the main queue. The main queue reinserts the keys read since they were last examined.
+///
+/// [S3-FIFO]: https://dl.acm.org/doi/10.1145/3600006.3613147
+/// [`EvictionPolicy`]: trait.EvictionPolicy.html
+pub struct S3Fifo {
+    small: List,
+    main: List,
+    ghost: List,
+    index: HashMap<u64, (Queue, usize, u8)>,
+    ghost_index: HashMap<u64, usize>,
+    small_ratio: f64,
+    last_victim: Option<u64>,
+}
+
+impl Default for S3Fifo {
+    fn default() -> Self {
+        Self::with_small_ratio(DEFAULT_SMALL_RATIO)
+    }
+}
+
+impl S3Fifo {
+    /// Returns an empty S3Fifo whose small queue holds 10% of the keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an empty S3Fifo whose small queue holds `ratio` of the keys, clamped to
+    /// `0.0..=1.0`.
+    pub fn with_small_ratio(ratio: f64) -> Self {
+        Self {
+            small: List::default(),
+            main: List::default(),
+            ghost: List::default(),
+            index: HashMap::new(),
+            ghost_index: HashMap::new(),
+            small_ratio: ratio.clamp(0.0, 1.0),
+            last_victim: None,
+        }
+    }
+
+    /// remember puts an evicted key into the ghost queue, which holds as many keys as the
+    /// main queue.
+    fn remember(&mut self, key: u64) {
+        let idx = self.ghost.push_front(key);
+        self.ghost_index.insert(key, idx);
+        while self.ghost.len() > self.main.len().max(1) {
+            if let Some(idx) = self.ghost.back() {
+                let forgotten = self.ghost.unlink(idx);
+                self.ghost_index.remove(&forgotten);
+            }
+        }
+    }
+}
+
+impl EvictionPolicy for S3Fifo {
+    fn insert(&mut self, key: u64, _cost: i64) {
+        if self.index.contains_key(&key) {
+            return;
+        }
+        match self.ghost_index.remove(&key) {
+            Some(idx) => {
+                self.ghost.unlink(idx);
+                let idx = self.main.push_front(key);
+                self.index.insert(key, (Queue::Main, idx, 0));
+            }
+            None => {
+                let idx = self.small.push_front(key);
+                self.index.insert(key, (Queue::Small, idx, 0));
+            }
+        }
+    }
+
+    #[inline]
+    fn access(&mut self, key: u64) {
+        if let Some((_, _, freq)) = self.index.get_mut(&key) {
+            *freq = (*freq + 1).min(MAX_FREQUENCY);
+        }
+    }
+
+    fn remove(&mut self, key: u64) {
+        if let Some((queue, idx, _)) = self.index.remove(&key) {
+            match queue {
+                Queue::Small => self.small.unlink(idx),
+                Queue::Main => self.main.unlink(idx),
+            };
+            // only the evicted keys go to the ghost queue, not the deleted ones.
+            if queue == Queue::Small && self.last_victim == Some(key) {
+                self.last_victim = None;
+                self.remember(key);
+            }
+        }
+    }
+
+    fn victim(
+        &mut self,
+        _keys: &mut dyn Iterator<Item = u64>,
+        _frequency: &dyn Fn(u64) -> i64,
+    ) -> Option<u64> {
+        // a victim which the admission kept is not evicted, forget it.
+        self.last_victim = None;
+        loop {
+            let small_full = self.small.len() as f64 >= self.index.len() as f64 * self.small_ratio;
+            if let Some(idx) = self
+                .small
+                .back()
+                .filter(|_| small_full || self.main.len() == 0)
+            {
+                let key = self.small.key(idx);
+                let (_, _, freq) = self.index[&key];
+                if freq == 0 {
+                    self.last_victim = Some(key);
+                    return Some(key);
+                }
+
+                // the key was read since it was inserted, promote it to the main queue.
+                self.small.unlink(idx);
+                let idx = self.main.push_front(key);
+                self.index.insert(key, (Queue::Main, idx, 0));
+                continue;
+            }
+
+            let idx = self.main.back()?;
+            let key = self.main.key(idx);
+            let entry = self.index.get_mut(&key)?;
+            if entry.2 == 0 {
+                self.last_victim = Some(key);
+                return Some(key);
+            }
+
+            // the key was read since it was last examined, reinsert it.
+            entry.2 -= 1;
+            self.main.move_to_front(idx);
+        }
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.small.clear();
+        self.main.clear();
+        self.ghost.clear();
+        self.index.clear();
+        self.ghost_index.clear();
+        self.last_victim = None;
+    }
+}
+
+/// Sieve is the [SIEVE] [`EvictionPolicy`]. The keys are kept in insertion order with a
+/// visited bit set by the reads. A hand moves from the oldest key to the newest one, clears
+/// the visited bits it meets, and evicts the first key which was not visited.
+///
+/// [SIEVE]: https://www.usenix.org/conference/nsdi24/presentation/zhang-yazhuo
+/// [`EvictionPolicy`]: trait.EvictionPolicy.html
+#[derive(Default)]
+pub struct Sieve {
+    list: List,
+    index: HashMap<u64, (usize, bool)>,
+    hand: Option<usize>,
+}
+
+impl Sieve {
+    /// Returns an empty Sieve.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EvictionPolicy for Sieve {
+    fn insert(&mut self, key: u64, _cost: i64) {
+        if self.index.contains_key(&key) {
+            return;
+        }
+        let idx = self.list.push_front(key);
+        self.index.insert(key, (idx, false));
+    }
+
+    #[inline]
+    fn access(&mut self, key: u64) {
+        if let Some((_, visited)) = self.index.get_mut(&key) {
+            *visited = true;
+        }
+    }
+
+    fn remove(&mut self, key: u64) {
+        if let Some((idx, _)) = self.index.remove(&key) {
+            if self.hand == Some(idx) {
+                self.hand = self.list.prev(idx);
+            }
+            self.list.unlink(idx);
+        }
+    }
+
+    fn victim(
+        &mut self,
+        _keys: &mut dyn Iterator<Item = u64>,
+        _frequency: &dyn Fn(u64) -> i64,
+    ) -> Option<u64> {
+        let mut idx = self.hand.or_else(|| self.list.back())?;
+        loop {
+            let key = self.list.key(idx);
+            let (_, visited) = self.index.get_mut(&key)?;
+            if !*visited {
+                self.hand = Some(idx);
+                return Some(key);
+            }
+            *visited = false;
+            idx = self.list.prev(idx).or_else(|| self.list.back())?;
+        }
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.list.clear();
+        self.index.clear();
+        self.hand = None;
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Region {
+    Window,
+    Probation,
+    Protected,
+}
+
+/// WTinyLfu is the [Window TinyLFU] [`EvictionPolicy`] of Caffeine. The new keys are always
+/// admitted into a small LRU window. The keys leaving the window are candidates for a segmented
+/// LRU main region, where they compete with its victim: the one TinyLFU estimates as the less
+/// frequently used is evicted. The window lets a newly popular key stay in the Cache long enough
+/// to build up its frequency, instead of losing against stale frequent keys.
+///
+/// A hill climber samples the hit rate and grows or shrinks the window, starting at 1% of the
+/// keys, toward the size which gives the best hit rate for the workload.
+///
+/// The window and the main region are sized by their number of keys, not by their cost: with
+/// very uneven costs, the window may hold much more or much less than its share of the cost.
+///
+/// [Window TinyLFU]: https://arxiv.org/pdf/1512.00727.pdf
+/// [`EvictionPolicy`]: trait.EvictionPolicy.html
+pub struct WTinyLfu {
+    window: List,
+    probation: List,
+    protected: List,
+    index: HashMap<u64, (Region, usize)>,
+    /// candidates are the keys which left the window and were not compared with a victim of
+    /// the main region yet, the most recent last.
+    candidates: Vec<u64>,
+    window_ratio: f64,
+    climber: HillClimber,
+}
+
+impl Default for WTinyLfu {
+    fn default() -> Self {
+        Self::with_window_ratio(DEFAULT_WINDOW_RATIO)
+    }
+}
+
+impl WTinyLfu {
+    /// Returns an empty WTinyLfu whose window starts at 1% of the keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an empty WTinyLfu whose window starts at `ratio` of the keys, clamped to
+    /// `0.0..=0.8`.
+    pub fn with_window_ratio(ratio: f64) -> Self {
+        Self {
+            window: List::default(),
+            probation: List::default(),
+            protected: List::default(),
+            index: HashMap::new(),
+            candidates: Vec::new(),
+            window_ratio: ratio.clamp(0.0, MAX_WINDOW_RATIO),
+            climber: HillClimber::default(),
+        }
+    }
+
+    /// Returns the share of the keys currently given to the window.
+    pub fn window_ratio(&self) -> f64 {
+        self.window_ratio
+    }
+
+    #[inline]
+    fn segment(&mut self, region: Region) -> &mut List {
+        match region {
+            Region::Window => &mut self.window,
+            Region::Probation => &mut self.probation,
+            Region::Protected => &mut self.protected,
+        }
+    }
+
+    #[inline]
+    fn relink(&mut self, key: u64, from: (Region, usize), to: Region) {
+        self.segment(from.0).unlink(from.1);
+        let idx = self.segment(to).push_front(key);
+        self.index.insert(key, (to, idx));
+    }
+
+    fn climb(&mut self) {
+        let sample = (10 * self.index.len() as u64).max(MIN_CLIMBER_SAMPLE);
+        if let Some(adjustment) = self.climber.adjust(sample) {
+            self.window_ratio = (self.window_ratio + adjustment).clamp(0.0, MAX_WINDOW_RATIO);
+        }
+    }
+}
+
+impl EvictionPolicy for WTinyLfu {
+    fn insert(&mut self, key: u64, _cost: i64) {
+        if self.index.contains_key(&key) {
+            return;
+        }
+        let idx = self.window.push_front(key);
+        self.index.insert(key, (Region::Window, idx));
+    }
+
+    fn access(&mut self, key: u64) {
+        self.climber.hits += 1;
+        self.climb();
+
+        let pos = match self.index.get(&key) {
+            Some(pos) => *pos,
+            None => return,
+        };
+        match pos.0 {
+            Region::Window => self.window.move_to_front(pos.1),
+            Region::Protected => self.protected.move_to_front(pos.1),
+            Region::Probation => {
+                self.relink(key, pos, Region::Protected);
+
+                // demote the least recently used protected key if the segment is full.
+                let main = self.probation.len() + self.protected.len();
+                let max_protected = ((main as f64 * DEFAULT_PROTECTED_RATIO) as usize).max(1);
+                if self.protected.len() > max_protected {
+                    if let Some(idx) = self.protected.back() {
+                        let demoted = self.protected.key(idx);
+                        self.relink(demoted, (Region::Protected, idx), Region::Probation);
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn miss(&mut self, _key: u64) {
+        self.climber.misses += 1;
+        self.climb();
+    }
+
+    #[inline]
+    fn remove(&mut self, key: u64) {
+        if let Some((region, idx)) = self.index.remove(&key) {
+            self.segment(region).unlink(idx);
+        }
+    }
+
+    fn victim(
+        &mut self,
+        _keys: &mut dyn Iterator<Item = u64>,
+        frequency: &dyn Fn(u64) -> i64,
+    ) -> Option<u64> {
+        // the keys beyond the size of the window move to the main region, each of them is a
+        // candidate, compared with a victim by a call, the most recent first, as by Caffeine.
+        let max_window = ((self.index.len() as f64 * self.window_ratio) as usize).max(1);
+        while self.window.len() > max_window {
+            let idx = self.window.back()?;
+            let key = self.window.key(idx);
+            self.relink(key, (Region::Window, idx), Region::Probation);
+            self.candidates.push(key);
+        }
+
+        // the candidates removed or promoted since they left the window are skipped, and
+        // dropped at once if they pile up.
+        if self.candidates.len() > self.index.len() {
+            let index = &self.index;
+            self.candidates
+                .retain(|key| matches!(index.get(key), Some((Region::Probation, _))));
+        }
+        let mut candidate = None;
+        while let Some(key) = self.candidates.pop() {
+            if matches!(self.index.get(&key), Some((Region::Probation, _))) {
+                candidate = Some(key);
+                break;
+            }
+        }
+
+        let main_victim = self
+            .probation
+            .back()
+            .map(|idx| self.probation.key(idx))
+            .filter(|key| Some(*key) != candidate)
+            .or_else(|| self.protected.back().map(|idx| self.protected.key(idx)));
+
+        match (candidate, main_victim) {
+            // the candidate is only admitted if it is more frequently used than the victim.
+            (Some(candidate), Some(victim)) if frequency(candidate) > frequency(victim) => {
+                Some(victim)
+            }
+            (Some(candidate), _) => Some(candidate),
+            (None, Some(victim)) => Some(victim),
+            (None, None) => self.window.back().map(|idx| self.window.key(idx)),
+        }
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.window.clear();
+        self.probation.clear();
+        self.protected.clear();
+        self.index.clear();
+        self.candidates.clear();
+        self.climber = HillClimber::default();
+    }
+
+    #[inline]
+    fn handles_admission(&self) -> bool {
+        true
+    }
+}
+
+/// HillClimber samples the hit rate and returns how much the window of [`WTinyLfu`] should
+/// move: in the same direction while the hit rate improves, in the other one otherwise.
+struct HillClimber {
+    hits: u64,
+    misses: u64,
+    previous_hit_rate: f64,
+    step: f64,
+}
+
+impl Default for HillClimber {
+    fn default() -> Self {
+        Self {
+            hits: 0,
+            misses: 0,
+            previous_hit_rate: 0.0,
+            step: CLIMBER_STEP,
+        }
+    }
+}
+
+impl HillClimber {
+    /// Returns the adjustment of the window once `sample` reads were seen.
+    fn adjust(&mut self, sample: u64) -> Option<f64> {
+        let reads = self.hits + self.misses;
+        if reads < sample {
+            return None;
+        }
+
+        let hit_rate = self.hits as f64 / reads as f64;
+        let change = hit_rate - self.previous_hit_rate;
+        let amount = if change >= 0.0 { self.step } else { -self.step };
+        self.step = if change.abs() >= CLIMBER_RESTART_THRESHOLD {
+            CLIMBER_STEP.copysign(amount)
+        } else {
+            amount * CLIMBER_STEP_DECAY
+        };
+
+        self.previous_hit_rate = hit_rate;
+        self.hits = 0;
+        self.misses = 0;
+        Some(amount)
+    }
+}
+
+const NIL: usize = usize::MAX;
+
+struct Node {
+    key: u64,
+    prev: usize,
+    next: usize,
+}
+
+/// List is a doubly linked list of keys whose nodes live in a vector, from the front (the
+/// newest key) to the back (the oldest key). The indexes of the nodes stay valid until they
+/// are unlinked.
+struct List {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+    len: usize,
+}
+
+impl Default for List {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            len: 0,
+        }
+    }
+}
+
+impl List {
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn key(&self, idx: usize) -> u64 {
+        self.nodes[idx].key
+    }
+
+    #[inline]
+    fn back(&self) -> Option<usize> {
+        (self.tail != NIL).then_some(self.tail)
+    }
+
+    /// Returns the node in front of `idx`, which is newer.
+    #[inline]
+    fn prev(&self, idx: usize) -> Option<usize> {
+        let prev = self.nodes[idx].prev;
+        (prev != NIL).then_some(prev)
+    }
+
+    fn push_front(&mut self, key: u64) -> usize {
+        let node = Node {
+            key,
+            prev: NIL,
+            next: NIL,
+        };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = node;
+                idx
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.link_front(idx);
+        self.len += 1;
+        idx
+    }
+
+    fn unlink(&mut self, idx: usize) -> u64 {
+        self.detach(idx);
+        self.free.push(idx);
+        self.len -= 1;
+        self.nodes[idx].key
+    }
+
+    fn move_to_front(&mut self, idx: usize) {
+        if self.head != idx {
+            self.detach(idx);
+            self.link_front(idx);
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn link_front(&mut self, idx: usize) {
+        self.nodes[idx].prev = NIL;
+        self.nodes[idx].next = self.head;
+        match self.head {
+            NIL => self.tail = idx,
+            head => self.nodes[head].prev = idx,
+        }
+        self.head = idx;
+    }
+
+    fn detach(&mut self, idx: usize) {
+        let (prev, next) = (self.nodes[idx].prev, self.nodes[idx].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn victim(policy: &mut dyn EvictionPolicy) -> Option<u64> {
+        policy.victim(&mut std::iter::empty(), &|_| 0)
+    }
+
+    /// evict takes the victims one by one, as the Cache does.
+    fn evict(policy: &mut dyn EvictionPolicy, n: usize) -> Vec<u64> {
+        (0..n)
+            .filter_map(|_| {
+                let key = victim(policy)?;
+                policy.remove(key);
+                Some(key)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_list() {
+        let mut list = List::default();
+        let a = list.push_front(1);
+        let b = list.push_front(2);
+        let c = list.push_front(3);
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.back(), Some(a));
+        assert_eq!(list.prev(a), Some(b));
+        assert_eq!(list.prev(c), None);
+
+        list.move_to_front(a);
+        assert_eq!(list.back(), Some(b));
+        assert_eq!(list.unlink(b), 2);
+        assert_eq!(list.key(list.back().unwrap()), 3);
+
+        // the freed node is reused.
+        assert_eq!(list.push_front(4), b);
+        list.clear();
+        assert_eq!(list.len(), 0);
+        assert_eq!(list.back(), None);
+    }
+
+    #[test]
+    fn test_sampled_lfu() {
+        let mut lfu = SampledLfu::with_samples(3);
+        let freq = |k: u64| [5, 1, 3, 0, 4][k as usize];
+        let mut keys = 0..5;
+        assert_eq!(lfu.victim(&mut keys, &freq), Some(1));
+        assert_eq!(lfu.pool, vec![0, 1, 2]);
+
+        // the pool is refilled after a removal, the sampled keys are not duplicated.
+        lfu.remove(1);
+        let mut keys = [0, 2, 3, 4].into_iter();
+        assert_eq!(lfu.victim(&mut keys, &freq), Some(3));
+        assert_eq!(lfu.pool.len(), 3);
+        lfu.clear();
+        assert_eq!(lfu.victim(&mut std::iter::empty(), &freq), None);
+    }
+
+    #[test]
+    fn test_lru() {
+        let mut lru = Lru::new();
+        (1..=4).for_each(|k| lru.insert(k, 1));
+        lru.access(1);
+        lru.access(3);
+        lru.access(10);
+        assert_eq!(evict(&mut lru, 5), vec![2, 4, 1, 3]);
+
+        (1..=2).for_each(|k| lru.insert(k, 1));
+        lru.clear();
+        assert_eq!(victim(&mut lru), None);
+    }
+
+    #[test]
+    fn test_slru() {
+        let mut slru = Slru::with_protected_ratio(0.5);
+        (1..=4).for_each(|k| slru.insert(k, 1));
+        slru.access(1);
+        slru.access(2);
+        assert_eq!(slru.protected.len(), 2);
+
+        // 3 is promoted, 1 is the least recently used protected key and is demoted.
+        slru.access(3);
+        assert_eq!(slru.protected.len(), 2);
+        assert_eq!(slru.index[&1].0, Segment::Probation);
+        assert_eq!(evict(&mut slru, 4), vec![4, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_s3fifo() {
+        let mut s3 = S3Fifo::with_small_ratio(0.5);
+        (1..=4).for_each(|k| s3.insert(k, 1));
+        s3.access(2);
+
+        // 1 is evicted from the small queue and remembered, 2 was read and moves to main.
+        assert_eq!(victim(&mut s3), Some(1));
+        s3.remove(1);
+        assert!(s3.ghost_index.contains_key(&1));
+        assert_eq!(victim(&mut s3), Some(3));
+        assert_eq!(s3.index[&2].0, Queue::Main);
+
+        // a deleted key is not remembered, a remembered key goes straight to main.
+        s3.remove(4);
+        assert!(!s3.ghost_index.contains_key(&4));
+        s3.insert(1, 1);
+        assert_eq!(s3.index[&1].0, Queue::Main);
+
+        s3.clear();
+        assert_eq!(victim(&mut s3), None);
+    }
+
+    #[test]
+    fn test_s3fifo_pending_victim() {
+        let mut s3 = S3Fifo::with_small_ratio(0.5);
+        (1..=4).for_each(|k| s3.insert(k, 1));
+
+        // deleting another key does not forget the victim which is about to be evicted.
+        assert_eq!(victim(&mut s3), Some(1));
+        s3.remove(2);
+        s3.remove(1);
+        assert!(s3.ghost_index.contains_key(&1));
+        assert!(!s3.ghost_index.contains_key(&2));
+        assert_eq!(s3.last_victim, None);
+    }
+
+    #[test]
+    fn test_s3fifo_main_reinsertion() {
+        let mut s3 = S3Fifo::with_small_ratio(0.0);
+        (1..=3).for_each(|k| s3.insert(k, 1));
+        (1..=3).for_each(|k| s3.access(k));
+
+        // the keys read in the small queue move to main with their frequency reset.
+        assert_eq!(victim(&mut s3), Some(1));
+        assert_eq!(s3.main.len(), 3);
+
+        // 1 is read again and reinserted, so 2 is evicted first.
+        s3.access(1);
+        assert_eq!(evict(&mut s3, 3), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn test_sieve() {
+        let mut sieve = Sieve::new();
+        (1..=5).for_each(|k| sieve.insert(k, 1));
+        sieve.access(1);
+        sieve.access(3);
+
+        // the hand skips 1 and clears its bit, then stops on 2.
+        assert_eq!(victim(&mut sieve), Some(2));
+        sieve.remove(2);
+        assert_eq!(sieve.hand, Some(sieve.index[&3].0));
+        assert_eq!(evict(&mut sieve, 2), vec![4, 5]);
+
+        // the hand wraps around to the oldest key.
+        sieve.insert(6, 1);
+        sieve.access(3);
+        assert_eq!(evict(&mut sieve, 3), vec![1, 6, 3]);
+        sieve.clear();
+        assert_eq!(victim(&mut sieve), None);
+    }
+
+    #[test]
+    fn test_wtinylfu() {
+        let mut w = WTinyLfu::with_window_ratio(0.25);
+        (1..=8).for_each(|k| w.insert(k, 1));
+        let freq = |k: u64| if k == 6 { 5 } else { 1 };
+
+        // 1 to 6 leave the window, 6 is the candidate and beats the main victim.
+        assert_eq!(w.victim(&mut std::iter::empty(), &freq), Some(1));
+        w.remove(1);
+        assert_eq!(w.window.len(), 2);
+        assert_eq!(w.index[&6].0, Region::Probation);
+
+        // a new key pushes 7 out of the window, it is not more frequent than 2.
+        w.insert(9, 1);
+        assert_eq!(w.victim(&mut std::iter::empty(), &freq), Some(7));
+        w.remove(7);
+
+        // the window shrinks with the keys, 8 leaves it. The keys which left it before are
+        // compared with the main victim too, they are not admitted for free.
+        assert_eq!(w.victim(&mut std::iter::empty(), &freq), Some(8));
+        w.remove(8);
+        assert_eq!(w.victim(&mut std::iter::empty(), &freq), Some(5));
+        w.remove(5);
+        assert_eq!(w.victim(&mut std::iter::empty(), &freq), Some(4));
+        w.remove(4);
+
+        // the reads promote the probation keys.
+        w.access(2);
+        assert_eq!(w.index[&2].0, Region::Protected);
+        assert!(w.handles_admission());
+        w.clear();
+        assert_eq!(victim(&mut w), None);
+    }
+
+    #[test]
+    fn test_hill_climber() {
+        let mut climber = HillClimber {
+            hits: 90,
+            misses: 10,
+            ..Default::default()
+        };
+        assert_eq!(climber.adjust(200), None);
+
+        // the hit rate improves, the window grows by a full step.
+        assert_eq!(climber.adjust(100), Some(CLIMBER_STEP));
+
+        // the hit rate drops, the climber turns back.
+        climber.hits = 50;
+        climber.misses = 50;
+        assert_eq!(climber.adjust(100), Some(-CLIMBER_STEP));
+
+        // the hit rate improves a little, the climber keeps its direction with a smaller step.
+        climber.hits = 51;
+        climber.misses = 49;
+        assert_eq!(climber.adjust(100), Some(-CLIMBER_STEP));
+        assert_eq!(climber.step, -CLIMBER_STEP * CLIMBER_STEP_DECAY);
+
+        let mut w = WTinyLfu::new();
+        (1..=4).for_each(|k| w.insert(k, 1));
+        (0..MIN_CLIMBER_SAMPLE).for_each(|_| w.access(1));
+        assert_eq!(w.window_ratio(), DEFAULT_WINDOW_RATIO + CLIMBER_STEP);
+    }
+}
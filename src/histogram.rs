@@ -1,5 +1,6 @@
 use atomic::Atomic;
 use std::fmt::{Display, Formatter};
+use std::io::{self, Write};
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
 
@@ -36,8 +37,8 @@ impl Histogram {
         }
     }
 
-    /// Returns HistogramData base on the min exponent and max exponent. The bounds are powers of two of the form
-    /// [2^min_exponent, ..., 2^max_exponent].
+    // Returns HistogramData base on the min exponent and max exponent. The bounds are powers of two of the form
+    // [2^min_exponent, ..., 2^max_exponent].
     // pub fn from_exponents(min_exp: u32, max_exp: u32) -> Self {
     //     Self::new((min_exp..=max_exp).map(|idx| (1 << idx) as f64).collect())
     // }
@@ -93,7 +94,6 @@ impl Histogram {
 
     /// `percentile` returns the percentile value for the histogram.
     /// value of p should be between [0.0-1.0]
-
     pub fn percentile(&self, p: f64) -> f64 {
         let count = self.count.load(Ordering::SeqCst);
         if count == 0 {
@@ -116,6 +116,41 @@ impl Histogram {
         }
     }
 
+    /// `encode_prometheus` writes the histogram as a Prometheus histogram named `name`, with
+    /// a cumulative bucket for each bound. `labels` are the rendered labels of each sample.
+    ///
+    /// A bucket counts the values below its bound, and `le` is inclusive, so the bucket is
+    /// labelled with the bound minus one: the values are integers.
+    pub(crate) fn encode_prometheus<W: Write>(
+        &self,
+        writer: &mut W,
+        name: &str,
+        labels: &str,
+    ) -> io::Result<()> {
+        let mut cum = 0;
+        for (idx, ct) in self.count_per_bucket.iter().enumerate() {
+            cum += ct.load(Ordering::SeqCst);
+            let le = match self.bounds.get(idx) {
+                Some(bound) => (bound.load(Ordering::SeqCst) as i64 - 1).to_string(),
+                None => "+Inf".to_string(),
+            };
+            writeln!(
+                writer,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, le, cum
+            )?;
+        }
+        writeln!(
+            writer,
+            "{}_sum{{{}}} {}",
+            name,
+            labels,
+            self.sum.load(Ordering::SeqCst)
+        )?;
+        // the count is the last cumulative bucket, so that they agree under concurrent updates.
+        writeln!(writer, "{}_count{{{}}} {}", name, labels, cum)
+    }
+
     /// `clear` reset the histogram. Helpful in situations where we need to reset the metrics
     pub fn clear(&self) {
         self.count.store(0, Ordering::SeqCst);
@@ -314,4 +349,22 @@ Count: 5
  --\n";
         assert_eq!(format!("{}", h), f)
     }
+
+    #[test]
+    fn test_encode_prometheus() {
+        let h = Histogram::new(vec![2.0, 4.0]);
+        // the values on a bound are counted by the next bucket.
+        [1, 2, 4].into_iter().for_each(|v| h.update(v));
+        let mut buf = Vec::new();
+        h.encode_prometheus(&mut buf, "h", "a=\"b\"").unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "h_bucket{a=\"b\",le=\"1\"} 1
+h_bucket{a=\"b\",le=\"3\"} 2
+h_bucket{a=\"b\",le=\"+Inf\"} 3
+h_sum{a=\"b\"} 7
+h_count{a=\"b\"} 3
+"
+        );
+    }
 }
@@ -29,7 +29,7 @@
 //!     * In async, [`AsyncCache`] starts two extra green threads. One is policy thread, the other is writing thread.
 //! * **Store policy** Stretto only store the value, which means the cache does not store the key.
 //! * **High Hit Ratios** - with Dgrpah's developers unique admission/eviction policy pairing, Stretto's performance is best in class.
-//! * **Eviction: SampledLFU** - on par with exact LRU and better performance on Search and Database traces.
+//! * **Eviction: SampledLFU** - on par with exact LRU and better performance on Search and Database traces, or pluggable: LRU, SLRU, S3-FIFO, SIEVE and an adaptive W-TinyLFU are also provided.
 //! * **Admission: TinyLFU** - extra performance with little memory overhead (12 bits per counter).
 //! * **Fast Throughput** - use a variety of techniques for managing contention and the result is excellent throughput.
 //! * **Cost-Based Eviction** - any large new item deemed valuable can evict multiple smaller items (cost could be anything).
@@ -48,12 +48,21 @@
 //!     * [max_cost](#max_cost)
 //!     * [key_builder](#key_builder)
 //!     * [buffer_size](#buffer_size)
+//!     * [buffer_items](#buffer_items)
 //!     * [metrics](#metrics)
 //!     * [ignore_internal_cost](#ignore_internal_cost)
 //!     * [cleanup_duration](#cleanup_duration)
+//!     * [expiration_resolution](#expiration_resolution)
+//!     * [time_to_idle](#time_to_idle)
+//!     * [retain_keys](#retain_keys)
 //!     * [update_validator](#update_validator)
 //!     * [callback](#callback)
 //!     * [coster](#coster)
+//!     * [expiry](#expiry)
+//!     * [clock](#clock)
+//!     * [eviction_policy](#eviction_policy)
+//!     * [admission](#admission)
+//!     * [policy_config](#policy_config)
 //!     * [hasher](#hasher)
 //!
 //! ## Usage
@@ -106,6 +115,13 @@
 //! try increasing this value in increments of 32 * 1024.
 //! This is a fine-tuning mechanism and you probably won't have to touch this.
 //!
+//! #### buffer_items
+//!
+//! `buffer_items` is the size of the Get buffers. Reads are recorded into lossy, striped buffers,
+//! and each full buffer is handed to the admission policy as one batch.
+//!
+//! Unless you have a rare use case, using `64` as the `buffer_items` value results in good performance.
+//!
 //! #### metrics
 //!
 //! Metrics is true when you want real-time logging of a variety of stats.
@@ -122,6 +138,28 @@
 //!
 //! The Cache will cleanup the expired values every 500ms by default.
 //!
+//! #### expiration_resolution
+//!
+//! The expiring values are kept in a hierarchical timer wheel whose slots span 1s by default,
+//! so the cleanup reclaims a value at most one slot after its deadline. Lower it (down to one
+//! millisecond) along with the `cleanup_duration` for values which live less than a few seconds,
+//! e.g. the windows of a rate limiter.
+//!
+//! #### time_to_idle
+//!
+//! The values inserted without a TTL expire once they have not been read for `time_to_idle`.
+//! Use `insert_with_tti` to set the time-to-idle of a single value. Reads only record the access
+//! time, the expiration is rescheduled lazily by the cleanup. Zero by default, which means the
+//! values never expire.
+//!
+//! #### retain_keys
+//!
+//! By default, the Cache only stores the hashes of the keys. Set `retain_keys` to true to keep the
+//! original keys alongside the values, so that the Cache can be iterated with `iter`, `keys` and
+//! `for_each` (and `stream` for the `AsyncCache`).
+//!
+//! Keep in mind that retaining the keys increases the memory usage.
+//!
 //! #### update_validator
 //!
 //! By default, the Cache will always update the value if the value already exists in the cache.
@@ -144,6 +182,34 @@
 //! 1. Set the [`Coster`] field to your own [`Coster`] implementation.
 //! 2. When calling [`insert`] for new items or item updates, use a cost of 0.
 //!
+//! #### expiry
+//!
+//! [`Expiry`] is a trait you can pass to the [`CacheBuilder`] in order to compute the TTL of a value
+//! from the value itself when it is created, updated or read, instead of at every call site.
+//!
+//! #### clock
+//!
+//! [`Clock`] is the source of time of all the expirations of the Cache. The default [`DefaultClock`]
+//! is monotonic and coarse, a [`MockClock`] lets the tests advance the time by hand.
+//!
+//! #### eviction_policy
+//!
+//! [`EvictionPolicy`] chooses the keys to evict when the Cache is full. The default is the
+//! [`SampledLfu`], [`Lru`], [`Slru`], [`S3Fifo`], [`Sieve`] and the adaptive [`WTinyLfu`] are also
+//! provided.
+//!
+//! #### admission
+//!
+//! [`Admission`] decides whether a new key is worth evicting the victims when the Cache is full.
+//! The default is strict TinyLFU, `Always` admits all the new keys and `TinyLfuJitter` admits a
+//! small share of the warm keys TinyLFU would reject, against hash flooding.
+//!
+//! #### policy_config
+//!
+//! [`PolicyConfig`] tunes the number of keys sampled by the default eviction policy, the false
+//! positive ratio of the doorkeeper, the reset interval of TinyLFU and the depth of its count-min
+//! sketch. It is validated when the Cache is built.
+//!
 //! #### hasher
 //!
 //! The hasher for the Cache, default is SipHasher.
@@ -168,15 +234,25 @@
 //! [`UpdateValidator`]: trait.UpdateValidator.html
 //! [`CacheCallback`]: trait.CacheCallback.html
 //! [`Coster`]: trait.Coster.html
+//! [`Expiry`]: trait.Expiry.html
+//! [`Clock`]: trait.Clock.html
+//! [`DefaultClock`]: struct.DefaultClock.html
+//! [`MockClock`]: struct.MockClock.html
 //! [`Cache`]: struct.Cache.html
 //! [`AsyncCache`]: struct.AsyncCache.html
 #![deny(missing_docs)]
-#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+#![allow(
+    clippy::too_many_arguments,
+    clippy::type_complexity,
+    clippy::doc_overindented_list_items
+)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 #![cfg_attr(docsrs, allow(unused_attributes))]
 mod bbloom;
 mod cache;
+mod clock;
 mod error;
+mod eviction;
 mod histogram;
 mod metrics;
 /// This package includes multiple probabalistic data structures needed for
@@ -189,6 +265,7 @@ mod metrics;
 /// [1]: https://arxiv.org/abs/1512.00727
 #[allow(dead_code)]
 mod policy;
+mod ring;
 mod sketch;
 mod store;
 mod ttl;
@@ -205,7 +282,7 @@ extern crate serde;
 #[cfg(feature = "async")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 pub(crate) mod axync {
-    pub(crate) use async_channel::{bounded, unbounded, Receiver, RecvError, Sender};
+    pub(crate) use async_channel::{bounded, unbounded, Receiver, RecvError, Sender, TrySendError};
     pub(crate) use futures::select;
     pub(crate) type WaitGroup = wg::AsyncWaitGroup;
     pub(crate) fn stop_channel() -> (Sender<()>, Receiver<()>) {
@@ -236,9 +313,16 @@ pub(crate) mod sync {
 #[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
 pub use cache::{Cache, CacheBuilder};
 
+pub use cache::{
+    DropReason, Entry, InsertOutcome, MaintenanceReport, OccupiedEntry, VacantEntry, WarmReport,
+};
+pub use clock::{Clock, DefaultClock, MockClock};
 pub use error::CacheError;
+pub use eviction::{EvictionPolicy, Lru, S3Fifo, SampledLfu, Sieve, Slru, WTinyLfu};
 pub use histogram::Histogram;
 pub use metrics::{MetricType, Metrics};
+pub use policy::{Admission, PolicyConfig};
+pub use ttl::Deadline;
 pub use utils::{ValueRef, ValueRefMut};
 
 use crate::ttl::Time;
@@ -246,6 +330,7 @@ use seahash::SeaHasher;
 use std::fmt::{Debug, Formatter};
 use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
 use std::marker::PhantomData;
+use std::time::{Duration, Instant};
 
 /// Item is the parameter when Cache reject, evict value,
 pub struct Item<V> {
@@ -423,6 +508,64 @@ impl<V: Send + Sync + 'static> Coster for DefaultCoster<V> {
     }
 }
 
+/// Expiry is a trait you can pass to the CacheBuilder in order to compute the TTL of a value
+/// from the value itself (e.g. the `max-age` of an HTTP response), instead of computing it at
+/// every call site of `insert_with_ttl`.
+///
+/// Each callback returns the new TTL of the value, or `None` to keep the current one.
+/// As everywhere else in the Cache, a zero TTL means the value never expires. The `now` given
+/// to the callbacks is the current time of the [`Clock`](trait.Clock.html) of the Cache.
+pub trait Expiry: Send + Sync {
+    /// Key
+    type Key;
+
+    /// Value
+    type Value: Send + Sync + 'static;
+
+    /// expire_after_create is called when a new value is inserted. Returning `None` keeps
+    /// the TTL given to the insert.
+    fn expire_after_create(
+        &self,
+        key: &Self::Key,
+        val: &Self::Value,
+        now: Instant,
+    ) -> Option<Duration> {
+        let _ = (key, val, now);
+        None
+    }
+
+    /// expire_after_update is called when the value of an existing key is replaced, with the
+    /// remaining TTL of the replaced value, `None` if it never expires. Returning `None` keeps
+    /// the TTL given to the insert, return `current_ttl` to keep the one of the replaced value.
+    ///
+    /// It is called while the store shard of the key is locked, so it must not call into the Cache.
+    fn expire_after_update(
+        &self,
+        key: &Self::Key,
+        val: &Self::Value,
+        now: Instant,
+        current_ttl: Option<Duration>,
+    ) -> Option<Duration> {
+        let _ = (key, val, now, current_ttl);
+        None
+    }
+
+    /// expire_after_read is called when the value is read by `get`, `get_mut` or `get_many`,
+    /// with its remaining TTL, `None` if it never expires. Returning `None` keeps it.
+    ///
+    /// Reads only know the borrowed form of the key, so the key is not passed. It is called
+    /// while the store shard of the key is locked, so it must not call into the Cache.
+    fn expire_after_read(
+        &self,
+        val: &Self::Value,
+        now: Instant,
+        current_ttl: Option<Duration>,
+    ) -> Option<Duration> {
+        let _ = (val, now, current_ttl);
+        None
+    }
+}
+
 /// [`KeyBuilder`] is the hashing algorithm used for every key. In Stretto, the Cache will never store the real key.
 /// The key will be processed by [`KeyBuilder`]. Stretto has two default built-in key builder,
 /// one is [`TransparentKeyBuilder`], the other is [`DefaultKeyBuilder`]. If your key implements [`TransparentKey`] trait,
@@ -512,9 +655,7 @@ impl<K: Hash + Eq> KeyBuilder for DefaultKeyBuilder<K> {
         Self::Key: core::borrow::Borrow<Q>,
         Q: core::hash::Hash + Eq + ?Sized,
     {
-        let mut s = self.sea.build_hasher();
-        key.hash(&mut s);
-        s.finish()
+        self.sea.hash_one(key)
     }
 
     #[inline]
@@ -523,9 +664,7 @@ impl<K: Hash + Eq> KeyBuilder for DefaultKeyBuilder<K> {
         Self::Key: core::borrow::Borrow<Q>,
         Q: core::hash::Hash + Eq + ?Sized,
     {
-        let mut x = self.xx.build_hasher();
-        key.hash(&mut x);
-        x.finish()
+        self.xx.hash_one(key)
     }
 }
 
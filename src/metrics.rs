@@ -2,12 +2,16 @@ use crate::histogram::Histogram;
 use crate::utils::vec_to_array;
 use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::Arc;
 
 const HISTOGRAM_BOUND_SIZE: usize = 16;
 
-const NUMS_OF_METRIC_TYPE: usize = 11;
+/// The prefix of the names of the metrics in the Prometheus text format.
+const PROMETHEUS_PREFIX: &str = "stretto";
+
+const NUMS_OF_METRIC_TYPE: usize = 14;
 const SIZE_FOR_EACH_TYPE: usize = 256;
 static METRIC_TYPES_ARRAY: [MetricType; NUMS_OF_METRIC_TYPE] = [
     MetricType::Hit,
@@ -21,6 +25,9 @@ static METRIC_TYPES_ARRAY: [MetricType; NUMS_OF_METRIC_TYPE] = [
     MetricType::RejectSets,
     MetricType::DropGets,
     MetricType::KeepGets,
+    MetricType::RejectCost,
+    MetricType::RejectFrequency,
+    MetricType::AdmitJitter,
 ];
 
 /// The data field in a Metrics
@@ -59,11 +66,44 @@ pub enum MetricType {
     /// track how many gets were kept on the floor
     KeepGets,
 
+    /// track how many sets were rejected because their cost is above the max cost
+    RejectCost,
+
+    /// track how many sets were rejected by the admission because they are less frequently
+    /// used than the victim
+    RejectFrequency,
+
+    /// track how many sets were admitted by the jitter of the admission
+    AdmitJitter,
+
     /// This should be the final enum. Other enums should be set before this.
     #[allow(dead_code)]
     DoNotUse,
 }
 
+impl MetricType {
+    /// `help` describes the metric type, for the Prometheus text format.
+    fn help(&self) -> &'static str {
+        match self {
+            MetricType::Hit => "The number of gets which found a value.",
+            MetricType::Miss => "The number of gets which found no value.",
+            MetricType::KeyAdd => "The number of new keys added.",
+            MetricType::KeyUpdate => "The number of keys updated.",
+            MetricType::KeyEvict => "The number of keys evicted.",
+            MetricType::CostAdd => "The sum of the costs of the keys added.",
+            MetricType::CostEvict => "The sum of the costs of the keys evicted.",
+            MetricType::DropSets => "The number of sets dropped before reaching the policy.",
+            MetricType::RejectSets => "The number of sets rejected by the policy.",
+            MetricType::DropGets => "The number of get counter increments dropped.",
+            MetricType::KeepGets => "The number of get counter increments kept.",
+            MetricType::RejectCost => "The number of sets rejected for their cost.",
+            MetricType::RejectFrequency => "The number of sets rejected for their frequency.",
+            MetricType::AdmitJitter => "The number of sets admitted by the jitter.",
+            MetricType::DoNotUse => "Unidentified.",
+        }
+    }
+}
+
 impl Display for MetricType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -78,6 +118,9 @@ impl Display for MetricType {
             MetricType::RejectSets => write!(f, "sets-rejected"),
             MetricType::DropGets => write!(f, "gets-dropped"),
             MetricType::KeepGets => write!(f, "gets-kept"),
+            MetricType::RejectCost => write!(f, "sets-rejected-cost"),
+            MetricType::RejectFrequency => write!(f, "sets-rejected-frequency"),
+            MetricType::AdmitJitter => write!(f, "sets-admitted-jitter"),
             MetricType::DoNotUse => write!(f, "unidentified"),
         }
     }
@@ -140,6 +183,14 @@ impl Metrics {
         }
     }
 
+    #[inline]
+    pub(crate) fn track_cost(&self, used: i64, max: i64) {
+        match self {
+            Metrics::Noop => {}
+            Metrics::Op(m) => m.track_cost(used, max),
+        }
+    }
+
     #[inline]
     pub(crate) fn add(&self, typ: MetricType, hash: u64, delta: u64) -> bool {
         match self {
@@ -200,12 +251,31 @@ impl Metrics {
         self.map(|m| m.get_sets_dropped())
     }
 
-    /// Returns the number of Set calls rejected by the policy (TinyLFU).
+    /// Returns the number of Set calls rejected by the policy (TinyLFU) or for their cost.
     #[inline]
     pub fn get_sets_rejected(&self) -> Option<u64> {
         self.map(|m| m.get_sets_rejected())
     }
 
+    /// Returns the number of Set calls rejected because their cost is above the max cost.
+    #[inline]
+    pub fn get_sets_rejected_cost(&self) -> Option<u64> {
+        self.map(|m| m.get_sets_rejected_cost())
+    }
+
+    /// Returns the number of Set calls rejected by the admission, because they are less
+    /// frequently used than the victim.
+    #[inline]
+    pub fn get_sets_rejected_frequency(&self) -> Option<u64> {
+        self.map(|m| m.get_sets_rejected_frequency())
+    }
+
+    /// Returns the number of Set calls admitted by the jitter of the admission.
+    #[inline]
+    pub fn get_sets_admitted_jitter(&self) -> Option<u64> {
+        self.map(|m| m.get_sets_admitted_jitter())
+    }
+
     /// Returns the number of Get counter increments that are dropped
     /// internally.
     #[inline]
@@ -232,6 +302,39 @@ impl Metrics {
         self.map(|m| m.life_expectancy_seconds())
     }
 
+    /// Returns the sum of the costs of the items in the cache, as counted by the policy.
+    #[inline]
+    pub fn get_cost_used(&self) -> Option<i64> {
+        self.map(|m| m.get_cost_used())
+    }
+
+    /// Returns the max cost of the cache.
+    #[inline]
+    pub fn get_max_cost(&self) -> Option<i64> {
+        self.map(|m| m.get_max_cost())
+    }
+
+    /// `encode_prometheus` writes the metrics in the Prometheus text exposition format: a
+    /// `stretto_<type>_total` counter for each [`MetricType`], the `stretto_cost_used` and
+    /// `stretto_cost_max` gauges and the `stretto_life_expectancy_seconds` histogram.
+    ///
+    /// Each sample is labelled with `cache="<cache_name>"` and `labels`, whose values are
+    /// escaped. It fails with `InvalidInput` if a label name is not a valid Prometheus label
+    /// name, starts with `__`, or is `cache` or `le`. Nothing is written by a Noop metrics.
+    ///
+    /// [`MetricType`]: enum.MetricType.html
+    pub fn encode_prometheus<W: Write>(
+        &self,
+        writer: &mut W,
+        cache_name: &str,
+        labels: &[(&str, &str)],
+    ) -> io::Result<()> {
+        match self {
+            Metrics::Noop => Ok(()),
+            Metrics::Op(m) => m.encode_prometheus(writer, cache_name, labels),
+        }
+    }
+
     /// clear resets all the metrics
     #[inline]
     pub fn clear(&self) {
@@ -266,6 +369,12 @@ pub struct MetricsInner {
 
     /// tracks the life expectancy of a key
     life: Histogram,
+
+    /// the cost used in the cache, set by the policy
+    cost_used: Arc<AtomicI64>,
+
+    /// the max cost of the cache, set by the policy
+    max_cost: Arc<AtomicI64>,
 }
 
 impl Default for MetricsInner {
@@ -293,6 +402,8 @@ impl MetricsInner {
         Self {
             all: Arc::new(map),
             life: h,
+            cost_used: Arc::new(AtomicI64::new(0)),
+            max_cost: Arc::new(AtomicI64::new(0)),
         }
     }
 
@@ -345,12 +456,31 @@ impl MetricsInner {
         self.get(&MetricType::DropSets)
     }
 
-    /// Returns the number of Set calls rejected by the policy (TinyLFU).
+    /// Returns the number of Set calls rejected by the policy (TinyLFU) or for their cost.
     #[inline]
     pub fn get_sets_rejected(&self) -> u64 {
         self.get(&MetricType::RejectSets)
     }
 
+    /// Returns the number of Set calls rejected because their cost is above the max cost.
+    #[inline]
+    pub fn get_sets_rejected_cost(&self) -> u64 {
+        self.get(&MetricType::RejectCost)
+    }
+
+    /// Returns the number of Set calls rejected by the admission, because they are less
+    /// frequently used than the victim.
+    #[inline]
+    pub fn get_sets_rejected_frequency(&self) -> u64 {
+        self.get(&MetricType::RejectFrequency)
+    }
+
+    /// Returns the number of Set calls admitted by the jitter of the admission.
+    #[inline]
+    pub fn get_sets_admitted_jitter(&self) -> u64 {
+        self.get(&MetricType::AdmitJitter)
+    }
+
     /// Returns the number of Get counter increments that are dropped
     /// internally.
     #[inline]
@@ -383,6 +513,80 @@ impl MetricsInner {
         self.life.clone()
     }
 
+    /// Returns the sum of the costs of the items in the cache, as counted by the policy.
+    #[inline]
+    pub fn get_cost_used(&self) -> i64 {
+        self.cost_used.load(Ordering::SeqCst)
+    }
+
+    /// Returns the max cost of the cache.
+    #[inline]
+    pub fn get_max_cost(&self) -> i64 {
+        self.max_cost.load(Ordering::SeqCst)
+    }
+
+    /// `encode_prometheus` writes the metrics in the Prometheus text exposition format, see
+    /// [`Metrics::encode_prometheus`](enum.Metrics.html#method.encode_prometheus).
+    pub fn encode_prometheus<W: Write>(
+        &self,
+        writer: &mut W,
+        cache_name: &str,
+        labels: &[(&str, &str)],
+    ) -> io::Result<()> {
+        let mut rendered = format!("cache=\"{}\"", escape_label_value(cache_name));
+        for (name, value) in labels {
+            if !is_label_name(name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid label name: {:?}", name),
+                ));
+            }
+            rendered.push_str(&format!(",{}=\"{}\"", name, escape_label_value(value)));
+        }
+
+        for typ in METRIC_TYPES_ARRAY.iter() {
+            let name = format!(
+                "{}_{}_total",
+                PROMETHEUS_PREFIX,
+                typ.to_string().replace('-', "_")
+            );
+            writeln!(writer, "# HELP {} {}", name, typ.help())?;
+            writeln!(writer, "# TYPE {} counter", name)?;
+            writeln!(writer, "{}{{{}}} {}", name, rendered, self.get(typ))?;
+        }
+
+        let gauges = [
+            (
+                "cost_used",
+                "The cost used in the cache.",
+                self.get_cost_used(),
+            ),
+            (
+                "cost_max",
+                "The max cost of the cache.",
+                self.get_max_cost(),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            writeln!(writer, "# HELP {}_{} {}", PROMETHEUS_PREFIX, name, help)?;
+            writeln!(writer, "# TYPE {}_{} gauge", PROMETHEUS_PREFIX, name)?;
+            writeln!(
+                writer,
+                "{}_{}{{{}}} {}",
+                PROMETHEUS_PREFIX, name, rendered, value
+            )?;
+        }
+
+        let name = format!("{}_life_expectancy_seconds", PROMETHEUS_PREFIX);
+        writeln!(
+            writer,
+            "# HELP {} The time the evicted keys spent in the cache.",
+            name
+        )?;
+        writeln!(writer, "# TYPE {} histogram", name)?;
+        self.life.encode_prometheus(writer, &name, &rendered)
+    }
+
     /// clear resets all the metrics
     #[inline]
     pub fn clear(&self) {
@@ -400,6 +604,12 @@ impl MetricsInner {
         self.life.update(num_seconds)
     }
 
+    #[inline]
+    pub(crate) fn track_cost(&self, used: i64, max: i64) {
+        self.cost_used.store(used, Ordering::SeqCst);
+        self.max_cost.store(max, Ordering::SeqCst);
+    }
+
     #[inline]
     pub(crate) fn add(&self, typ: MetricType, hash: u64, delta: u64) {
         if let Some(val) = self.all.get(&typ) {
@@ -433,9 +643,9 @@ impl Serialize for MetricsInner {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("MetricsInner", 13)?;
+        let mut s = serializer.serialize_struct("MetricsInner", 16)?;
 
-        let types: [&'static str; 11] = [
+        let types: [&'static str; NUMS_OF_METRIC_TYPE] = [
             "hit",
             "miss",
             "keys-added",
@@ -447,6 +657,9 @@ impl Serialize for MetricsInner {
             "sets-rejected",
             "gets-dropped",
             "gets-kept",
+            "sets-rejected-cost",
+            "sets-rejected-frequency",
+            "sets-admitted-jitter",
         ];
 
         for (idx, typ) in METRIC_TYPES_ARRAY.iter().enumerate() {
@@ -490,6 +703,28 @@ impl Display for MetricsInner {
     }
 }
 
+/// `is_label_name` tells whether `name` is a valid Prometheus label name which is not
+/// reserved, by Prometheus or by the labels of the cache.
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+        && name != "cache"
+        && name != "le"
+}
+
+/// `escape_label_value` escapes the backslashes, the double quotes and the line feeds of a
+/// Prometheus label value.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
 fn new_histogram_bound() -> Vec<f64> {
     (1..=HISTOGRAM_BOUND_SIZE as u64)
         .map(|idx| (1 << idx) as f64)
@@ -498,8 +733,8 @@ fn new_histogram_bound() -> Vec<f64> {
 
 #[cfg(test)]
 mod test {
-    use crate::metrics::MetricsInner;
-    use crate::Metrics;
+    use crate::metrics::{MetricsInner, NUMS_OF_METRIC_TYPE};
+    use crate::{MetricType, Metrics};
 
     #[test]
     fn test_metrics() {
@@ -515,6 +750,9 @@ mod test {
         assert!(m.get_gets_kept().is_none());
         assert!(m.get_sets_dropped().is_none());
         assert!(m.get_sets_rejected().is_none());
+        assert!(m.get_sets_rejected_cost().is_none());
+        assert!(m.get_sets_rejected_frequency().is_none());
+        assert!(m.get_sets_admitted_jitter().is_none());
         assert!(m.get_cost_evicted().is_none());
         assert!(m.get_cost_added().is_none());
         assert!(m.life_expectancy_seconds().is_none());
@@ -532,6 +770,9 @@ mod test {
         m.get_gets_kept().unwrap();
         m.get_sets_dropped().unwrap();
         m.get_sets_rejected().unwrap();
+        m.get_sets_rejected_cost().unwrap();
+        m.get_sets_rejected_frequency().unwrap();
+        m.get_sets_admitted_jitter().unwrap();
         m.get_cost_evicted().unwrap();
         m.get_cost_added().unwrap();
         m.life_expectancy_seconds().unwrap();
@@ -540,6 +781,70 @@ mod test {
         println!("{}", m);
     }
 
+    #[test]
+    fn test_encode_prometheus() {
+        let mut buf = Vec::new();
+        Metrics::new()
+            .encode_prometheus(&mut buf, "users", &[])
+            .unwrap();
+        assert!(buf.is_empty());
+
+        let m = Metrics::new_op();
+        m.add(MetricType::Hit, 1, 3);
+        m.add(MetricType::Hit, 2, 2);
+        m.track_cost(7, 10);
+        m.track_eviction(1);
+        m.track_eviction(3);
+        m.track_eviction(1 << 20);
+        m.encode_prometheus(&mut buf, "us\"ers", &[("region", "eu\\1\n")])
+            .unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        let labels = r#"cache="us\"ers",region="eu\\1\n""#;
+
+        for line in [
+            "# HELP stretto_hit_total The number of gets which found a value.".to_string(),
+            "# TYPE stretto_hit_total counter".to_string(),
+            format!("stretto_hit_total{{{}}} 5", labels),
+            format!("stretto_sets_rejected_frequency_total{{{}}} 0", labels),
+            "# TYPE stretto_cost_used gauge".to_string(),
+            format!("stretto_cost_used{{{}}} 7", labels),
+            format!("stretto_cost_max{{{}}} 10", labels),
+            "# TYPE stretto_life_expectancy_seconds histogram".to_string(),
+            format!(
+                "stretto_life_expectancy_seconds_bucket{{{},le=\"1\"}} 1",
+                labels
+            ),
+            format!(
+                "stretto_life_expectancy_seconds_bucket{{{},le=\"3\"}} 2",
+                labels
+            ),
+            format!(
+                "stretto_life_expectancy_seconds_bucket{{{},le=\"65535\"}} 2",
+                labels
+            ),
+            format!(
+                "stretto_life_expectancy_seconds_bucket{{{},le=\"+Inf\"}} 3",
+                labels
+            ),
+            format!(
+                "stretto_life_expectancy_seconds_sum{{{}}} {}",
+                labels,
+                4 + (1 << 20)
+            ),
+            format!("stretto_life_expectancy_seconds_count{{{}}} 3", labels),
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {}", line);
+        }
+        assert_eq!(text.matches("# TYPE").count(), NUMS_OF_METRIC_TYPE + 3);
+
+        for name in ["", "1a", "a-b", "__a", "cache", "le"] {
+            let err = m
+                .encode_prometheus(&mut Vec::new(), "users", &[(name, "v")])
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
     #[test]
     #[cfg(all(feature = "serde", feature = "serde_json"))]
     fn test_display() {
@@ -564,6 +869,9 @@ mod test {
   \"sets-rejected\": 0,
   \"gets-dropped\": 0,
   \"gets-kept\": 0,
+  \"sets-rejected-cost\": 0,
+  \"sets-rejected-frequency\": 0,
+  \"sets-admitted-jitter\": 0,
   \"gets-total\": 0,
   \"hit-ratio\": 0.00
 }"
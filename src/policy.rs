@@ -4,8 +4,10 @@ mod test;
 use crate::{
     bbloom::Bloom,
     error::CacheError,
+    eviction::{EvictionPolicy, DEFAULT_SAMPLES},
     metrics::{MetricType, Metrics},
-    sketch::CountMinSketch,
+    sketch::{CountMinSketch, DEFAULT_DEPTH},
+    utils::StateReader,
 };
 use parking_lot::Mutex;
 use std::{
@@ -17,14 +19,13 @@ use std::{
     },
 };
 
-/// DEFAULT_SAMPLES is the number of items to sample when looking at eviction
-/// candidates. 5 seems to be the most optimal number [citation needed].
-const DEFAULT_SAMPLES: usize = 5;
+/// ITEMS_BUFFER_SIZE is the number of batches of reads which can wait for the policy
+/// processor. The batches pushed while it is full are dropped, as in Ristretto.
+pub(crate) const ITEMS_BUFFER_SIZE: usize = 3;
 
 macro_rules! impl_policy {
     ($policy: ident) => {
-        use crate::policy::PolicyPair;
-        use crate::policy::DEFAULT_SAMPLES;
+        use crate::policy::{Admission, Decision, PolicyPair};
 
         impl<S: BuildHasher + Clone + 'static> $policy<S> {
             #[inline]
@@ -33,17 +34,36 @@ macro_rules! impl_policy {
                 self.inner.lock().set_metrics(metrics);
             }
 
+            /// `set_admission` changes how the new keys are admitted when the cache is full.
+            #[inline]
+            pub fn set_admission(&self, admission: Admission) {
+                self.inner.lock().admission = admission;
+            }
+
+            /// `flush` applies the batches of reads still waiting for the policy processor
+            /// to the admission and eviction policies.
+            pub fn flush(&self) {
+                if let Some(items_rx) = self.items_rx.lock().as_ref() {
+                    let mut inner = self.inner.lock();
+                    while let Ok(items) = items_rx.try_recv() {
+                        inner.access(items);
+                    }
+                }
+            }
+
             pub fn add(&self, key: u64, cost: i64) -> (Option<Vec<PolicyPair>>, bool) {
                 let mut inner = self.inner.lock();
                 let max_cost = inner.costs.get_max_cost();
 
                 // cannot ad an item bigger than entire cache
                 if cost > max_cost {
+                    self.metrics.add(MetricType::RejectSets, key, 1);
+                    self.metrics.add(MetricType::RejectCost, key, 1);
                     return (None, false);
                 }
 
                 // no need to go any further if the item is already in the cache
-                if inner.costs.update(&key, cost) {
+                if inner.update(&key, cost) {
                     // an update does not count as an addition, so return false.
                     return (None, false);
                 }
@@ -54,70 +74,80 @@ macro_rules! impl_policy {
                 if room >= 0 {
                     // There's enough room in the cache to store the new item without
                     // overflowing. Do that now and stop here.
-                    inner.costs.increment(key, cost);
+                    inner.increment(key, cost);
                     self.metrics.add(MetricType::CostAdd, key, cost as u64);
                     return (None, true);
                 }
 
                 // inc_hits is the hit count for the incoming item
                 let inc_hits = inner.admit.estimate(key);
-                // sample is the eviction candidate pool to be filled via random sampling.
-                // TODO: perhaps we should use a min heap here. Right now our time
-                // complexity is N for finding the min. Min heap should bring it down to
-                // O(lg N). We try to use std::collections::BinaryHeap, but it is very slower.
-                // https://github.com/al8n/stretto/pull/6/commits/c3a2a549ad4b033651470774224c583e2322d08a
-                let mut sample = Vec::with_capacity(DEFAULT_SAMPLES);
                 let mut victims = Vec::new();
 
-                // Delete victims until there's enough space or a minKey is found that has
+                // Delete victims until there's enough space or a victim is found that has
                 // more hits than incoming item.
                 while room < 0 {
-                    // fill up empty slots in sample
-                    sample = inner.costs.fill_sample(sample);
-
-                    // find minimally used item in sample
-                    let (mut min_key, mut min_hits, mut min_id, mut min_cost) =
-                        (0u64, i64::MAX, 0, 0i64);
-
-                    sample.iter().enumerate().for_each(|(idx, pair)| {
-                        // Look up hit count for sample key.
-                        let hits = inner.admit.estimate(pair.key);
-                        if hits < min_hits {
-                            min_key = pair.key;
-                            min_hits = hits;
-                            min_id = idx;
-                            min_cost = pair.cost;
+                    let victim = match inner.victim() {
+                        Some(victim) => victim,
+                        None => {
+                            self.metrics.add(MetricType::RejectSets, key, 1);
+                            return (Some(victims), false);
                         }
-                    });
-
-                    // If the incoming item isn't worth keeping in the policy, reject.
-                    if inc_hits < min_hits {
-                        self.metrics.add(MetricType::RejectSets, key, 1);
-                        return (Some(victims), false);
+                    };
+
+                    // If the incoming item isn't worth keeping in the policy, reject, unless
+                    // the eviction policy already decided the admission.
+                    if !inner.eviction.handles_admission() {
+                        let victim_hits = inner.admit.estimate(victim);
+                        match inner.admission.decide(inc_hits, victim_hits) {
+                            Decision::Admit => {}
+                            Decision::Jitter => {
+                                self.metrics.add(MetricType::AdmitJitter, key, 1);
+                            }
+                            Decision::Reject => {
+                                self.metrics.add(MetricType::RejectSets, key, 1);
+                                self.metrics.add(MetricType::RejectFrequency, key, 1);
+                                return (Some(victims), false);
+                            }
+                        };
                     }
 
-                    // Delete the victim from metadata.
-                    inner.costs.remove(&min_key).map(|cost| {
-                        self.metrics
-                            .add(MetricType::CostEvict, min_key, cost as u64);
-                        self.metrics.add(MetricType::KeyEvict, min_key, 1);
-                    });
-
-                    // Delete the victim from sample.
-                    let new_len = sample.len() - 1;
-                    sample[min_id] = sample[new_len];
-                    sample.drain(new_len..);
-                    // store victim in evicted victims slice
-                    victims.push(PolicyPair::new(min_key, min_cost));
+                    // Delete the victim from metadata and store it in evicted victims slice.
+                    // A victim unknown to the ledger frees no room, reject rather than loop on it.
+                    match inner.remove(&victim) {
+                        Some(cost) => {
+                            self.metrics.add(MetricType::CostEvict, victim, cost as u64);
+                            self.metrics.add(MetricType::KeyEvict, victim, 1);
+                            victims.push(PolicyPair::new(victim, cost));
+                        }
+                        None => {
+                            self.metrics.add(MetricType::RejectSets, key, 1);
+                            return (Some(victims), false);
+                        }
+                    }
 
                     room = inner.costs.room_left(cost);
                 }
 
-                inner.costs.increment(key, cost);
+                inner.increment(key, cost);
                 self.metrics.add(MetricType::CostAdd, key, cost as u64);
                 (Some(victims), true)
             }
 
+            /// `warm` adds a new key only if there is room left for it, without evicting nor
+            /// asking the admission policy, and counts `frequency` reads of the key first. It
+            /// returns whether the key was added.
+            pub fn warm(&self, key: u64, cost: i64, frequency: u8) -> bool {
+                let mut inner = self.inner.lock();
+                if inner.costs.contains(&key) || inner.costs.room_left(cost) < 0 {
+                    return false;
+                }
+
+                (0..frequency).for_each(|_| inner.admit.increment(key));
+                inner.increment(key, cost);
+                self.metrics.add(MetricType::CostAdd, key, cost as u64);
+                true
+            }
+
             #[inline]
             pub fn contains(&self, k: &u64) -> bool {
                 let inner = self.inner.lock();
@@ -127,7 +157,7 @@ macro_rules! impl_policy {
             #[inline]
             pub fn remove(&self, k: &u64) {
                 let mut inner = self.inner.lock();
-                inner.costs.remove(k).map(|cost| {
+                inner.remove(k).map(|cost| {
                     self.metrics.add(MetricType::CostEvict, *k, cost as u64);
                     self.metrics.add(MetricType::KeyEvict, *k, 1);
                 });
@@ -142,7 +172,7 @@ macro_rules! impl_policy {
             #[inline]
             pub fn update(&self, k: &u64, cost: i64) {
                 let mut inner = self.inner.lock();
-                inner.costs.update(k, cost);
+                inner.update(k, cost);
             }
 
             #[inline]
@@ -156,6 +186,19 @@ macro_rules! impl_policy {
                 let mut inner = self.inner.lock();
                 inner.admit.clear();
                 inner.costs.clear();
+                inner.eviction.clear();
+            }
+
+            /// `export_state` encodes the access history of the admission policy.
+            #[inline]
+            pub fn export_state(&self) -> Vec<u8> {
+                self.inner.lock().admit.export_state()
+            }
+
+            /// `import_state` replaces the access history of the admission policy.
+            #[inline]
+            pub fn import_state(&self, state: &[u8]) -> Result<(), CacheError> {
+                self.inner.lock().admit.import_state(state)
             }
 
             #[inline]
@@ -169,6 +212,31 @@ macro_rules! impl_policy {
                 let inner = self.inner.lock();
                 inner.costs.update_max_cost(mc)
             }
+
+            /// `evict_overflow` evicts the victims chosen by the eviction policy until the used
+            /// cost fits in the max cost again, e.g. after the max cost was lowered.
+            pub fn evict_overflow(&self) -> Vec<PolicyPair> {
+                let mut inner = self.inner.lock();
+                let mut victims = Vec::new();
+
+                while inner.costs.room_left(0) < 0 {
+                    let victim = match inner.victim() {
+                        Some(victim) => victim,
+                        None => break,
+                    };
+
+                    // as in `add`, a victim unknown to the ledger frees no room.
+                    match inner.remove(&victim) {
+                        Some(cost) => {
+                            self.metrics.add(MetricType::CostEvict, victim, cost as u64);
+                            self.metrics.add(MetricType::KeyEvict, victim, 1);
+                            victims.push(PolicyPair::new(victim, cost));
+                        }
+                        None => break,
+                    }
+                }
+                victims
+            }
         }
 
         unsafe impl<S: BuildHasher + Clone + 'static + Send> Send for $policy<S> {}
@@ -191,8 +259,175 @@ mod axync;
 pub(crate) use axync::AsyncLFUPolicy;
 
 pub(crate) struct PolicyInner<S = RandomState> {
-    admit: TinyLFU,
-    costs: SampledLFU<S>,
+    pub(crate) admit: TinyLFU,
+    pub(crate) admission: Admission,
+    pub(crate) costs: CostLedger<S>,
+    pub(crate) eviction: Box<dyn EvictionPolicy>,
+}
+
+/// DEFAULT_FALSE_POSITIVE_RATIO is the default false positive ratio of the doorkeeper.
+const DEFAULT_FALSE_POSITIVE_RATIO: f64 = 0.01;
+
+/// MAX_SKETCH_DEPTH is the largest number of rows of the count-min sketch.
+const MAX_SKETCH_DEPTH: usize = 16;
+
+/// PolicyConfig tunes the admission and eviction policies of the Cache, it is validated when
+/// the Cache is built.
+///
+/// ```
+/// use stretto::PolicyConfig;
+///
+/// let config = PolicyConfig::new()
+///     .set_samples(10)
+///     .set_false_positive_ratio(0.001)
+///     .set_reset_interval(1_000_000)
+///     .set_sketch_depth(6);
+/// assert!(config.validate().is_ok());
+/// assert!(config.set_false_positive_ratio(1.5).validate().is_err());
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PolicyConfig {
+    pub(crate) samples: usize,
+    pub(crate) false_positive_ratio: f64,
+    pub(crate) reset_interval: Option<usize>,
+    pub(crate) sketch_depth: usize,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            samples: DEFAULT_SAMPLES,
+            false_positive_ratio: DEFAULT_FALSE_POSITIVE_RATIO,
+            reset_interval: None,
+            sketch_depth: DEFAULT_DEPTH,
+        }
+    }
+}
+
+impl PolicyConfig {
+    /// Create a new PolicyConfig with the default tuning.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of keys sampled by the default [`SampledLfu`] eviction policy to find
+    /// a victim. It is not used by the other eviction policies.
+    ///
+    /// Default is 5.
+    ///
+    /// [`SampledLfu`]: struct.SampledLfu.html
+    #[inline]
+    pub fn set_samples(self, samples: usize) -> Self {
+        Self { samples, ..self }
+    }
+
+    /// Set the false positive ratio of the doorkeeper, the bloom filter in front of the
+    /// count-min sketch of TinyLFU. A lower ratio takes more memory.
+    ///
+    /// Default is 0.01.
+    #[inline]
+    pub fn set_false_positive_ratio(self, false_positive_ratio: f64) -> Self {
+        Self {
+            false_positive_ratio,
+            ..self
+        }
+    }
+
+    /// Set the number of increments after which TinyLFU halves its counters and clears its
+    /// doorkeeper, so that the old accesses fade away.
+    ///
+    /// Default is `num_counters`.
+    #[inline]
+    pub fn set_reset_interval(self, reset_interval: usize) -> Self {
+        Self {
+            reset_interval: Some(reset_interval),
+            ..self
+        }
+    }
+
+    /// Set the number of rows of the count-min sketch of TinyLFU, between 1 and 16. More rows
+    /// estimate the frequencies with fewer collisions, but take more memory and time.
+    ///
+    /// Default is 4.
+    #[inline]
+    pub fn set_sketch_depth(self, sketch_depth: usize) -> Self {
+        Self {
+            sketch_depth,
+            ..self
+        }
+    }
+
+    /// Returns an error if one of the settings is out of its range.
+    pub fn validate(&self) -> Result<(), CacheError> {
+        if self.samples == 0 {
+            return Err(CacheError::InvalidSamples(self.samples));
+        }
+        if !(self.false_positive_ratio > 0.0 && self.false_positive_ratio < 1.0) {
+            return Err(CacheError::InvalidFalsePositiveRatio(
+                self.false_positive_ratio,
+            ));
+        }
+        if self.reset_interval == Some(0) {
+            return Err(CacheError::InvalidResetInterval(0));
+        }
+        if !(1..=MAX_SKETCH_DEPTH).contains(&self.sketch_depth) {
+            return Err(CacheError::InvalidSketchDepth(self.sketch_depth));
+        }
+        Ok(())
+    }
+}
+
+/// WARM_HITS is the fewest hits of a new key the jittered admission considers warm.
+const WARM_HITS: i64 = 6;
+
+/// Admission decides whether a new key is worth evicting the victim chosen by the eviction
+/// policy when the Cache is full. It is not used with an eviction policy which handles the
+/// admission by itself, like the [`WTinyLfu`].
+///
+/// [`WTinyLfu`]: struct.WTinyLfu.html
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Admission {
+    /// Admits all the new keys, for the caches whose inserts are under control.
+    Always,
+
+    /// Rejects the new keys which TinyLFU estimates as less frequently used than the victim.
+    #[default]
+    TinyLfu,
+
+    /// Like `TinyLfu`, but also admits a warm new key (read at least 6 times) less frequently
+    /// used than the victim with the given probability, clamped to `0.0..=1.0`. This prevents
+    /// an attacker from pinning the hot keys out of the Cache by flooding it with keys whose
+    /// hashes collide with them. Caffeine admits 1/128 of them.
+    TinyLfuJitter {
+        /// The probability to admit a warm new key which would be rejected.
+        probability: f64,
+    },
+}
+
+/// Decision is the outcome of an [`Admission`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Decision {
+    Admit,
+    /// The key is admitted by the jitter of `TinyLfuJitter`.
+    Jitter,
+    Reject,
+}
+
+impl Admission {
+    /// `decide` compares the hits of the new key with the ones of the victim.
+    pub(crate) fn decide(&self, inc_hits: i64, victim_hits: i64) -> Decision {
+        match *self {
+            Admission::Always => Decision::Admit,
+            _ if inc_hits >= victim_hits => Decision::Admit,
+            Admission::TinyLfuJitter { probability }
+                if inc_hits >= WARM_HITS && rand::random::<f64>() < probability.clamp(0.0, 1.0) =>
+            {
+                Decision::Jitter
+            }
+            _ => Decision::Reject,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default)]
@@ -221,47 +456,88 @@ impl<S: BuildHasher + Clone + 'static> PolicyInner<S> {
     #[inline]
     fn set_metrics(&mut self, metrics: Arc<Metrics>) {
         self.costs.metrics = metrics;
+        self.costs.track();
     }
 
     #[inline]
-    fn with_hasher(ctrs: usize, max_cost: i64, hasher: S) -> Result<Arc<Mutex<Self>>, CacheError> {
+    fn with_hasher(
+        ctrs: usize,
+        max_cost: i64,
+        hasher: S,
+        eviction: Box<dyn EvictionPolicy>,
+        config: PolicyConfig,
+    ) -> Result<Arc<Mutex<Self>>, CacheError> {
         let this = Self {
-            admit: TinyLFU::new(ctrs)?,
-            costs: SampledLFU::with_hasher(max_cost, hasher),
+            admit: TinyLFU::with_config(ctrs, &config)?,
+            admission: Admission::default(),
+            costs: CostLedger::with_hasher(max_cost, hasher),
+            eviction,
         };
         Ok(Arc::new(Mutex::new(this)))
     }
+
+    /// `victim` asks the eviction policy for the next key to evict.
+    #[inline]
+    fn victim(&mut self) -> Option<u64> {
+        let Self {
+            admit,
+            costs,
+            eviction,
+            ..
+        } = self;
+        eviction.victim(&mut costs.key_costs.keys().copied(), &|k| admit.estimate(k))
+    }
+
+    /// `access` applies a batch of reads to the admission and eviction policies.
+    #[inline]
+    fn access(&mut self, keys: Vec<u64>) {
+        keys.iter().for_each(|k| {
+            if self.costs.contains(k) {
+                self.eviction.access(*k)
+            } else {
+                self.eviction.miss(*k)
+            }
+        });
+        self.admit.increments(keys);
+    }
+
+    #[inline]
+    fn increment(&mut self, key: u64, cost: i64) {
+        self.costs.increment(key, cost);
+        self.eviction.insert(key, cost);
+    }
+
+    #[inline]
+    fn update(&mut self, key: &u64, cost: i64) -> bool {
+        let updated = self.costs.update(key, cost);
+        if updated {
+            self.eviction.update(*key, cost);
+        }
+        updated
+    }
+
+    #[inline]
+    fn remove(&mut self, key: &u64) -> Option<i64> {
+        self.eviction.remove(*key);
+        self.costs.remove(key)
+    }
 }
 
 unsafe impl<S: BuildHasher + Clone + 'static> Send for PolicyInner<S> {}
 unsafe impl<S: BuildHasher + Clone + 'static> Sync for PolicyInner<S> {}
 
-/// SampledLFU stores key-costs paris.
-pub(crate) struct SampledLFU<S = RandomState> {
-    samples: usize,
+/// CostLedger stores key-costs pairs.
+pub(crate) struct CostLedger<S = RandomState> {
     max_cost: AtomicI64,
     used: i64,
     key_costs: HashMap<u64, i64, S>,
     metrics: Arc<Metrics>,
 }
 
-impl SampledLFU {
-    /// Create a new SampledLFU
+impl CostLedger {
+    /// Create a new CostLedger
     pub fn new(max_cost: i64) -> Self {
         Self {
-            samples: DEFAULT_SAMPLES,
-            max_cost: AtomicI64::new(max_cost),
-            used: 0,
-            key_costs: HashMap::new(),
-            metrics: Arc::new(Metrics::new()),
-        }
-    }
-
-    /// Create a new SampledLFU with samples.
-    #[inline]
-    pub fn with_samples(max_cost: i64, samples: usize) -> Self {
-        Self {
-            samples,
             max_cost: AtomicI64::new(max_cost),
             used: 0,
             key_costs: HashMap::new(),
@@ -270,24 +546,11 @@ impl SampledLFU {
     }
 }
 
-impl<S: BuildHasher + Clone + 'static> SampledLFU<S> {
-    /// Create a new SampledLFU with specific hasher
+impl<S: BuildHasher + Clone + 'static> CostLedger<S> {
+    /// Create a new CostLedger with specific hasher
     #[inline]
     pub fn with_hasher(max_cost: i64, hasher: S) -> Self {
         Self {
-            samples: DEFAULT_SAMPLES,
-            max_cost: AtomicI64::new(max_cost),
-            used: 0,
-            key_costs: HashMap::with_hasher(hasher),
-            metrics: Arc::new(Metrics::Noop),
-        }
-    }
-
-    /// Create a new SampledLFU with samples and hasher
-    #[inline]
-    pub fn with_samples_and_hasher(max_cost: i64, samples: usize, hasher: S) -> Self {
-        Self {
-            samples,
             max_cost: AtomicI64::new(max_cost),
             used: 0,
             key_costs: HashMap::with_hasher(hasher),
@@ -299,6 +562,7 @@ impl<S: BuildHasher + Clone + 'static> SampledLFU<S> {
     #[inline]
     pub fn update_max_cost(&self, mc: i64) {
         self.max_cost.store(mc, Ordering::SeqCst);
+        self.track();
     }
 
     /// get the max_cost
@@ -307,41 +571,28 @@ impl<S: BuildHasher + Clone + 'static> SampledLFU<S> {
         self.max_cost.load(Ordering::SeqCst)
     }
 
-    /// get the remain space of SampledLRU
+    /// get the remain space of CostLedger
     #[inline]
     pub fn room_left(&self, cost: i64) -> i64 {
         self.get_max_cost() - (self.used + cost)
     }
 
-    /// try to fill the SampledLFU by the given pairs.
-    pub fn fill_sample(&mut self, mut pairs: Vec<PolicyPair>) -> Vec<PolicyPair> {
-        if pairs.len() >= self.samples {
-            pairs
-        } else {
-            for (k, v) in self.key_costs.iter() {
-                pairs.push(PolicyPair::new(*k, *v));
-                if pairs.len() >= self.samples {
-                    return pairs;
-                }
-            }
-            pairs
-        }
-    }
-
-    /// Put a hashed key and cost to SampledLFU
+    /// Put a hashed key and cost to CostLedger
     #[inline]
     pub fn increment(&mut self, key: u64, cost: i64) {
         self.key_costs.insert(key, cost);
         self.used += cost;
+        self.track();
     }
 
-    /// Remove an entry from SampledLFU by hashed key
+    /// Remove an entry from CostLedger by hashed key
     #[inline]
     pub fn remove(&mut self, kh: &u64) -> Option<i64> {
-        self.key_costs.remove(kh).map(|cost| {
+        let cost = self.key_costs.remove(kh).inspect(|cost| {
             self.used -= cost;
-            cost
-        })
+        });
+        self.track();
+        cost
     }
 
     #[inline]
@@ -349,14 +600,21 @@ impl<S: BuildHasher + Clone + 'static> SampledLFU<S> {
         self.key_costs.contains_key(k)
     }
 
-    /// Clear the SampledLFU
+    /// Clear the CostLedger
     #[inline]
     pub fn clear(&mut self) {
         self.used = 0;
         self.key_costs.clear();
+        self.track();
+    }
+
+    /// `track` reports the used and the max cost to the metrics.
+    #[inline]
+    fn track(&self) {
+        self.metrics.track_cost(self.used, self.get_max_cost());
     }
 
-    /// Update the cost by hashed key. If the provided key in SampledLFU, then update it and return true, otherwise false.
+    /// Update the cost by hashed key. If the provided key in CostLedger, then update it and return true, otherwise false.
     #[inline]
     pub fn update(&mut self, k: &u64, cost: i64) -> bool {
         // Update the cost of an existing key, but don't worry about evicting.
@@ -383,14 +641,21 @@ impl<S: BuildHasher + Clone + 'static> SampledLFU<S> {
 
                 self.used += cost - prev_val;
                 *prev = cost;
+                self.track();
                 true
             }
         }
     }
 }
 
-unsafe impl<S: BuildHasher + Clone + 'static> Send for SampledLFU<S> {}
-unsafe impl<S: BuildHasher + Clone + 'static> Sync for SampledLFU<S> {}
+unsafe impl<S: BuildHasher + Clone + 'static> Send for CostLedger<S> {}
+unsafe impl<S: BuildHasher + Clone + 'static> Sync for CostLedger<S> {}
+
+/// The magic bytes of an exported policy state.
+const POLICY_STATE_MAGIC: &[u8; 4] = b"STPS";
+
+/// The version of the policy state format, bumped on every incompatible change.
+const POLICY_STATE_VERSION: u8 = 1;
 
 /// TinyLFU is an admission helper that keeps track of access frequency using
 /// tiny (4-bit) counters in the form of a count-min sketch.
@@ -405,10 +670,16 @@ impl TinyLFU {
     /// The constructor of TinyLFU
     #[inline]
     pub fn new(num_ctrs: usize) -> Result<Self, CacheError> {
+        Self::with_config(num_ctrs, &PolicyConfig::default())
+    }
+
+    /// Create a new TinyLFU with the sketch tuning of the config.
+    pub fn with_config(num_ctrs: usize, config: &PolicyConfig) -> Result<Self, CacheError> {
+        config.validate()?;
         Ok(Self {
-            ctr: CountMinSketch::new(num_ctrs as u64)?,
-            doorkeeper: Bloom::new(num_ctrs, 0.01),
-            samples: num_ctrs,
+            ctr: CountMinSketch::with_depth(num_ctrs as u64, config.sketch_depth)?,
+            doorkeeper: Bloom::new(num_ctrs, config.false_positive_ratio),
+            samples: config.reset_interval.unwrap_or(num_ctrs),
             w: 0,
         })
     }
@@ -418,9 +689,9 @@ impl TinyLFU {
     /// # Details
     /// Explanation from [TinyLFU: A Highly Efficient Cache Admission Policy §3.4.2]:
     /// - When querying items, we use both the Doorkeeper and the main structures.
-    /// That is, if the item is included in the Doorkeeper,
-    /// TinyLFU estimates the frequency of this item as its estimation in the main structure plus 1.
-    /// Otherwise, TinyLFU returns just the estimation from the main structure.
+    ///   That is, if the item is included in the Doorkeeper,
+    ///   TinyLFU estimates the frequency of this item as its estimation in the main structure plus 1.
+    ///   Otherwise, TinyLFU returns just the estimation from the main structure.
     ///
     /// [TinyLFU: A Highly Efficient Cache Admission Policy §3.4.2]: https://arxiv.org/pdf/1512.00727.pdf
     #[inline]
@@ -496,4 +767,46 @@ impl TinyLFU {
     pub fn contains(&self, kh: u64) -> bool {
         self.doorkeeper.contains(kh)
     }
+
+    /// `export_state` encodes the access history: the magic and the version of the format,
+    /// the reset counter, the count-min sketch and the doorkeeper, in little-endian.
+    pub fn export_state(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(POLICY_STATE_MAGIC);
+        buf.push(POLICY_STATE_VERSION);
+        buf.extend_from_slice(&(self.w as u64).to_le_bytes());
+        self.ctr.encode(&mut buf);
+        self.doorkeeper.encode(&mut buf);
+        buf
+    }
+
+    /// `import_state` replaces the access history with the one of [`export_state`]. The state
+    /// is left untouched if it cannot be decoded.
+    ///
+    /// [`export_state`]: struct.TinyLFU.html#method.export_state
+    pub fn import_state(&mut self, state: &[u8]) -> Result<(), CacheError> {
+        let mut r = StateReader::new(state);
+        if r.bytes(POLICY_STATE_MAGIC.len()).ok() != Some(&POLICY_STATE_MAGIC[..]) {
+            return Err(CacheError::InvalidPolicyState(
+                "not a policy state".to_string(),
+            ));
+        }
+        let version = r.u8()?;
+        if version != POLICY_STATE_VERSION {
+            return Err(CacheError::InvalidPolicyState(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let w = r.u64()? as usize;
+        let ctr = self.ctr.decode(&mut r)?;
+        let doorkeeper = self.doorkeeper.decode(&mut r)?;
+        r.finish()?;
+
+        self.w = w.min(self.samples);
+        self.ctr = ctr;
+        self.doorkeeper = doorkeeper;
+        Ok(())
+    }
 }
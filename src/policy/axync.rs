@@ -1,5 +1,7 @@
-use crate::axync::{select, stop_channel, unbounded, Receiver, RecvError, Sender};
-use crate::policy::PolicyInner;
+use crate::axync::{bounded, select, stop_channel, Receiver, RecvError, Sender, TrySendError};
+use crate::eviction::{EvictionPolicy, SampledLfu};
+use crate::policy::{PolicyConfig, PolicyInner, ITEMS_BUFFER_SIZE};
+use crate::ring::RingConsumer;
 use crate::{CacheError, MetricType, Metrics};
 use futures::future::{BoxFuture, FutureExt};
 use parking_lot::Mutex;
@@ -11,6 +13,9 @@ use std::sync::Arc;
 pub(crate) struct AsyncLFUPolicy<S = RandomState> {
     pub(crate) inner: Arc<Mutex<PolicyInner<S>>>,
     pub(crate) items_tx: Sender<Vec<u64>>,
+    /// items_rx lets the batches be flushed, the policy processor drops it when it stops
+    /// so that the channel is disconnected.
+    pub(crate) items_rx: Arc<Mutex<Option<Receiver<Vec<u64>>>>>,
     pub(crate) stop_tx: Sender<()>,
     pub(crate) is_closed: AtomicBool,
     pub(crate) metrics: Arc<Metrics>,
@@ -22,7 +27,16 @@ impl AsyncLFUPolicy {
     where
         SP: Fn(BoxFuture<'static, ()>) -> R + Send + Sync + 'static,
     {
-        Self::with_hasher(ctrs, max_cost, RandomState::new(), spawner)
+        let eviction = Box::new(SampledLfu::new());
+        let config = PolicyConfig::default();
+        Self::with_hasher(
+            ctrs,
+            max_cost,
+            RandomState::new(),
+            eviction,
+            config,
+            spawner,
+        )
     }
 }
 
@@ -32,23 +46,29 @@ impl<S: BuildHasher + Clone + 'static + Send> AsyncLFUPolicy<S> {
         ctrs: usize,
         max_cost: i64,
         hasher: S,
+        eviction: Box<dyn EvictionPolicy>,
+        config: PolicyConfig,
         spawner: SP,
     ) -> Result<Self, CacheError>
     where
         SP: Fn(BoxFuture<'static, ()>) -> R + Send + Sync + 'static,
     {
-        let inner = PolicyInner::with_hasher(ctrs, max_cost, hasher)?;
+        let inner = PolicyInner::with_hasher(ctrs, max_cost, hasher, eviction, config)?;
 
-        let (items_tx, items_rx) = unbounded();
+        let (items_tx, items_rx) = bounded(ITEMS_BUFFER_SIZE);
         let (stop_tx, stop_rx) = stop_channel();
 
-        PolicyProcessor::new(inner.clone(), items_rx, stop_rx).spawn(Box::new(move |fut| {
-            spawner(fut);
-        }));
+        let shared_rx = Arc::new(Mutex::new(Some(items_rx.clone())));
+        PolicyProcessor::new(inner.clone(), items_rx, shared_rx.clone(), stop_rx).spawn(Box::new(
+            move |fut| {
+                spawner(fut);
+            },
+        ));
 
         let this = Self {
             inner,
             items_tx,
+            items_rx: shared_rx,
             stop_tx,
             is_closed: AtomicBool::new(false),
             metrics: Arc::new(Metrics::new()),
@@ -99,9 +119,52 @@ impl<S: BuildHasher + Clone + 'static + Send> AsyncLFUPolicy<S> {
     }
 }
 
+impl<S: BuildHasher + Clone + 'static> AsyncLFUPolicy<S> {
+    /// `try_push` is the non-blocking version of [`push`], it is used on the
+    /// synchronous read path of the cache.
+    ///
+    /// [`push`]: struct.AsyncLFUPolicy.html#method.push
+    pub fn try_push(&self, keys: Vec<u64>) -> Result<bool, CacheError> {
+        if self.is_closed.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        let num_of_keys = keys.len() as u64;
+        if num_of_keys == 0 {
+            return Ok(true);
+        }
+        let first = keys[0];
+
+        match self.items_tx.try_send(keys) {
+            Ok(_) => {
+                self.metrics.add(MetricType::KeepGets, first, num_of_keys);
+                Ok(true)
+            }
+            Err(TrySendError::Full(_)) => {
+                self.metrics.add(MetricType::DropGets, first, num_of_keys);
+                Ok(false)
+            }
+            Err(TrySendError::Closed(e)) => {
+                self.metrics.add(MetricType::DropGets, first, num_of_keys);
+                Err(CacheError::SendError(format!(
+                    "sending on a disconnected channel, msg: {:?}",
+                    e
+                )))
+            }
+        }
+    }
+}
+
+impl<S: BuildHasher + Clone + 'static> RingConsumer for AsyncLFUPolicy<S> {
+    #[inline]
+    fn push(&self, keys: Vec<u64>) -> bool {
+        self.try_push(keys).unwrap_or(false)
+    }
+}
+
 pub(crate) struct PolicyProcessor<S> {
     inner: Arc<Mutex<PolicyInner<S>>>,
     items_rx: Receiver<Vec<u64>>,
+    shared_rx: Arc<Mutex<Option<Receiver<Vec<u64>>>>>,
     stop_rx: Receiver<()>,
 }
 
@@ -110,11 +173,13 @@ impl<S: BuildHasher + Clone + 'static + Send> PolicyProcessor<S> {
     fn new(
         inner: Arc<Mutex<PolicyInner<S>>>,
         items_rx: Receiver<Vec<u64>>,
+        shared_rx: Arc<Mutex<Option<Receiver<Vec<u64>>>>>,
         stop_rx: Receiver<()>,
     ) -> Self {
         Self {
             inner,
             items_rx,
+            shared_rx,
             stop_rx,
         }
     }
@@ -126,6 +191,7 @@ impl<S: BuildHasher + Clone + 'static + Send> PolicyProcessor<S> {
                 select! {
                     items = self.items_rx.recv().fuse() => self.handle_items(items),
                     _ = self.stop_rx.recv().fuse() => {
+                        self.shared_rx.lock().take();
                         drop(self);
                         return;
                     },
@@ -140,7 +206,7 @@ impl<S: BuildHasher + Clone + 'static + Send> PolicyProcessor<S> {
         match items {
             Ok(items) => {
                 let mut inner = self.inner.lock();
-                inner.admit.increments(items);
+                inner.access(items);
             }
             Err(_) => {
                 // error!("policy processor error")
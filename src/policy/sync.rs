@@ -1,8 +1,7 @@
-use crate::policy::PolicyInner;
-use crate::sync::{
-    select, spawn, stop_channel, unbounded, JoinHandle, Receiver, Sender, UnboundedReceiver,
-    UnboundedSender,
-};
+use crate::eviction::{EvictionPolicy, SampledLfu};
+use crate::policy::{PolicyConfig, PolicyInner, ITEMS_BUFFER_SIZE};
+use crate::ring::RingConsumer;
+use crate::sync::{bounded, select, spawn, stop_channel, JoinHandle, Receiver, Sender};
 use crate::{CacheError, MetricType, Metrics};
 use crossbeam_channel::RecvError;
 use parking_lot::Mutex;
@@ -13,7 +12,10 @@ use std::sync::Arc;
 
 pub(crate) struct LFUPolicy<S = RandomState> {
     pub(crate) inner: Arc<Mutex<PolicyInner<S>>>,
-    pub(crate) items_tx: UnboundedSender<Vec<u64>>,
+    pub(crate) items_tx: Sender<Vec<u64>>,
+    /// items_rx lets the batches be flushed, the policy processor drops it when it stops
+    /// so that the channel is disconnected.
+    pub(crate) items_rx: Arc<Mutex<Option<Receiver<Vec<u64>>>>>,
     pub(crate) stop_tx: Sender<()>,
     pub(crate) is_closed: AtomicBool,
     pub(crate) metrics: Arc<Metrics>,
@@ -22,23 +24,33 @@ pub(crate) struct LFUPolicy<S = RandomState> {
 impl LFUPolicy {
     #[inline]
     pub(crate) fn new(ctrs: usize, max_cost: i64) -> Result<Self, CacheError> {
-        Self::with_hasher(ctrs, max_cost, RandomState::new())
+        let eviction = Box::new(SampledLfu::new());
+        let config = PolicyConfig::default();
+        Self::with_hasher(ctrs, max_cost, RandomState::new(), eviction, config)
     }
 }
 
 impl<S: BuildHasher + Clone + 'static> LFUPolicy<S> {
     #[inline]
-    pub fn with_hasher(ctrs: usize, max_cost: i64, hasher: S) -> Result<Self, CacheError> {
-        let inner = PolicyInner::with_hasher(ctrs, max_cost, hasher)?;
-
-        let (items_tx, items_rx) = unbounded();
+    pub fn with_hasher(
+        ctrs: usize,
+        max_cost: i64,
+        hasher: S,
+        eviction: Box<dyn EvictionPolicy>,
+        config: PolicyConfig,
+    ) -> Result<Self, CacheError> {
+        let inner = PolicyInner::with_hasher(ctrs, max_cost, hasher, eviction, config)?;
+
+        let (items_tx, items_rx) = bounded(ITEMS_BUFFER_SIZE);
         let (stop_tx, stop_rx) = stop_channel();
 
-        PolicyProcessor::new(inner.clone(), items_rx, stop_rx).spawn();
+        let shared_rx = Arc::new(Mutex::new(Some(items_rx.clone())));
+        PolicyProcessor::new(inner.clone(), items_rx, shared_rx.clone(), stop_rx).spawn();
 
         let this = Self {
             inner,
             items_tx,
+            items_rx: shared_rx,
             stop_tx,
             is_closed: AtomicBool::new(false),
             metrics: Arc::new(Metrics::new()),
@@ -89,9 +101,17 @@ impl<S: BuildHasher + Clone + 'static> LFUPolicy<S> {
     }
 }
 
+impl<S: BuildHasher + Clone + 'static> RingConsumer for LFUPolicy<S> {
+    #[inline]
+    fn push(&self, keys: Vec<u64>) -> bool {
+        LFUPolicy::push(self, keys).unwrap_or(false)
+    }
+}
+
 pub(crate) struct PolicyProcessor<S> {
     inner: Arc<Mutex<PolicyInner<S>>>,
-    items_rx: UnboundedReceiver<Vec<u64>>,
+    items_rx: Receiver<Vec<u64>>,
+    shared_rx: Arc<Mutex<Option<Receiver<Vec<u64>>>>>,
     stop_rx: Receiver<()>,
 }
 
@@ -99,12 +119,14 @@ impl<S: BuildHasher + Clone + 'static> PolicyProcessor<S> {
     #[inline]
     fn new(
         inner: Arc<Mutex<PolicyInner<S>>>,
-        items_rx: UnboundedReceiver<Vec<u64>>,
+        items_rx: Receiver<Vec<u64>>,
+        shared_rx: Arc<Mutex<Option<Receiver<Vec<u64>>>>>,
         stop_rx: Receiver<()>,
     ) -> Self {
         Self {
             inner,
             items_rx,
+            shared_rx,
             stop_rx,
         }
     }
@@ -115,6 +137,7 @@ impl<S: BuildHasher + Clone + 'static> PolicyProcessor<S> {
             select! {
                 recv(self.items_rx) -> items => self.handle_items(items),
                 recv(self.stop_rx) -> _ => {
+                    self.shared_rx.lock().take();
                     drop(self);
                     return;
                 },
@@ -127,7 +150,7 @@ impl<S: BuildHasher + Clone + 'static> PolicyProcessor<S> {
         match items {
             Ok(items) => {
                 let mut inner = self.inner.lock();
-                inner.admit.increments(items);
+                inner.access(items);
             }
             #[cfg(feature = "log")]
             Err(e) => {
@@ -1,5 +1,8 @@
+use crate::eviction::{EvictionPolicy, Lru};
 use crate::metrics::Metrics;
-use crate::policy::{LFUPolicy, SampledLFU, TinyLFU};
+use crate::policy::{
+    Admission, CostLedger, Decision, LFUPolicy, PolicyConfig, TinyLFU, ITEMS_BUFFER_SIZE,
+};
 use std::collections::hash_map::RandomState;
 use std::sync::Arc;
 use std::time::Duration;
@@ -19,6 +22,17 @@ fn test_policy_metrics() {
     p.collect_metrics(Arc::new(Metrics::new_op()));
     assert!(p.metrics.is_op());
     assert!(p.inner.lock().costs.metrics.is_op());
+    assert_eq!(p.metrics.get_max_cost(), Some(10));
+
+    p.add(1, 4);
+    p.add(2, 3);
+    p.update(&2, 5);
+    p.remove(&1);
+    assert_eq!(p.metrics.get_cost_used(), Some(5));
+    p.update_max_cost(20);
+    assert_eq!(p.metrics.get_max_cost(), Some(20));
+    p.clear();
+    assert_eq!(p.metrics.get_cost_used(), Some(0));
 }
 
 #[test]
@@ -53,6 +67,21 @@ fn test_policy_push() {
     assert_ne!(0, keep_count);
 }
 
+#[test]
+fn test_policy_push_full() {
+    let mut p = LFUPolicy::new(100, 10).unwrap();
+    p.collect_metrics(Arc::new(Metrics::new_op()));
+
+    // the policy processor is stuck on the lock, at most one batch more than the buffer
+    // is accepted.
+    let inner = p.inner.lock();
+    let kept = (0..10).filter(|_| p.push(vec![1, 2]).unwrap()).count();
+    drop(inner);
+    assert!((ITEMS_BUFFER_SIZE..=ITEMS_BUFFER_SIZE + 1).contains(&kept));
+    assert_eq!(p.metrics.get_gets_kept(), Some(2 * kept as u64));
+    assert_eq!(p.metrics.get_gets_dropped(), Some(2 * (10 - kept) as u64));
+}
+
 #[test]
 fn test_policy_add() {
     let p = LFUPolicy::new(1000, 100).unwrap();
@@ -84,6 +113,21 @@ fn test_policy_add() {
     assert!(!added);
 }
 
+#[test]
+fn test_policy_warm() {
+    let p = LFUPolicy::new(1000, 100).unwrap();
+    assert!(p.warm(1, 60, 3));
+    assert!(!p.warm(1, 10, 0));
+    assert!(!p.warm(2, 50, 0));
+    assert!(p.warm(3, 40, 0));
+    assert_eq!(p.cap(), 0);
+
+    let inner = p.inner.lock();
+    assert_eq!(inner.admit.estimate(1), 3);
+    assert_eq!(inner.admit.estimate(3), 0);
+    assert_eq!(inner.costs.key_costs.get(&1), Some(&60));
+}
+
 #[test]
 fn test_policy_has() {
     let p = LFUPolicy::new(100, 10).unwrap();
@@ -118,6 +162,117 @@ fn test_policy_update() {
     assert_eq!(inner.costs.key_costs.get(&1).unwrap(), &2);
 }
 
+#[test]
+fn test_policy_evict_overflow() {
+    let p = LFUPolicy::new(100, 10).unwrap();
+    (1..=5).for_each(|k| {
+        p.add(k, 2);
+    });
+    p.inner.lock().admit.increments(vec![1, 1, 2, 2, 3, 3]);
+    assert!(p.evict_overflow().is_empty());
+
+    // the keys with the fewest hits go first.
+    p.update_max_cost(6);
+    let mut victims = p.evict_overflow().iter().map(|v| v.key).collect::<Vec<_>>();
+    victims.sort_unstable();
+    assert_eq!(victims, vec![4, 5]);
+    assert_eq!(p.cap(), 0);
+}
+
+#[test]
+fn test_policy_eviction_policy() {
+    let eviction = Box::new(Lru::new());
+    let config = PolicyConfig::default();
+    let p = LFUPolicy::with_hasher(100, 3, RandomState::new(), eviction, config).unwrap();
+    (1..=3).for_each(|k| {
+        p.add(k, 1);
+    });
+    p.inner.lock().access(vec![1]);
+
+    // 2 is the least recently used key, 4 is as popular as it.
+    let (victims, added) = p.add(4, 1);
+    assert!(added);
+    assert_eq!(victims.unwrap()[0].key, 2);
+    assert!(!p.contains(&2));
+    assert!(p.evict_overflow().is_empty());
+}
+
+/// StrayVictim always names a victim which the policy does not track.
+struct StrayVictim;
+
+impl EvictionPolicy for StrayVictim {
+    fn insert(&mut self, _key: u64, _cost: i64) {}
+
+    fn remove(&mut self, _key: u64) {}
+
+    fn victim(
+        &mut self,
+        _keys: &mut dyn Iterator<Item = u64>,
+        _frequency: &dyn Fn(u64) -> i64,
+    ) -> Option<u64> {
+        Some(u64::MAX)
+    }
+
+    fn clear(&mut self) {}
+}
+
+#[test]
+fn test_policy_stray_victim() {
+    let config = PolicyConfig::default();
+    let p =
+        LFUPolicy::with_hasher(100, 2, RandomState::new(), Box::new(StrayVictim), config).unwrap();
+    p.set_admission(Admission::Always);
+    (1..=2).for_each(|k| {
+        p.add(k, 1);
+    });
+
+    // the stray victim frees no room, the new key is rejected instead of looping.
+    let (victims, added) = p.add(3, 1);
+    assert!(!added);
+    assert!(victims.unwrap().is_empty());
+
+    p.update_max_cost(1);
+    assert!(p.evict_overflow().is_empty());
+    assert_eq!(p.cap(), -1);
+}
+
+#[test]
+fn test_policy_admission() {
+    let mut p = LFUPolicy::new(100, 2).unwrap();
+    p.collect_metrics(Arc::new(Metrics::new_op()));
+    (1..=2).for_each(|k| {
+        p.add(k, 1);
+    });
+    p.inner.lock().admit.increments(vec![1, 1, 2, 2]);
+
+    let (_, added) = p.add(3, 3);
+    assert!(!added);
+    let (_, added) = p.add(3, 1);
+    assert!(!added);
+    assert_eq!(p.metrics.get_sets_rejected_cost(), Some(1));
+    assert_eq!(p.metrics.get_sets_rejected_frequency(), Some(1));
+    assert_eq!(p.metrics.get_sets_rejected(), Some(2));
+
+    p.set_admission(Admission::Always);
+    let (victims, added) = p.add(3, 1);
+    assert!(added);
+    assert_eq!(victims.unwrap().len(), 1);
+}
+
+#[test]
+fn test_admission_decide() {
+    assert_eq!(Admission::Always.decide(0, 10), Decision::Admit);
+    assert_eq!(Admission::TinyLfu.decide(3, 3), Decision::Admit);
+    assert_eq!(Admission::TinyLfu.decide(6, 10), Decision::Reject);
+
+    // only the warm keys are admitted by the jitter.
+    let jitter = Admission::TinyLfuJitter { probability: 1.0 };
+    assert_eq!(jitter.decide(6, 10), Decision::Jitter);
+    assert_eq!(jitter.decide(5, 10), Decision::Reject);
+    let never = Admission::TinyLfuJitter { probability: 0.0 };
+    assert_eq!(never.decide(6, 10), Decision::Reject);
+}
+
 #[test]
 fn test_policy_cost() {
     let p = LFUPolicy::new(100, 10).unwrap();
@@ -190,10 +345,11 @@ mod async_test {
         p.push(vec![1, 2, 2]).await.unwrap();
         sleep(WAIT).await;
 
-        let inner = p.inner.lock();
-        assert_eq!(inner.admit.estimate(2), 2);
-        assert_eq!(inner.admit.estimate(1), 1);
-        drop(inner);
+        {
+            let inner = p.inner.lock();
+            assert_eq!(inner.admit.estimate(2), 2);
+            assert_eq!(inner.admit.estimate(1), 1);
+        }
 
         p.stop_tx.send(()).await.unwrap();
         sleep(WAIT).await;
@@ -329,14 +485,14 @@ mod async_test {
 }
 
 #[test]
-fn test_sampled_lfu_constructor() {
-    let _ = SampledLFU::with_samples_and_hasher(100, 100, RandomState::new());
-    let _ = SampledLFU::with_samples(100, 100);
+fn test_cost_ledger_constructor() {
+    let _ = CostLedger::with_hasher(100, RandomState::new());
+    let _ = CostLedger::new(100);
 }
 
 #[test]
-fn test_sampled_lfu_remove() {
-    let mut lfu = SampledLFU::new(4);
+fn test_cost_ledger_remove() {
+    let mut lfu = CostLedger::new(4);
     lfu.increment(1, 1);
     lfu.increment(2, 2);
     assert_eq!(lfu.remove(&2), Some(2));
@@ -346,8 +502,8 @@ fn test_sampled_lfu_remove() {
 }
 
 #[test]
-fn test_sampled_lfu_room() {
-    let mut l = SampledLFU::new(16);
+fn test_cost_ledger_room() {
+    let mut l = CostLedger::new(16);
     l.increment(1, 1);
     l.increment(2, 2);
     l.increment(3, 3);
@@ -355,8 +511,8 @@ fn test_sampled_lfu_room() {
 }
 
 #[test]
-fn test_sampled_lfu_clear() {
-    let mut l = SampledLFU::new(4);
+fn test_cost_ledger_clear() {
+    let mut l = CostLedger::new(4);
     l.increment(1, 1);
     l.increment(2, 2);
     l.increment(3, 3);
@@ -366,8 +522,8 @@ fn test_sampled_lfu_clear() {
 }
 
 #[test]
-fn test_sampled_lfu_update() {
-    let mut l = SampledLFU::new(5);
+fn test_cost_ledger_update() {
+    let mut l = CostLedger::new(5);
     l.increment(1, 1);
     l.increment(2, 2);
     assert!(l.update(&1, 2));
@@ -378,20 +534,29 @@ fn test_sampled_lfu_update() {
 }
 
 #[test]
-fn test_sampled_lfu_fill_sample() {
-    let mut l = SampledLFU::new(16);
-    l.increment(4, 4);
-    l.increment(5, 5);
-    let sample = l.fill_sample(vec![(1, 1).into(), (2, 2).into(), (3, 3).into()]);
-    let k = sample[sample.len() - 1].key;
-    assert_eq!(5, sample.len());
-    assert_ne!(1, k);
-    assert_ne!(2, k);
-    assert_ne!(3, k);
-    assert_eq!(sample.len(), l.fill_sample(sample.clone()).len());
-    l.remove(&5);
-    let sample = l.fill_sample(sample[0..(sample.len() - 2)].to_vec());
-    assert_eq!(4, sample.len())
+fn test_policy_config() {
+    assert!(PolicyConfig::new().validate().is_ok());
+    let invalid = [
+        PolicyConfig::new().set_samples(0),
+        PolicyConfig::new().set_false_positive_ratio(0.0),
+        PolicyConfig::new().set_false_positive_ratio(1.0),
+        PolicyConfig::new().set_reset_interval(0),
+        PolicyConfig::new().set_sketch_depth(0),
+        PolicyConfig::new().set_sketch_depth(17),
+    ];
+    for config in invalid {
+        assert!(TinyLFU::with_config(16, &config).is_err());
+    }
+
+    // the counters are halved every 4 increments.
+    let config = PolicyConfig::new()
+        .set_reset_interval(4)
+        .set_sketch_depth(2);
+    let mut l = TinyLFU::with_config(16, &config).unwrap();
+    l.increments(vec![1, 1, 1]);
+    assert_eq!(l.estimate(1), 3);
+    l.increment(1);
+    assert_eq!(l.estimate(1), 1);
 }
 
 #[test]
@@ -432,6 +597,45 @@ fn test_tinylfu_increments() {
     assert_eq!(6, l.w);
 }
 
+#[test]
+fn test_tinylfu_state() {
+    let mut l = TinyLFU::new(64).unwrap();
+    l.increments([1, 2, 2, 3, 3, 3].to_vec());
+    let state = l.export_state();
+
+    let mut restored = TinyLFU::new(64).unwrap();
+    restored.import_state(&state).unwrap();
+    assert_eq!(restored.estimate(1), 1);
+    assert_eq!(restored.estimate(2), 2);
+    assert_eq!(restored.estimate(3), 3);
+    assert_eq!(restored.estimate(4), 0);
+    assert_eq!(restored.w, 6);
+    assert_eq!(restored.export_state(), state);
+
+    let mut bad_magic = state.clone();
+    bad_magic[0] = b'X';
+    let mut bad_version = state.clone();
+    bad_version[4] = 2;
+    let mut trailing = state.clone();
+    trailing.push(0);
+    let invalid = [
+        Vec::new(),
+        bad_magic,
+        bad_version,
+        state[..state.len() - 1].to_vec(),
+        trailing,
+        TinyLFU::new(128).unwrap().export_state(),
+        TinyLFU::with_config(64, &PolicyConfig::new().set_sketch_depth(2))
+            .unwrap()
+            .export_state(),
+    ];
+    for state in invalid {
+        assert!(restored.import_state(&state).is_err());
+    }
+    assert_eq!(restored.estimate(3), 3);
+    assert_eq!(restored.w, 6);
+}
+
 #[test]
 fn test_tinylfu_clear() {
     let mut l = TinyLFU::new(16).unwrap();
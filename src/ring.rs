@@ -0,0 +1,171 @@
+//! This mod implements a lossy, striped ring buffer for batching read accesses.
+//!
+//! This file is a translation of the reference Golang code, available at <https://github.com/dgraph-io/ristretto/blob/master/ring.go>
+//!
+//! Instead of a `sync.Pool`, the stripes are a fixed set of mutex-guarded buffers.
+//! A push never blocks: if every stripe is contended, the key is dropped on the floor,
+//! which is fine because the access information is only used by the admission policy.
+use parking_lot::Mutex;
+use std::mem;
+use std::sync::Arc;
+
+/// NUM_OF_STRIPES is the number of stripes a RingBuffer is made of.
+const NUM_OF_STRIPES: usize = 16;
+
+/// RingConsumer is the user-defined object responsible for receiving and
+/// processing items in batches when buffers are drained.
+pub(crate) trait RingConsumer {
+    /// `push` consumes a batch of key hashes, returns false if the batch was dropped.
+    fn push(&self, keys: Vec<u64>) -> bool;
+}
+
+/// RingBuffer stores multiple buffers (stripes) and distributes Pushed items
+/// between them to lower contention.
+///
+/// This implements the "batching" process described in the BP-Wrapper paper
+/// (section III part A).
+pub(crate) struct RingBuffer<P> {
+    stripes: Box<[Mutex<Vec<u64>>]>,
+    capa: usize,
+    consumer: Arc<P>,
+}
+
+impl<P: RingConsumer> RingBuffer<P> {
+    /// Create a new RingBuffer, each stripe holds `capa` key hashes before
+    /// they are handed to the consumer.
+    pub(crate) fn new(consumer: Arc<P>, capa: usize) -> Self {
+        let stripes = (0..NUM_OF_STRIPES)
+            .map(|_| Mutex::new(Vec::with_capacity(capa)))
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+
+        Self {
+            stripes,
+            capa,
+            consumer,
+        }
+    }
+
+    /// `push` adds an element to one of the internal stripes and possibly drains if
+    /// the stripe becomes full.
+    pub(crate) fn push(&self, key: u64) {
+        let start = (key as usize) % NUM_OF_STRIPES;
+        for i in 0..NUM_OF_STRIPES {
+            let mut stripe = match self.stripes[(start + i) % NUM_OF_STRIPES].try_lock() {
+                Some(stripe) => stripe,
+                None => continue,
+            };
+
+            stripe.push(key);
+            if stripe.len() < self.capa {
+                return;
+            }
+
+            let batch = mem::replace(&mut *stripe, Vec::with_capacity(self.capa));
+            drop(stripe);
+            // The consumer decides whether to keep the batch; either way the
+            // stripe has already been replaced, so nothing to do here.
+            self.consumer.push(batch);
+            return;
+        }
+    }
+
+    /// `flush` hands the keys of every stripe to the consumer, even if the stripe is not full.
+    pub(crate) fn flush(&self) {
+        for stripe in self.stripes.iter() {
+            let batch = mem::take(&mut *stripe.lock());
+            if !batch.is_empty() {
+                self.consumer.push(batch);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ring::{RingBuffer, RingConsumer, NUM_OF_STRIPES};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct TestConsumer {
+        keep: bool,
+        batches: Mutex<Vec<Vec<u64>>>,
+    }
+
+    impl RingConsumer for TestConsumer {
+        fn push(&self, keys: Vec<u64>) -> bool {
+            self.batches.lock().push(keys);
+            self.keep
+        }
+    }
+
+    #[test]
+    fn test_ring_drain() {
+        let consumer = Arc::new(TestConsumer {
+            keep: true,
+            ..Default::default()
+        });
+        let r = RingBuffer::new(consumer.clone(), 4);
+        (0..3).for_each(|_| r.push(1));
+        assert!(consumer.batches.lock().is_empty());
+
+        r.push(1);
+        assert_eq!(consumer.batches.lock().as_slice(), &[vec![1, 1, 1, 1]]);
+    }
+
+    #[test]
+    fn test_ring_flush() {
+        let consumer = Arc::new(TestConsumer {
+            keep: true,
+            ..Default::default()
+        });
+        let r = RingBuffer::new(consumer.clone(), 4);
+        r.push(1);
+        r.push(2);
+        r.flush();
+
+        // only the stripes holding keys are handed out.
+        assert_eq!(consumer.batches.lock().as_slice(), &[vec![1], vec![2]]);
+        r.flush();
+        assert_eq!(consumer.batches.lock().len(), 2);
+    }
+
+    #[test]
+    fn test_ring_reset() {
+        let consumer = Arc::new(TestConsumer::default());
+        let r = RingBuffer::new(consumer.clone(), 4);
+        (0..8).for_each(|_| r.push(3));
+
+        // dropped batches must not be handed out again.
+        assert_eq!(
+            consumer.batches.lock().as_slice(),
+            &[vec![3, 3, 3, 3], vec![3, 3, 3, 3]]
+        );
+    }
+
+    #[test]
+    fn test_ring_contended_stripe() {
+        let consumer = Arc::new(TestConsumer {
+            keep: true,
+            ..Default::default()
+        });
+        let r = RingBuffer::new(consumer.clone(), 2);
+
+        // the home stripe of key 0 is busy, so the push spills to the next one.
+        let busy = r.stripes[0].lock();
+        r.push(0);
+        assert_eq!(r.stripes[1].lock().as_slice(), &[0]);
+
+        // every stripe busy, the key is dropped on the floor.
+        let others = r.stripes[1..NUM_OF_STRIPES]
+            .iter()
+            .map(|s| s.lock())
+            .collect::<Vec<_>>();
+        r.push(0);
+        drop(others);
+        drop(busy);
+        assert_eq!(r.stripes[1].lock().as_slice(), &[0]);
+        assert!(consumer.batches.lock().is_empty());
+    }
+}
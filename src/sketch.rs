@@ -4,12 +4,14 @@
 //!
 //! I claim no additional copyright over the original implementation.
 use crate::error::CacheError;
+use crate::utils::StateReader;
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::fmt::{Debug, Formatter};
 use std::ops::{Index, IndexMut};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-const DEPTH: usize = 4;
+/// DEFAULT_DEPTH is the default number of rows of the sketch.
+pub(crate) const DEFAULT_DEPTH: usize = 4;
 
 pub(crate) struct CountMinRow(Vec<u8>);
 
@@ -21,7 +23,7 @@ impl CountMinRow {
 
     #[inline]
     pub(crate) fn get(&self, i: u64) -> u8 {
-        ((self[(i / 2) as usize] >> ((i & 1) * 4)) as u8) & 0x0f
+        (self[(i / 2) as usize] >> ((i & 1) * 4)) & 0x0f
     }
 
     #[inline]
@@ -78,17 +80,26 @@ impl Debug for CountMinRow {
 /// `CountMinSketch` is a small conservative-update count-min sketch
 /// implementation with 4-bit counters
 pub(crate) struct CountMinSketch {
-    rows: [CountMinRow; DEPTH],
-    seeds: [u64; DEPTH],
+    rows: Vec<CountMinRow>,
+    seeds: Vec<u64>,
     mask: u64,
 }
 
 impl CountMinSketch {
+    #[cfg(test)]
     #[inline]
     pub(crate) fn new(ctrs: u64) -> Result<Self, CacheError> {
+        Self::with_depth(ctrs, DEFAULT_DEPTH)
+    }
+
+    /// `with_depth` returns a sketch with `depth` rows, the more rows, the fewer collisions.
+    pub(crate) fn with_depth(ctrs: u64, depth: usize) -> Result<Self, CacheError> {
         if ctrs < 1 {
             return Err(CacheError::InvalidCountMinWidth(ctrs));
         }
+        if depth < 1 {
+            return Err(CacheError::InvalidSketchDepth(depth));
+        }
 
         let ctrs = ctrs.next_power_of_two();
         let hctrs = ctrs / 2;
@@ -100,16 +111,9 @@ impl CountMinSketch {
                 .as_secs(),
         );
 
-        let seeds: Vec<u64> = { (0..DEPTH).map(|_| source.gen::<u64>()).collect() };
-
         let this = Self {
-            rows: [
-                CountMinRow::new(hctrs),
-                CountMinRow::new(hctrs),
-                CountMinRow::new(hctrs),
-                CountMinRow::new(hctrs),
-            ],
-            seeds: [seeds[0], seeds[1], seeds[2], seeds[3]],
+            rows: (0..depth).map(|_| CountMinRow::new(hctrs)).collect(),
+            seeds: (0..depth).map(|_| source.gen::<u64>()).collect(),
             mask: ctrs - 1,
         };
 
@@ -120,10 +124,10 @@ impl CountMinSketch {
     #[inline]
     pub(crate) fn increment(&mut self, hashed: u64) {
         let mask = self.mask;
-        (0..DEPTH).for_each(|i| {
-            let seed = self.seeds[i];
-            self.rows[i].increment((hashed ^ seed) & mask);
-        })
+        self.rows
+            .iter_mut()
+            .zip(self.seeds.iter())
+            .for_each(|(row, seed)| row.increment((hashed ^ seed) & mask))
     }
 
     /// `estimate` returns the value of the specified key.
@@ -131,13 +135,15 @@ impl CountMinSketch {
     pub(crate) fn estimate(&self, hashed: u64) -> i64 {
         let mask = self.mask;
         let mut min = 255u8;
-        (0..DEPTH).for_each(|i| {
-            let seed = self.seeds[i];
-            let val = self.rows[i].get((hashed ^ seed) & mask);
-            if val < min {
-                min = val;
-            }
-        });
+        self.rows
+            .iter()
+            .zip(self.seeds.iter())
+            .for_each(|(row, seed)| {
+                let val = row.get((hashed ^ seed) & mask);
+                if val < min {
+                    min = val;
+                }
+            });
 
         min as i64
     }
@@ -153,6 +159,42 @@ impl CountMinSketch {
     pub(crate) fn clear(&mut self) {
         self.rows.iter_mut().for_each(|row| row.clear())
     }
+
+    /// `encode` appends the mask, the depth, the seeds and the rows to `buf`.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.mask.to_le_bytes());
+        buf.extend_from_slice(&(self.rows.len() as u64).to_le_bytes());
+        self.seeds
+            .iter()
+            .for_each(|seed| buf.extend_from_slice(&seed.to_le_bytes()));
+        self.rows
+            .iter()
+            .for_each(|row| buf.extend_from_slice(&row.0));
+    }
+
+    /// `decode` reads a sketch written by [`encode`], which must have the shape of this one.
+    ///
+    /// [`encode`]: struct.CountMinSketch.html#method.encode
+    pub(crate) fn decode(&self, r: &mut StateReader) -> Result<Self, CacheError> {
+        let mask = r.u64()?;
+        let depth = r.u64()?;
+        if mask != self.mask || depth != self.rows.len() as u64 {
+            return Err(CacheError::InvalidPolicyState(format!(
+                "count min sketch of {} counters and depth {}, expected {} counters and depth {}",
+                mask.wrapping_add(1),
+                depth,
+                self.mask + 1,
+                self.rows.len()
+            )));
+        }
+
+        let seeds = (0..depth).map(|_| r.u64()).collect::<Result<_, _>>()?;
+        let width = self.rows[0].0.len();
+        let rows = (0..depth)
+            .map(|_| r.bytes(width).map(|row| CountMinRow(row.to_vec())))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rows, seeds, mask })
+    }
 }
 
 #[cfg(test)]
@@ -200,14 +242,45 @@ mod test {
         s.increment(1);
         s.increment(5);
         s.increment(9);
-        for i in 0..DEPTH {
+        for i in 0..DEFAULT_DEPTH {
             if format!("{:?}", s.rows[i]) != format!("{:?}", s.rows[0]) {
                 break;
             }
-            assert_ne!(i, DEPTH - 1);
+            assert_ne!(i, DEFAULT_DEPTH - 1);
         }
     }
 
+    #[test]
+    fn test_count_min_sketch_depth() {
+        assert!(CountMinSketch::with_depth(16, 0).is_err());
+        let mut s = CountMinSketch::with_depth(16, 8).unwrap();
+        assert_eq!(s.rows.len(), 8);
+        assert_eq!(s.seeds.len(), 8);
+        s.increment(1);
+        assert_eq!(s.estimate(1), 1);
+    }
+
+    #[test]
+    fn test_count_min_sketch_encode() {
+        let mut s = CountMinSketch::new(16).unwrap();
+        s.increment(1);
+        s.increment(1);
+        let mut buf = Vec::new();
+        s.encode(&mut buf);
+        assert_eq!(buf.len(), 8 + 8 + DEFAULT_DEPTH * 8 + DEFAULT_DEPTH * 8);
+
+        let mut r = StateReader::new(&buf);
+        let decoded = CountMinSketch::new(16).unwrap().decode(&mut r).unwrap();
+        assert!(r.finish().is_ok());
+        assert_eq!(decoded.seeds, s.seeds);
+        assert_eq!(decoded.estimate(1), 2);
+
+        let mut r = StateReader::new(&buf);
+        assert!(CountMinSketch::new(32).unwrap().decode(&mut r).is_err());
+        let mut r = StateReader::new(&buf[..buf.len() - 1]);
+        assert!(CountMinSketch::new(16).unwrap().decode(&mut r).is_err());
+    }
+
     #[test]
     fn test_count_min_sketch_estimate() {
         let mut s = CountMinSketch::new(16).unwrap();
@@ -1,23 +1,110 @@
+use crate::cache::DEFAULT_EXPIRATION_RESOLUTION;
 use crate::policy::LFUPolicy;
 use crate::ttl::{ExpirationMap, Time};
 use crate::utils::{change_lifetime_const, SharedValue, ValueRef, ValueRefMut};
-use crate::{CacheError, DefaultUpdateValidator, Item as CrateItem, UpdateValidator};
-use parking_lot::RwLock;
+use crate::{
+    CacheError, Clock, DefaultClock, DefaultUpdateValidator, Item as CrateItem, UpdateValidator,
+};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::any::Any;
 use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::{Debug, Formatter};
 use std::hash::BuildHasher;
 use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-const NUM_OF_SHARDS: usize = 256;
+pub(crate) const NUM_OF_SHARDS: usize = 256;
+
+/// `group_by_shard` groups the positions of the keys by the shard which holds them.
+fn group_by_shard(keys: impl Iterator<Item = u64>) -> Vec<Vec<usize>> {
+    let mut groups = vec![Vec::new(); NUM_OF_SHARDS];
+    keys.enumerate()
+        .for_each(|(pos, key)| groups[(key as usize) % NUM_OF_SHARDS].push(pos));
+    groups
+}
+
+/// RetainedKey is the original key of an item, only kept when the cache retains keys.
+pub(crate) type RetainedKey = Box<dyn Any + Send + Sync>;
+
+/// ReadExpiry computes the new TTL of a value which is read at the given time from its
+/// remaining TTL, see [`Expiry::expire_after_read`](crate::Expiry::expire_after_read).
+pub(crate) type ReadExpiry<V> =
+    Box<dyn Fn(&V, Instant, Option<Duration>) -> Option<Duration> + Send + Sync>;
+
+pub(crate) fn retain_key<K: Send + Sync + 'static>(key: K) -> RetainedKey {
+    Box::new(key)
+}
 
 pub(crate) struct StoreItem<V> {
     pub(crate) key: u64,
     pub(crate) conflict: u64,
     pub(crate) value: SharedValue<V>,
     pub(crate) expiration: Time,
+    pub(crate) retained_key: Option<RetainedKey>,
+    /// deadline is the nanoseconds after the creation of `expiration` at which the item
+    /// expires, zero if it never does. Reads may move it under the shard read lock, the
+    /// expiration map is only brought up to date with it lazily, by the cleanup.
+    pub(crate) deadline: AtomicU64,
+    /// rescheduled is set once a read moved the deadline before the scheduled one, so that
+    /// the item is handed to the next cleanup only once.
+    pub(crate) rescheduled: AtomicBool,
+}
+
+impl<V> StoreItem<V> {
+    /// `retained` returns the original key and the value, if the key was retained
+    /// and the item is not expired.
+    pub(crate) fn retained<K: 'static>(&self, now: Instant) -> Option<(&K, &V)> {
+        if self.is_expired(now) {
+            return None;
+        }
+
+        self.retained_key
+            .as_ref()?
+            .downcast_ref::<K>()
+            .map(|k| (k, self.value.get()))
+    }
+
+    /// `into_entry` returns the original key, if it was retained, and the value.
+    pub(crate) fn into_entry<K: 'static>(self) -> (Option<K>, V) {
+        let key = self
+            .retained_key
+            .and_then(|k| k.downcast::<K>().ok())
+            .map(|k| *k);
+        (key, self.value.into_inner())
+    }
+
+    #[inline]
+    pub(crate) fn is_expired(&self, now: Instant) -> bool {
+        self.deadline().is_expired(now)
+    }
+
+    /// `deadline` returns the expiration of the item, including the changes made by reads.
+    #[inline]
+    pub(crate) fn deadline(&self) -> Time {
+        self.expiration
+            .with_deadline(self.deadline.load(Ordering::Relaxed))
+    }
+
+    /// `touch` records a read of the item at `now`, which resets the time-to-idle of an idle item.
+    #[inline]
+    pub(crate) fn touch(&self, now: Instant) {
+        if self.expiration.is_idle() {
+            if let Some(deadline) = self.expiration.renewed(now).deadline() {
+                self.deadline
+                    .fetch_max(self.expiration.nanos_until(deadline), Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[inline]
+    fn set_expiration(&mut self, expiration: Time) {
+        self.expiration = expiration;
+        *self.deadline.get_mut() = expiration.deadline_nanos();
+    }
 }
 
 impl<V> Debug for StoreItem<V> {
@@ -37,18 +124,27 @@ pub(crate) struct ShardedMap<V, U = DefaultUpdateValidator<V>, SS = RandomState,
     em: ExpirationMap<ES>,
     store_item_size: usize,
     validator: U,
+    clock: Arc<dyn Clock>,
+    read_expiry: Option<ReadExpiry<V>>,
+    /// rescheduled keeps the items whose deadline was moved by a read to before the time
+    /// they are scheduled at in the expiration map, until the next cleanup. An item is only
+    /// pushed by the first of these reads, the others find its `rescheduled` flag set.
+    rescheduled: Mutex<Vec<(u64, u64)>>,
 }
 
 impl<V: Send + Sync + 'static> ShardedMap<V> {
     #[allow(dead_code)]
     pub fn new() -> Self {
-        Self::with_validator(ExpirationMap::new(), DefaultUpdateValidator::default())
+        Self::with_validator(
+            DefaultUpdateValidator::default(),
+            Arc::new(DefaultClock::new()),
+        )
     }
 }
 
 impl<V: Send + Sync + 'static, U: UpdateValidator<Value = V>> ShardedMap<V, U> {
     #[allow(dead_code)]
-    pub fn with_validator(em: ExpirationMap<RandomState>, validator: U) -> Self {
+    pub fn with_validator(validator: U, clock: Arc<dyn Clock>) -> Self {
         let shards = Box::new(
             (0..NUM_OF_SHARDS)
                 .map(|_| RwLock::new(HashMap::new()))
@@ -57,12 +153,20 @@ impl<V: Send + Sync + 'static, U: UpdateValidator<Value = V>> ShardedMap<V, U> {
                 .unwrap(),
         );
 
+        let em = ExpirationMap::with_hasher(
+            RandomState::default(),
+            clock.now(),
+            DEFAULT_EXPIRATION_RESOLUTION,
+        );
         let size = mem::size_of::<StoreItem<V>>();
         Self {
             shards,
             em,
             store_item_size: size,
             validator,
+            clock,
+            read_expiry: None,
+            rescheduled: Mutex::new(Vec::new()),
         }
     }
 }
@@ -74,7 +178,12 @@ impl<
         ES: BuildHasher + Clone + 'static,
     > ShardedMap<V, U, SS, ES>
 {
-    pub fn with_validator_and_hasher(em: ExpirationMap<ES>, validator: U, hasher: SS) -> Self {
+    pub fn with_validator_and_hasher(
+        em: ExpirationMap<ES>,
+        validator: U,
+        hasher: SS,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         let shards = Box::new(
             (0..NUM_OF_SHARDS)
                 .map(|_| RwLock::new(HashMap::with_hasher(hasher.clone())))
@@ -89,6 +198,54 @@ impl<
             em,
             store_item_size: size,
             validator,
+            clock,
+            read_expiry: None,
+            rescheduled: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// `set_read_expiry` makes the reads move the deadline of the items, see [`ReadExpiry`].
+    pub fn set_read_expiry(&mut self, read_expiry: ReadExpiry<V>) {
+        self.read_expiry = Some(read_expiry);
+    }
+
+    /// `now` returns the current time of the clock of the store.
+    #[inline]
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// `refresh_clock` refreshes the time of the clock of the store, if it caches it.
+    #[inline]
+    pub fn refresh_clock(&self) {
+        self.clock.refresh()
+    }
+
+    /// `on_read` resets the time-to-idle of the item, and lets the read expiry move its deadline.
+    #[inline]
+    fn on_read(&self, item: &StoreItem<V>, now: Instant) {
+        item.touch(now);
+
+        let expire = match &self.read_expiry {
+            Some(expire) => expire,
+            None => return,
+        };
+        let current = item.deadline();
+        let current = (!current.is_zero()).then(|| current.get_ttl(now));
+        if let Some(ttl) = expire(item.value.get(), now, current) {
+            let deadline = Time::new(now, ttl)
+                .deadline()
+                .map_or(0, |deadline| item.expiration.nanos_until(deadline));
+            item.deadline.store(deadline, Ordering::Relaxed);
+
+            // A later deadline is caught up with when the cleanup reaches the scheduled one.
+            let scheduled = item.expiration.deadline_nanos();
+            if deadline != 0
+                && (scheduled == 0 || deadline < scheduled)
+                && !item.rescheduled.swap(true, Ordering::Relaxed)
+            {
+                self.rescheduled.lock().push((item.key, item.conflict));
+            }
         }
     }
 
@@ -101,13 +258,15 @@ impl<
             }
 
             // Handle expired items
-            if !item.expiration.is_zero() && item.expiration.is_expired() {
+            let now = self.clock.now();
+            if item.is_expired(now) {
                 return None;
             }
 
+            self.on_read(item, now);
             unsafe {
                 let vptr = change_lifetime_const(item);
-                Some(ValueRef::new(data, vptr))
+                Some(ValueRef::new(data, vptr, &*self.clock))
             }
         } else {
             None
@@ -123,10 +282,12 @@ impl<
             }
 
             // Handle expired items
-            if !item.expiration.is_zero() && item.expiration.is_expired() {
+            let now = self.clock.now();
+            if item.is_expired(now) {
                 return None;
             }
 
+            self.on_read(item, now);
             unsafe {
                 let vptr = &mut *item.value.as_ptr();
                 Some(ValueRefMut::new(data, vptr))
@@ -136,37 +297,80 @@ impl<
         }
     }
 
+    /// `get_many` looks up the keys grouped by shard, so that each shard read lock is
+    /// taken only once. `f` is called with the position of each key found and its value.
+    pub fn get_many<F: FnMut(usize, &V)>(&self, keys: &[(u64, u64)], mut f: F) {
+        let now = self.clock.now();
+        for (shard, positions) in group_by_shard(keys.iter().map(|(key, _)| *key))
+            .into_iter()
+            .enumerate()
+        {
+            if positions.is_empty() {
+                continue;
+            }
+
+            let data = self.shards[shard].read();
+            for pos in positions {
+                let (key, conflict) = &keys[pos];
+                if let Some(item) = data.get(key) {
+                    if *conflict != 0 && (*conflict != item.conflict) {
+                        continue;
+                    }
+
+                    // Handle expired items
+                    if item.is_expired(now) {
+                        continue;
+                    }
+
+                    self.on_read(item, now);
+                    f(pos, item.value.get());
+                }
+            }
+        }
+    }
+
     pub fn try_insert(
         &self,
         key: u64,
         val: V,
         conflict: u64,
         expiration: Time,
+        retained_key: Option<RetainedKey>,
     ) -> Result<(), CacheError> {
         let mut data = self.shards[(key as usize) % NUM_OF_SHARDS].write();
 
-        match data.get(&key) {
-            None => {
-                // The value is not in the map already. There's no need to return anything.
-                // Simply add the expiration map.
-                self.em.try_insert(key, conflict, expiration)?;
+        if let Some(sitem) = data.get(&key) {
+            // The item existed already. We need to check the conflict key and reject the
+            // update if they do not match. Only after that the expiration map is updated.
+            if conflict != 0 && (conflict != sitem.conflict) {
+                return Ok(());
             }
-            Some(sitem) => {
-                // The item existed already. We need to check the conflict key and reject the
-                // update if they do not match. Only after that the expiration map is updated.
-                if conflict != 0 && (conflict != sitem.conflict) {
-                    return Ok(());
-                }
 
-                if !self.validator.should_update(sitem.value.get(), &val) {
-                    return Ok(());
-                }
-
-                self.em
-                    .try_update(key, conflict, sitem.expiration, expiration)?;
+            if !self.validator.should_update(sitem.value.get(), &val) {
+                return Ok(());
             }
         }
 
+        self.insert_in(&mut data, key, val, conflict, expiration, retained_key);
+        Ok(())
+    }
+
+    /// `insert_in` puts a new item in the locked shard `data`, in place of the item of
+    /// the key, if any, without checking its conflict or asking the update validator.
+    pub fn insert_in(
+        &self,
+        data: &mut HashMap<u64, StoreItem<V>, SS>,
+        key: u64,
+        val: V,
+        conflict: u64,
+        expiration: Time,
+        retained_key: Option<RetainedKey>,
+    ) {
+        match data.get(&key) {
+            None => self.em.insert(key, conflict, expiration),
+            Some(sitem) => self.em.update(key, conflict, sitem.expiration, expiration),
+        }
+
         data.insert(
             key,
             StoreItem {
@@ -174,20 +378,76 @@ impl<
                 conflict,
                 value: SharedValue::new(val),
                 expiration,
+                retained_key,
+                deadline: AtomicU64::new(expiration.deadline_nanos()),
+                rescheduled: AtomicBool::new(false),
             },
         );
-
-        Ok(())
     }
 
+    #[cfg(test)]
     pub fn try_update(
         &self,
         key: u64,
-        mut val: V,
+        val: V,
         conflict: u64,
         expiration: Time,
+    ) -> Result<UpdateResult<V>, CacheError> {
+        self.try_update_with(key, val, conflict, |_, _| expiration)
+    }
+
+    /// `try_update_with` is like `try_update`, but the new expiration is computed by `expire`
+    /// from the new value and the current expiration, while the shard lock is held.
+    pub fn try_update_with<F: FnOnce(&V, Time) -> Time>(
+        &self,
+        key: u64,
+        val: V,
+        conflict: u64,
+        expire: F,
     ) -> Result<UpdateResult<V>, CacheError> {
         let mut data = self.shards[(key as usize) % NUM_OF_SHARDS].write();
+        self.try_update_in(&mut data, key, val, conflict, expire)
+    }
+
+    /// `try_update_many` is the batch version of `try_update_with`, the keys are grouped by shard
+    /// so that each shard lock is taken only once. `expire` is called with the position of the
+    /// item. The results are in the order of `items`.
+    pub fn try_update_many<F: Fn(usize, &V, Time) -> Time>(
+        &self,
+        items: Vec<(u64, V, u64)>,
+        expire: F,
+    ) -> Result<Vec<UpdateResult<V>>, CacheError> {
+        let groups = group_by_shard(items.iter().map(|(key, ..)| *key));
+        let mut items = items.into_iter().map(Some).collect::<Vec<_>>();
+        let mut results = (0..items.len()).map(|_| None).collect::<Vec<_>>();
+        for (shard, positions) in groups.into_iter().enumerate() {
+            if positions.is_empty() {
+                continue;
+            }
+
+            let mut data = self.shards[shard].write();
+            for pos in positions {
+                let (key, val, conflict) = items[pos].take().unwrap();
+                results[pos] =
+                    Some(
+                        self.try_update_in(&mut data, key, val, conflict, |val, current| {
+                            expire(pos, val, current)
+                        })?,
+                    );
+            }
+        }
+
+        Ok(results.into_iter().map(Option::unwrap).collect())
+    }
+
+    fn try_update_in<F: FnOnce(&V, Time) -> Time>(
+        &self,
+        data: &mut HashMap<u64, StoreItem<V>, SS>,
+        key: u64,
+        mut val: V,
+        conflict: u64,
+        expire: F,
+    ) -> Result<UpdateResult<V>, CacheError> {
         match data.get_mut(&key) {
             None => Ok(UpdateResult::NotExist(val)),
             Some(item) => {
@@ -199,22 +459,106 @@ impl<
                     return Ok(UpdateResult::Reject(val));
                 }
 
-                self.em
-                    .try_update(key, conflict, item.expiration, expiration)?;
+                let expiration = expire(&val, item.deadline());
+                self.em.update(key, conflict, item.expiration, expiration);
                 mem::swap(&mut val, item.value.get_mut());
-                item.expiration = expiration;
+                item.set_expiration(expiration);
                 Ok(UpdateResult::Update(val))
             }
         }
     }
 
+    /// `try_update_expiration` replaces the expiration of the item with the one returned by `f`,
+    /// and moves the key to the matching bucket of the expiration map. Returns false if the
+    /// item is absent or expired.
+    pub fn try_update_expiration<F: FnOnce(Time) -> Time>(
+        &self,
+        key: u64,
+        conflict: u64,
+        f: F,
+    ) -> Result<bool, CacheError> {
+        let now = self.clock.now();
+        let mut data = self.shards[(key as usize) % NUM_OF_SHARDS].write();
+        match data.get_mut(&key) {
+            Some(item) if (conflict == 0 || conflict == item.conflict) && !item.is_expired(now) => {
+                let expiration = f(item.deadline());
+                self.em
+                    .update(key, item.conflict, item.expiration, expiration);
+                item.set_expiration(expiration);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// `live_expiration` returns the expiration of the item, if it is present and not expired.
+    pub fn live_expiration(&self, key: &u64, conflict: u64) -> Option<Time> {
+        let now = self.clock.now();
+        self.shards[((*key) as usize) % NUM_OF_SHARDS]
+            .read()
+            .get(key)
+            .filter(|item| (conflict == 0 || conflict == item.conflict) && !item.is_expired(now))
+            .map(StoreItem::deadline)
+    }
+
+    /// `inspect` calls `f` on the item of the key, under the read lock of its shard.
+    pub fn inspect<R, F: FnOnce(&StoreItem<V>) -> R>(&self, key: &u64, f: F) -> Option<R> {
+        self.shards[((*key) as usize) % NUM_OF_SHARDS]
+            .read()
+            .get(key)
+            .map(f)
+    }
+
+    pub fn shard_mut(&self, key: u64) -> RwLockWriteGuard<'_, HashMap<u64, StoreItem<V>, SS>> {
+        self.shards[(key as usize) % NUM_OF_SHARDS].write()
+    }
+
+    pub fn shards(
+        &self,
+    ) -> impl Iterator<Item = RwLockReadGuard<'_, HashMap<u64, StoreItem<V>, SS>>> {
+        self.shards.iter().map(|shard| shard.read())
+    }
+
     pub fn len(&self) -> usize {
         self.shards.iter().map(|l| l.read().len()).sum()
     }
 
     pub fn try_remove(&self, key: &u64, conflict: u64) -> Result<Option<StoreItem<V>>, CacheError> {
         let mut data = self.shards[(*key as usize) % NUM_OF_SHARDS].write();
+        self.try_remove_in(&mut data, key, conflict)
+    }
+
+    /// `try_remove_many` is the batch version of `try_remove`, the keys are grouped by shard
+    /// so that each shard lock is taken only once. The results are in the order of `keys`.
+    pub fn try_remove_many(
+        &self,
+        keys: &[(u64, u64)],
+    ) -> Result<Vec<Option<StoreItem<V>>>, CacheError> {
+        let mut results = (0..keys.len()).map(|_| None).collect::<Vec<_>>();
+        for (shard, positions) in group_by_shard(keys.iter().map(|(key, _)| *key))
+            .into_iter()
+            .enumerate()
+        {
+            if positions.is_empty() {
+                continue;
+            }
 
+            let mut data = self.shards[shard].write();
+            for pos in positions {
+                let (key, conflict) = &keys[pos];
+                results[pos] = self.try_remove_in(&mut data, key, *conflict)?;
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub fn try_remove_in(
+        &self,
+        data: &mut HashMap<u64, StoreItem<V>, SS>,
+        key: &u64,
+        conflict: u64,
+    ) -> Result<Option<StoreItem<V>>, CacheError> {
         match data.get(key) {
             None => Ok(None),
             Some(item) => {
@@ -223,7 +567,7 @@ impl<
                 }
 
                 if !item.expiration.is_zero() {
-                    self.em.try_remove(key, item.expiration)?;
+                    self.em.remove(key, item.expiration);
                 }
 
                 Ok(data.remove(key))
@@ -231,6 +575,12 @@ impl<
         }
     }
 
+    pub fn try_remove_expiration(&self, key: &u64, expiration: Time) -> Result<(), CacheError> {
+        self.em.remove(key, expiration);
+        Ok(())
+    }
+
+    #[cfg(test)]
     pub fn expiration(&self, key: &u64) -> Option<Time> {
         self.shards[((*key) as usize) % NUM_OF_SHARDS]
             .read()
@@ -238,42 +588,59 @@ impl<
             .map(|val| val.expiration)
     }
 
+    /// `try_expire` removes the item if it is expired. An item whose deadline was moved by
+    /// reads since it was scheduled in the expiration map is moved to the matching bucket instead.
+    fn try_expire(
+        &self,
+        key: &u64,
+        conflict: u64,
+        now: Instant,
+    ) -> Result<Option<StoreItem<V>>, CacheError> {
+        let mut data = self.shards[(*key as usize) % NUM_OF_SHARDS].write();
+        match data.get_mut(key) {
+            Some(item) if conflict == 0 || conflict == item.conflict => {
+                if item.is_expired(now) {
+                    return self.try_remove_in(&mut data, key, conflict);
+                }
+
+                *item.rescheduled.get_mut() = false;
+                if *item.deadline.get_mut() != item.expiration.deadline_nanos() {
+                    let deadline = item.deadline();
+                    self.em
+                        .update(*key, item.conflict, item.expiration, deadline);
+                    item.set_expiration(deadline);
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
     pub fn try_cleanup<PS: BuildHasher + Clone + 'static>(
         &self,
         policy: Arc<LFUPolicy<PS>>,
     ) -> Result<Vec<CrateItem<V>>, CacheError> {
-        let now = Time::now();
-        Ok(self
-            .em
-            .try_cleanup(now)?
-            .map_or(Vec::with_capacity(0), |m| {
-                m.iter()
-                    // Sanity check. Verify that the store agrees that this key is expired.
-                    .filter_map(|(k, v)| {
-                        self.expiration(k)
-                            .and_then(|t| {
-                                if t.is_expired() {
-                                    let cost = policy.cost(k);
-                                    policy.remove(k);
-                                    self.try_remove(k, *v)
-                                        .map(|maybe_sitem| {
-                                            maybe_sitem.map(|sitem| CrateItem {
-                                                val: Some(sitem.value.into_inner()),
-                                                index: sitem.key,
-                                                conflict: sitem.conflict,
-                                                cost,
-                                                exp: t,
-                                            })
-                                        })
-                                        .ok()
-                                } else {
-                                    None
-                                }
-                            })
-                            .flatten()
-                    })
-                    .collect()
-            }))
+        let now = self.clock.now();
+        let keys = self.em.cleanup(now);
+        let rescheduled = mem::take(&mut *self.rescheduled.lock());
+
+        let mut removed_items = Vec::new();
+        for (k, v) in keys.into_iter().chain(rescheduled) {
+            // Sanity check. Verify that the store agrees that this key is expired.
+            if let Some(sitem) = self.try_expire(&k, v, now)? {
+                let cost = policy.cost(&k);
+                policy.remove(&k);
+                removed_items.push(CrateItem {
+                    index: sitem.key,
+                    conflict: sitem.conflict,
+                    cost,
+                    exp: sitem.deadline(),
+                    val: Some(sitem.value.into_inner()),
+                })
+            }
+        }
+
+        Ok(removed_items)
     }
 
     #[cfg(feature = "async")]
@@ -281,29 +648,23 @@ impl<
         &self,
         policy: Arc<crate::policy::AsyncLFUPolicy<PS>>,
     ) -> Result<Vec<CrateItem<V>>, CacheError> {
-        let now = Time::now();
-        let items = self.em.try_cleanup(now)?;
+        let now = self.clock.now();
+        let keys = self.em.cleanup(now);
+        let rescheduled = mem::take(&mut *self.rescheduled.lock());
 
         let mut removed_items = Vec::new();
-        if let Some(items) = items {
-            for (k, v) in items.iter() {
-                let expiration = self.expiration(k);
-                if let Some(t) = expiration {
-                    if t.is_expired() {
-                        let cost = policy.cost(k);
-                        policy.remove(k);
-                        let removed_item = self.try_remove(k, *v)?;
-                        if let Some(sitem) = removed_item {
-                            removed_items.push(CrateItem {
-                                val: Some(sitem.value.into_inner()),
-                                index: sitem.key,
-                                conflict: sitem.conflict,
-                                cost,
-                                exp: t,
-                            })
-                        }
-                    }
-                }
+        for (k, v) in keys.into_iter().chain(rescheduled) {
+            // Sanity check. Verify that the store agrees that this key is expired.
+            if let Some(sitem) = self.try_expire(&k, v, now)? {
+                let cost = policy.cost(&k);
+                policy.remove(&k);
+                removed_items.push(CrateItem {
+                    index: sitem.key,
+                    conflict: sitem.conflict,
+                    cost,
+                    exp: sitem.deadline(),
+                    val: Some(sitem.value.into_inner()),
+                })
             }
         }
 
@@ -354,11 +715,14 @@ impl<V: Send + Sync + 'static> UpdateResult<V> {
 
 #[cfg(test)]
 mod test {
+    use crate::policy::LFUPolicy;
     use crate::store::{ShardedMap, StoreItem};
     use crate::ttl::Time;
     use crate::utils::SharedValue;
+    use crate::{Clock, DefaultUpdateValidator, MockClock};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
     use std::sync::Arc;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_store_item_debug() {
@@ -366,7 +730,10 @@ mod test {
             key: 0,
             conflict: 0,
             value: SharedValue::new(3),
-            expiration: Time::now(),
+            expiration: Time::never(Instant::now()),
+            retained_key: None,
+            deadline: AtomicU64::new(0),
+            rescheduled: AtomicBool::new(false),
         };
 
         eprintln!("{:?}", item);
@@ -381,7 +748,8 @@ mod test {
     fn test_store_set_get() {
         let s: ShardedMap<u64> = ShardedMap::new();
 
-        s.try_insert(1, 2, 0, Time::now()).unwrap();
+        s.try_insert(1, 2, 0, Time::never(Instant::now()), None)
+            .unwrap();
         let val = s.get(&1, 0).unwrap();
         assert_eq!(&2, val.value());
         val.release();
@@ -400,7 +768,8 @@ mod test {
         let s1 = s.clone();
 
         std::thread::spawn(move || {
-            s.try_insert(1, 2, 0, Time::now()).unwrap();
+            s.try_insert(1, 2, 0, Time::never(Instant::now()), None)
+                .unwrap();
         });
 
         loop {
@@ -414,13 +783,48 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_concurrent_insert_with_ttl() {
+        let clock = MockClock::new();
+        let s: Arc<ShardedMap<u64>> = Arc::new(ShardedMap::with_validator(
+            DefaultUpdateValidator::default(),
+            Arc::new(clock.clone()),
+        ));
+
+        // the threads schedule and move the expirations of the keys of every shard at once.
+        let handles = (0..8u64)
+            .map(|t| {
+                let (s, clock) = (s.clone(), clock.clone());
+                std::thread::spawn(move || {
+                    for i in 0..1_000u64 {
+                        let key = t * 1_000 + i;
+                        let exp = Time::new(clock.now(), Duration::from_millis(100 + i));
+                        s.try_insert(key, key, 0, exp, None).unwrap();
+                        s.try_update(key, key, 0, exp.extend(Duration::from_secs(1)))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+        assert_eq!(s.len(), 8_000);
+
+        let policy = Arc::new(LFUPolicy::new(100, 10).unwrap());
+        clock.advance(Duration::from_secs(1));
+        assert!(s.try_cleanup(policy.clone()).unwrap().is_empty());
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(s.try_cleanup(policy).unwrap().len(), 8_000);
+        assert_eq!(s.len(), 0);
+    }
+
     #[test]
     fn test_concurrent_get_mut_insert() {
         let s = Arc::new(ShardedMap::new());
         let s1 = s.clone();
 
         std::thread::spawn(move || {
-            s.try_insert(1, 2, 0, Time::now()).unwrap();
+            s.try_insert(1, 2, 0, Time::never(Instant::now()), None)
+                .unwrap();
             loop {
                 match s.get(&1, 0) {
                     None => continue,
@@ -455,7 +859,8 @@ mod test {
     fn test_store_remove() {
         let s: ShardedMap<u64> = ShardedMap::new();
 
-        s.try_insert(1, 2, 0, Time::now()).unwrap();
+        s.try_insert(1, 2, 0, Time::never(Instant::now()), None)
+            .unwrap();
         assert_eq!(s.try_remove(&1, 0).unwrap().unwrap().value.into_inner(), 2);
         let v = s.get(&1, 0);
         assert!(v.is_none());
@@ -465,18 +870,19 @@ mod test {
     #[test]
     fn test_store_update() {
         let s = ShardedMap::new();
-        s.try_insert(1, 1, 0, Time::now()).unwrap();
-        let v = s.try_update(1, 2, 0, Time::now()).unwrap();
+        s.try_insert(1, 1, 0, Time::never(Instant::now()), None)
+            .unwrap();
+        let v = s.try_update(1, 2, 0, Time::never(Instant::now())).unwrap();
         assert_eq!(v.into_inner(), 1);
 
         assert_eq!(s.get(&1, 0).unwrap().read(), 2);
 
-        let v = s.try_update(1, 3, 0, Time::now()).unwrap();
+        let v = s.try_update(1, 3, 0, Time::never(Instant::now())).unwrap();
         assert_eq!(v.into_inner(), 2);
 
         assert_eq!(s.get(&1, 0).unwrap().read(), 3);
 
-        let v = s.try_update(2, 2, 0, Time::now()).unwrap();
+        let v = s.try_update(2, 2, 0, Time::never(Instant::now())).unwrap();
         assert_eq!(v.into_inner(), 2);
         let v = s.get(&2, 0);
         assert!(v.is_none());
@@ -484,9 +890,9 @@ mod test {
 
     #[test]
     fn test_store_expiration() {
-        let exp = Time::now_with_expiration(Duration::from_secs(1));
+        let exp = Time::new(Instant::now(), Duration::from_secs(1));
         let s = ShardedMap::new();
-        s.try_insert(1, 1, 0, exp).unwrap();
+        s.try_insert(1, 1, 0, exp, None).unwrap();
 
         assert_eq!(s.get(&1, 0).unwrap().read(), 1);
 
@@ -501,6 +907,93 @@ mod test {
         assert!(s.expiration(&4340958203495).is_none());
     }
 
+    #[test]
+    fn test_store_update_expiration() {
+        let exp = Time::new(Instant::now(), Duration::from_secs(1));
+        let s = ShardedMap::new();
+        s.try_insert(1, 1, 0, exp, None).unwrap();
+        s.try_insert(2, 2, 0, exp, None).unwrap();
+
+        assert!(s
+            .try_update_expiration(1, 0, |_| Time::never(Instant::now()))
+            .unwrap());
+        assert!(s.live_expiration(&1, 0).unwrap().is_zero());
+        assert!(!s
+            .try_update_expiration(3, 0, |_| Time::never(Instant::now()))
+            .unwrap());
+
+        // only the persisted key left the slot of the original expiration.
+        let keys =
+            s.em.cleanup(exp.deadline().unwrap() + Duration::from_secs(1));
+        assert_eq!(keys, vec![(2, 0)]);
+    }
+
+    #[test]
+    fn test_store_idle_expiration() {
+        let clock = MockClock::new();
+        let s: ShardedMap<u64> =
+            ShardedMap::with_validator(DefaultUpdateValidator::default(), Arc::new(clock.clone()));
+        s.try_insert(
+            1,
+            1,
+            0,
+            Time::idle(clock.now(), Duration::from_millis(100)),
+            None,
+        )
+        .unwrap();
+        let scheduled = s.expiration(&1).unwrap();
+
+        clock.advance(Duration::from_millis(60));
+        assert_eq!(s.get(&1, 0).unwrap().read(), 1);
+        clock.advance(Duration::from_millis(60));
+
+        // the read pushed the deadline back, the cleanup reschedules the item.
+        assert!(s.try_expire(&1, 0, clock.now()).unwrap().is_none());
+        assert!(s.expiration(&1).unwrap() > scheduled);
+
+        clock.advance(Duration::from_millis(60));
+        assert!(s.get(&1, 0).is_none());
+        assert_eq!(
+            s.try_expire(&1, 0, clock.now())
+                .unwrap()
+                .unwrap()
+                .value
+                .into_inner(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_store_read_expiry_rescheduled_once() {
+        let clock = MockClock::new();
+        let ttl = Arc::new(AtomicU64::new(10));
+        let mut s: ShardedMap<u64> =
+            ShardedMap::with_validator(DefaultUpdateValidator::default(), Arc::new(clock.clone()));
+        let read_ttl = ttl.clone();
+        s.set_read_expiry(Box::new(move |_, _, _| {
+            Some(Duration::from_millis(read_ttl.load(Ordering::Relaxed)))
+        }));
+        s.try_insert(
+            1,
+            1,
+            0,
+            Time::new(clock.now(), Duration::from_secs(1)),
+            None,
+        )
+        .unwrap();
+
+        // every read moves the deadline before the scheduled one, the item is kept once.
+        (0..10).for_each(|_| assert_eq!(s.get(&1, 0).unwrap().read(), 1));
+        assert_eq!(*s.rescheduled.lock(), vec![(1, 0)]);
+
+        // once the cleanup rescheduled the item, a read may hand it over again.
+        s.rescheduled.lock().clear();
+        assert!(s.try_expire(&1, 0, clock.now()).unwrap().is_none());
+        ttl.store(1, Ordering::Relaxed);
+        assert_eq!(s.get(&1, 0).unwrap().read(), 1);
+        assert_eq!(*s.rescheduled.lock(), vec![(1, 0)]);
+    }
+
     #[test]
     fn test_store_collision() {
         let s = ShardedMap::new();
@@ -511,16 +1004,20 @@ mod test {
                 key: 1,
                 conflict: 0,
                 value: SharedValue::new(1),
-                expiration: Time::now(),
+                expiration: Time::never(Instant::now()),
+                retained_key: None,
+                deadline: AtomicU64::new(0),
+                rescheduled: AtomicBool::new(false),
             },
         );
         drop(data1);
         assert!(s.get(&1, 1).is_none());
 
-        s.try_insert(1, 2, 1, Time::now()).unwrap();
+        s.try_insert(1, 2, 1, Time::never(Instant::now()), None)
+            .unwrap();
         assert_ne!(s.get(&1, 0).unwrap().read(), 2);
 
-        let v = s.try_update(1, 2, 1, Time::now()).unwrap();
+        let v = s.try_update(1, 2, 1, Time::never(Instant::now())).unwrap();
         assert_eq!(v.into_inner(), 2);
         assert_ne!(s.get(&1, 0).unwrap().read(), 2);
 
@@ -1,222 +1,442 @@
-use parking_lot::RwLock;
-use std::cell::RefCell;
+use parking_lot::{Mutex, MutexGuard};
 use std::collections::{hash_map::RandomState, HashMap};
 use std::hash::BuildHasher;
-use std::ops::{Deref, DerefMut};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::store::NUM_OF_SHARDS;
+
+/// Deadline is the point in time at which a value expires, either a wall-clock
+/// [`SystemTime`] (e.g. the `exp` claim of a token or an `Expires` header) or an [`Instant`].
+///
+/// A wall-clock deadline is converted to the [`Clock`] of the cache when it is given,
+/// later changes of the wall clock do not move it.
+///
+/// [`SystemTime`]: https://doc.rust-lang.org/std/time/struct.SystemTime.html
+/// [`Instant`]: https://doc.rust-lang.org/std/time/struct.Instant.html
+/// [`Clock`]: trait.Clock.html
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Deadline {
+    /// A wall-clock deadline.
+    System(SystemTime),
+    /// A monotonic deadline.
+    Instant(Instant),
+}
 
-use crate::CacheError;
+impl Deadline {
+    /// `instant` returns the deadline on the clock whose current time is `now`.
+    pub(crate) fn instant(self, now: Instant) -> Instant {
+        match self {
+            Deadline::System(t) => match t.duration_since(SystemTime::now()) {
+                Ok(d) => now + d,
+                Err(e) => now.checked_sub(e.duration()).unwrap_or(now),
+            },
+            Deadline::Instant(t) => t,
+        }
+    }
+}
 
-fn storage_bucket(t: Time) -> i64 {
-    (t.unix() + 1) as i64
+impl From<SystemTime> for Deadline {
+    fn from(t: SystemTime) -> Self {
+        Deadline::System(t)
+    }
 }
 
-fn cleanup_bucket(t: Time) -> i64 {
-    // The bucket to cleanup is always behind the storage bucket by one so that
-    // no elements in that bucket (which might not have expired yet) are deleted.
-    storage_bucket(t) - 1
+impl From<Instant> for Deadline {
+    fn from(t: Instant) -> Self {
+        Deadline::Instant(t)
+    }
 }
 
+/// Time is the expiration of an item. It is measured with the [`Clock`] of the cache, all
+/// the methods which need the current time take it from the caller.
+///
+/// [`Clock`]: trait.Clock.html
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct Time {
     d: Duration,
-    created_at: SystemTime,
+    created_at: Instant,
+    /// idle is set when `d` is a time-to-idle, `created_at` is then the last access
+    /// known when the time was scheduled in the expiration map.
+    idle: bool,
 }
 
 impl Time {
-    pub fn now() -> Self {
+    /// `never` returns a time created at `now` which never expires.
+    pub fn never(now: Instant) -> Self {
         Self {
             d: Duration::ZERO,
-            created_at: SystemTime::now(),
+            created_at: now,
+            idle: false,
         }
     }
 
-    pub fn now_with_expiration(duration: Duration) -> Self {
+    /// `new` returns a time which expires `d` after `now`. A zero `d` means the time never expires.
+    pub fn new(now: Instant, d: Duration) -> Self {
         Self {
-            d: duration,
-            created_at: SystemTime::now(),
+            d,
+            created_at: now,
+            idle: false,
         }
     }
 
-    pub fn is_zero(&self) -> bool {
-        self.d.is_zero()
+    /// `idle` returns a time which expires once it has not been accessed for `tti` since `now`.
+    /// A zero `tti` means the time never expires.
+    pub fn idle(now: Instant, tti: Duration) -> Self {
+        Self {
+            d: tti,
+            created_at: now,
+            idle: !tti.is_zero(),
+        }
     }
 
-    pub fn unix(&self) -> u64 {
-        self.created_at
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d + self.d)
-            .unwrap()
-            .as_secs()
-    }
+    /// `at` returns a time which expires at `deadline`. A deadline which is already
+    /// reached is moved to `now`, so that the time is still reached by the cleanup.
+    pub fn at(now: Instant, deadline: Instant) -> Self {
+        if deadline > now {
+            return Self {
+                d: deadline - now,
+                created_at: now,
+                idle: false,
+            };
+        }
 
-    pub fn elapsed(&self) -> Duration {
-        self.created_at.elapsed().unwrap()
+        let d = Duration::from_nanos(1);
+        Self {
+            d,
+            created_at: now.checked_sub(d).unwrap_or(now),
+            idle: false,
+        }
     }
 
-    pub fn is_expired(&self) -> bool {
-        self.created_at.elapsed().map_or(false, |d| d >= self.d)
+    /// `renewed` returns the time counted from `now`, with the same duration.
+    pub(crate) fn renewed(&self, now: Instant) -> Self {
+        Self {
+            created_at: now,
+            ..*self
+        }
     }
 
-    pub fn get_ttl(&self) -> Duration {
-        if self.d.is_zero() {
-            return Duration::MAX;
+    /// `with_deadline` returns the time moved so that it expires `nanos` after it was
+    /// created, zero meaning never. An idle time keeps its time-to-idle.
+    pub(crate) fn with_deadline(&self, nanos: u64) -> Self {
+        let deadline = Duration::from_nanos(nanos);
+        if self.idle && deadline >= self.d {
+            return Self {
+                created_at: self.created_at + (deadline - self.d),
+                ..*self
+            };
         }
-        let elapsed = self.created_at.elapsed().unwrap();
-        if elapsed >= self.d {
-            Duration::ZERO
-        } else {
-            self.d - elapsed
+
+        Self {
+            d: deadline,
+            created_at: self.created_at,
+            idle: false,
         }
     }
-}
 
-/// Bucket is a map of key to conflict.
-#[derive(Debug, Default)]
-struct Bucket<S = RandomState> {
-    map: HashMap<u64, u64, S>,
-}
+    /// `deadline_nanos` returns the nanoseconds after the creation of the time at which
+    /// it expires, zero if it never does.
+    pub(crate) fn deadline_nanos(&self) -> u64 {
+        self.d.as_nanos() as u64
+    }
+
+    /// `nanos_until` returns the nanoseconds from the creation of the time to `deadline`,
+    /// in the unit of `deadline_nanos`.
+    pub(crate) fn nanos_until(&self, deadline: Instant) -> u64 {
+        (deadline
+            .saturating_duration_since(self.created_at)
+            .as_nanos() as u64)
+            .max(1)
+    }
+
+    /// `deadline` returns the time at which the time expires, `None` if it never does.
+    pub fn deadline(&self) -> Option<Instant> {
+        (!self.d.is_zero()).then(|| self.created_at + self.d)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.d.is_zero()
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        !self.d.is_zero() && now.saturating_duration_since(self.created_at) >= self.d
+    }
+
+    /// `extend` pushes the expiration back by `d`, a time which never expires is returned as is.
+    /// An idle time becomes a fixed one, which expires `d` after the idle deadline.
+    pub fn extend(&self, d: Duration) -> Self {
+        if self.d.is_zero() {
+            return *self;
+        }
 
-impl<S: BuildHasher> Bucket<S> {
-    pub fn with_hasher(hasher: S) -> Self {
         Self {
-            map: HashMap::with_hasher(hasher),
+            d: self.d.saturating_add(d),
+            created_at: self.created_at,
+            idle: false,
         }
     }
-}
 
-impl<S: BuildHasher> Deref for Bucket<S> {
-    type Target = HashMap<u64, u64, S>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.map
+    pub fn get_ttl(&self, now: Instant) -> Duration {
+        if self.d.is_zero() {
+            return Duration::MAX;
+        }
+        self.d
+            .saturating_sub(now.saturating_duration_since(self.created_at))
     }
 }
 
-impl<S: BuildHasher> DerefMut for Bucket<S> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.map
-    }
+/// The number of slots of a level of the timer wheel is `1 << SLOT_BITS`.
+const SLOT_BITS: u32 = 6;
+const NUM_SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = NUM_SLOTS as u64 - 1;
+/// The number of levels of the timer wheel, which spans `64^6` ticks. A deadline
+/// further away is kept in the last level until it gets closer.
+const NUM_LEVELS: usize = 6;
+
+/// Slot is a map of key to conflict and the tick at which the key expires.
+type Slot<S> = HashMap<u64, (u64, u64), S>;
+
+/// `level_for` returns the level which holds a key expiring at tick `when`, seen from
+/// tick `current`: the first level whose slots cover the ticks up to `when`.
+fn level_for(current: u64, when: u64) -> usize {
+    let masked = (current ^ when) | SLOT_MASK;
+    let significant = (u64::BITS - 1 - masked.leading_zeros()) / SLOT_BITS;
+    (significant as usize).min(NUM_LEVELS - 1)
+}
+
+/// `slot_for` returns the slot of `level` which holds a key expiring at tick `when`.
+fn slot_for(level: usize, when: u64) -> usize {
+    ((when >> (SLOT_BITS * level as u32)) & SLOT_MASK) as usize
 }
 
+/// TimerWheel is a hierarchical timer wheel. The slots of the first level span one tick,
+/// the slots of every next level span all the slots of the previous one. When the wheel
+/// reaches the slot of a higher level, its keys are cascaded into the lower levels, so
+/// that the keys always expire from the first level, at their own tick.
 #[derive(Debug)]
-pub(crate) struct ExpirationMap<S = RandomState> {
-    buckets: RwLock<RefCell<HashMap<i64, Bucket<S>, S>>>,
+struct TimerWheel<S = RandomState> {
+    /// slots holds the non empty slots, by `level * NUM_SLOTS + slot`.
+    slots: HashMap<usize, Slot<S>, S>,
+    /// occupied has a bit set for every non empty slot of each level.
+    occupied: [u64; NUM_LEVELS],
+    /// due holds the keys whose tick was already reached when they were inserted.
+    due: HashMap<u64, u64, S>,
+    /// current is the last tick the wheel was advanced to.
+    current: u64,
     hasher: S,
 }
 
-impl Default for ExpirationMap {
-    fn default() -> Self {
-        let hasher = RandomState::default();
+impl<S: BuildHasher + Clone> TimerWheel<S> {
+    fn with_hasher(hasher: S) -> Self {
         Self {
-            buckets: RwLock::new(RefCell::new(HashMap::with_hasher(hasher.clone()))),
+            slots: HashMap::with_hasher(hasher.clone()),
+            occupied: [0; NUM_LEVELS],
+            due: HashMap::with_hasher(hasher.clone()),
+            current: 0,
             hasher,
         }
     }
-}
 
-impl ExpirationMap {
-    pub fn new() -> Self {
-        Self::default()
+    fn insert(&mut self, key: u64, conflict: u64, when: u64) {
+        if when <= self.current {
+            self.due.insert(key, conflict);
+            return;
+        }
+
+        let level = level_for(self.current, when);
+        let slot = slot_for(level, when);
+        self.slots
+            .entry(level * NUM_SLOTS + slot)
+            .or_insert_with(|| HashMap::with_hasher(self.hasher.clone()))
+            .insert(key, (conflict, when));
+        self.occupied[level] |= 1 << slot;
     }
+
+    fn remove(&mut self, key: &u64, when: u64) {
+        if self.due.remove(key).is_some() {
+            return;
+        }
+
+        // a key expiring at `when` can only be in one slot of each level.
+        for level in 0..NUM_LEVELS {
+            let slot = slot_for(level, when);
+            let index = level * NUM_SLOTS + slot;
+            if let Some(m) = self.slots.get_mut(&index) {
+                if m.remove(key).is_some() {
+                    if m.is_empty() {
+                        self.slots.remove(&index);
+                        self.occupied[level] &= !(1 << slot);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// `take_slot` empties a slot.
+    fn take_slot(&mut self, level: usize, slot: usize) -> Option<Slot<S>> {
+        self.occupied[level] &= !(1 << slot);
+        self.slots.remove(&(level * NUM_SLOTS + slot))
+    }
+
+    /// `next_tick` returns the first tick after the current one at which a non empty
+    /// slot is reached, either to expire its keys or to cascade them.
+    fn next_tick(&self) -> Option<u64> {
+        self.occupied
+            .iter()
+            .enumerate()
+            .filter(|(_, occupied)| **occupied != 0)
+            .map(|(level, occupied)| {
+                let shift = SLOT_BITS * level as u32;
+                let base = (self.current >> shift) + 1;
+                let distance = occupied
+                    .rotate_right((base & SLOT_MASK) as u32)
+                    .trailing_zeros();
+                (base + distance as u64) << shift
+            })
+            .min()
+    }
+
+    /// `advance` moves the wheel to tick `now` and pushes the keys which expired on the
+    /// way to `expired`.
+    fn advance(&mut self, now: u64, expired: &mut Vec<(u64, u64)>) {
+        expired.extend(self.due.drain());
+        while let Some(tick) = self.next_tick().filter(|tick| *tick <= now) {
+            self.current = tick;
+            for level in (1..NUM_LEVELS).rev() {
+                let shift = SLOT_BITS * level as u32;
+                if tick & ((1 << shift) - 1) != 0 {
+                    continue;
+                }
+
+                let slot = self.take_slot(level, slot_for(level, tick));
+                for (key, (conflict, when)) in slot.into_iter().flatten() {
+                    if when <= tick {
+                        expired.push((key, conflict));
+                    } else {
+                        self.insert(key, conflict, when);
+                    }
+                }
+            }
+
+            let slot = self.take_slot(0, slot_for(0, tick));
+            expired.extend(
+                slot.into_iter()
+                    .flatten()
+                    .map(|(key, (conflict, _))| (key, conflict)),
+            );
+        }
+        self.current = self.current.max(now);
+    }
+}
+
+/// ExpirationMap is the index of the expiring keys. It is split in as many shards as the
+/// store, each with its own timer wheel behind its own lock, so that the keys of different
+/// shards are scheduled without contending.
+#[derive(Debug)]
+pub(crate) struct ExpirationMap<S = RandomState> {
+    shards: Box<[Mutex<TimerWheel<S>>; NUM_OF_SHARDS]>,
+    hasher: S,
+    /// origin is the time of the first tick of the wheels.
+    origin: Instant,
+    /// resolution is the length of a tick in nanoseconds.
+    resolution: u64,
 }
 
 impl<S: BuildHasher + Clone + 'static> ExpirationMap<S> {
-    pub(crate) fn with_hasher(hasher: S) -> ExpirationMap<S> {
+    /// `with_hasher` returns an expiration map whose ticks last `resolution` and start at
+    /// `origin`, which must not be later than any time inserted in the map.
+    pub(crate) fn with_hasher(
+        hasher: S,
+        origin: Instant,
+        resolution: Duration,
+    ) -> ExpirationMap<S> {
+        let shards = Box::new(std::array::from_fn(|_| {
+            Mutex::new(TimerWheel::with_hasher(hasher.clone()))
+        }));
+
         ExpirationMap {
-            buckets: RwLock::new(RefCell::new(HashMap::with_hasher(hasher.clone()))),
+            shards,
             hasher,
+            origin,
+            resolution: (resolution.as_nanos() as u64).max(1),
         }
     }
 
-    pub fn try_insert(&self, key: u64, conflict: u64, expiration: Time) -> Result<(), CacheError> {
-        // Items that don't expire don't need to be in the expiration map.
-        if expiration.is_zero() {
-            return Ok(());
-        }
+    fn shard(&self, key: u64) -> MutexGuard<'_, TimerWheel<S>> {
+        self.shards[(key as usize) % NUM_OF_SHARDS].lock()
+    }
 
-        let bucket_num = storage_bucket(expiration);
+    /// `storage_tick` returns the first tick at which `t` is expired.
+    fn storage_tick(&self, t: Time) -> u64 {
+        let deadline = t.created_at + t.d;
+        let nanos = deadline.saturating_duration_since(self.origin).as_nanos() as u64;
+        nanos.div_ceil(self.resolution)
+    }
 
-        let m = self.buckets.read();
+    /// `cleanup_tick` returns the last tick which is entirely past at `now`.
+    fn cleanup_tick(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.origin).as_nanos() as u64 / self.resolution
+    }
 
-        let mut m = m
-            .try_borrow_mut()
-            .map_err(|e| CacheError::InsertError(e.to_string()))?;
-        match m.get_mut(&bucket_num) {
-            None => {
-                let mut bucket = Bucket::with_hasher(self.hasher.clone());
-                bucket.map.insert(key, conflict);
-                m.insert(bucket_num, bucket);
-            }
-            Some(bucket) => {
-                bucket.map.insert(key, conflict);
-            }
+    pub fn insert(&self, key: u64, conflict: u64, expiration: Time) {
+        // Items that don't expire don't need to be in the expiration map.
+        if expiration.is_zero() {
+            return;
         }
 
-        Ok(())
+        let when = self.storage_tick(expiration);
+        self.shard(key).insert(key, conflict, when);
     }
 
-    pub fn try_update(
-        &self,
-        key: u64,
-        conflict: u64,
-        old_exp_time: Time,
-        new_exp_time: Time,
-    ) -> Result<(), CacheError> {
+    pub fn update(&self, key: u64, conflict: u64, old_exp_time: Time, new_exp_time: Time) {
         if old_exp_time.is_zero() && new_exp_time.is_zero() {
-            return Ok(());
+            return;
         }
 
-        let m = self.buckets.read();
-        let (old_bucket_num, new_bucket_num) =
-            (storage_bucket(old_exp_time), storage_bucket(new_exp_time));
+        let (old_when, new_when) = (
+            self.storage_tick(old_exp_time),
+            self.storage_tick(new_exp_time),
+        );
 
-        if old_bucket_num == new_bucket_num {
-            return Ok(());
+        if !old_exp_time.is_zero() && !new_exp_time.is_zero() && old_when == new_when {
+            return;
         }
 
-        let mut m = m
-            .try_borrow_mut()
-            .map_err(|e| CacheError::UpdateError(e.to_string()))?;
-        m.remove(&old_bucket_num);
+        let mut wheel = self.shard(key);
 
-        match m.get_mut(&new_bucket_num) {
-            None => {
-                let mut bucket = Bucket::with_hasher(self.hasher.clone());
-                bucket.map.insert(key, conflict);
-                m.insert(new_bucket_num, bucket);
-            }
-            Some(bucket) => {
-                bucket.map.insert(key, conflict);
-            }
+        // Items that don't expire are not in the expiration map.
+        if !old_exp_time.is_zero() {
+            wheel.remove(&key, old_when);
         }
 
-        Ok(())
+        if !new_exp_time.is_zero() {
+            wheel.insert(key, conflict, new_when);
+        }
     }
 
-    pub fn try_remove(&self, key: &u64, expiration: Time) -> Result<(), CacheError> {
-        let bucket_num = storage_bucket(expiration);
-        let m = self.buckets.read();
-        if let Some(bucket) = m
-            .try_borrow_mut()
-            .map_err(|e| CacheError::RemoveError(e.to_string()))?
-            .get_mut(&bucket_num)
-        {
-            bucket.remove(key);
-        };
+    pub fn remove(&self, key: &u64, expiration: Time) {
+        if expiration.is_zero() {
+            return;
+        }
 
-        Ok(())
+        let when = self.storage_tick(expiration);
+        self.shard(*key).remove(key, when);
     }
 
-    pub fn try_cleanup(&self, now: Time) -> Result<Option<HashMap<u64, u64, S>>, CacheError> {
-        let bucket_num = cleanup_bucket(now);
-        Ok(self
-            .buckets
-            .read()
-            .try_borrow_mut()
-            .map_err(|e| CacheError::CleanupError(e.to_string()))?
-            .remove(&bucket_num)
-            .map(|bucket| bucket.map))
+    /// `cleanup` advances the wheels to `now` and returns the keys and conflicts of every
+    /// item which expired since the last cleanup, however many ticks were skipped.
+    pub fn cleanup(&self, now: Instant) -> Vec<(u64, u64)> {
+        let now = self.cleanup_tick(now);
+        let mut expired = Vec::new();
+        for shard in self.shards.iter() {
+            shard.lock().advance(now, &mut expired);
+        }
+        expired
     }
 
     pub fn hasher(&self) -> S {
@@ -224,6 +444,124 @@ impl<S: BuildHasher + Clone + 'static> ExpirationMap<S> {
     }
 }
 
-unsafe impl<S: BuildHasher + Clone + 'static> Send for ExpirationMap<S> {}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn advance(wheel: &mut TimerWheel, now: u64) -> Vec<(u64, u64)> {
+        let mut expired = Vec::new();
+        wheel.advance(now, &mut expired);
+        expired
+    }
 
-unsafe impl<S: BuildHasher + Clone + 'static> Sync for ExpirationMap<S> {}
+    #[test]
+    fn test_timer_wheel_cascade() {
+        let mut wheel = TimerWheel::with_hasher(RandomState::default());
+        // one key per level, and one beyond the span of the wheel.
+        let ticks = [5, 100, 5_000, 300_000, 20_000_000, 1 << 40];
+        for (key, when) in ticks.iter().enumerate() {
+            wheel.insert(key as u64, 0, *when);
+        }
+
+        for (key, when) in ticks.iter().enumerate() {
+            assert!(advance(&mut wheel, *when - 1).is_empty());
+            assert_eq!(advance(&mut wheel, *when), vec![(key as u64, 0)]);
+        }
+        assert_eq!(wheel.next_tick(), None);
+        assert!(wheel.slots.is_empty());
+    }
+
+    #[test]
+    fn test_timer_wheel_remove() {
+        let mut wheel = TimerWheel::with_hasher(RandomState::default());
+        wheel.insert(1, 1, 10);
+        wheel.insert(2, 2, 10);
+        wheel.insert(3, 3, 1_000);
+        advance(&mut wheel, 500);
+
+        // the key cascaded to the first level is still found by its tick.
+        wheel.remove(&3, 1_000);
+        wheel.remove(&2, 10);
+        assert!(advance(&mut wheel, 2_000).is_empty());
+
+        // a key whose tick is already reached expires on the next advance.
+        wheel.insert(4, 4, 1_000);
+        assert_eq!(advance(&mut wheel, 2_000), vec![(4, 4)]);
+    }
+
+    #[test]
+    fn test_expiration_map_resolution() {
+        let origin = Instant::now();
+        let em =
+            ExpirationMap::with_hasher(RandomState::default(), origin, Duration::from_millis(10));
+        em.insert(1, 1, Time::new(origin, Duration::from_millis(105)));
+        em.insert(2, 2, Time::new(origin, Duration::from_millis(250)));
+        em.insert(3, 3, Time::new(origin, Duration::from_secs(3600)));
+
+        assert!(em.cleanup(origin + Duration::from_millis(105)).is_empty());
+        assert_eq!(
+            em.cleanup(origin + Duration::from_millis(110)),
+            vec![(1, 1)]
+        );
+        // every tick skipped since the last cleanup is reclaimed at once.
+        let mut keys = em.cleanup(origin + Duration::from_secs(3600));
+        keys.sort_unstable();
+        assert_eq!(keys, vec![(2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn test_expiration_map_concurrent() {
+        let origin = Instant::now();
+        let em = Arc::new(ExpirationMap::with_hasher(
+            RandomState::default(),
+            origin,
+            Duration::from_millis(1),
+        ));
+
+        // every thread schedules its keys, moves half of them and removes a quarter,
+        // while the main thread keeps cleaning up the past ticks.
+        let handles = (0..8u64)
+            .map(|t| {
+                let em = em.clone();
+                thread::spawn(move || {
+                    for i in 0..2_000u64 {
+                        let key = t * 10_000 + i;
+                        let exp = Time::new(origin, Duration::from_millis(1 + i));
+                        em.insert(key, key, exp);
+                        if i % 2 == 0 {
+                            let moved = Time::new(origin, Duration::from_secs(10) + exp.d);
+                            em.update(key, key, exp, moved);
+                            if i % 4 == 0 {
+                                em.remove(&key, moved);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut expired = Vec::new();
+        while handles.iter().any(|h| !h.is_finished()) {
+            expired.extend(em.cleanup(Instant::now().min(origin + Duration::from_secs(5))));
+        }
+        handles.into_iter().for_each(|h| h.join().unwrap());
+        expired.extend(em.cleanup(origin + Duration::from_secs(60)));
+
+        // a key moved or removed after it expired was already reported, the store
+        // discards such stale keys, but no key which is still scheduled may be lost.
+        expired.sort_unstable();
+        expired.dedup();
+        expired.retain(|(key, _)| key % 10_000 % 4 != 0);
+        let want = (0..8u64)
+            .flat_map(|t| {
+                (0..2_000u64)
+                    .filter(|i| i % 4 != 0)
+                    .map(move |i| t * 10_000 + i)
+            })
+            .map(|key| (key, key))
+            .collect::<Vec<_>>();
+        assert_eq!(expired, want);
+    }
+}
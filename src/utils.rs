@@ -15,7 +15,9 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use crate::error::CacheError;
 use crate::store::StoreItem;
+use crate::Clock;
 use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
 use std::cell::UnsafeCell;
 use std::collections::hash_map::RandomState;
@@ -30,6 +32,7 @@ use std::time::Duration;
 pub struct ValueRef<'a, V, S = RandomState> {
     _guard: RwLockReadGuard<'a, HashMap<u64, StoreItem<V>, S>>,
     val: &'a StoreItem<V>,
+    clock: &'a dyn Clock,
 }
 
 unsafe impl<'a, V: Send, S: BuildHasher> Send for ValueRef<'a, V, S> {}
@@ -41,8 +44,13 @@ impl<'a, V, S: BuildHasher> ValueRef<'a, V, S> {
     pub(crate) fn new(
         guard: RwLockReadGuard<'a, HashMap<u64, StoreItem<V>, S>>,
         val: &'a StoreItem<V>,
+        clock: &'a dyn Clock,
     ) -> Self {
-        Self { _guard: guard, val }
+        Self {
+            _guard: guard,
+            val,
+            clock,
+        }
     }
 
     /// Get the reference of the inner value.
@@ -60,7 +68,7 @@ impl<'a, V, S: BuildHasher> ValueRef<'a, V, S> {
     /// Get the expiration time.
     #[inline]
     pub fn ttl(&self) -> Duration {
-        self.val.expiration.get_ttl()
+        self.val.deadline().get_ttl(self.clock.now())
     }
 }
 
@@ -241,10 +249,54 @@ pub(crate) fn vec_to_array<T, const N: usize>(v: Vec<T>) -> [T; N] {
 ///
 /// Requires that you ensure the reference does not become invalid.
 /// The object has to outlive the reference.
-pub(crate) unsafe fn change_lifetime_const<'a, 'b, T>(x: &'a T) -> &'b T {
+pub(crate) unsafe fn change_lifetime_const<'b, T>(x: &T) -> &'b T {
     &*(x as *const T)
 }
 
+/// StateReader reads the little-endian fields of an exported policy state.
+pub(crate) struct StateReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// `bytes` returns the next `n` bytes, or an error if the state is truncated.
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'a [u8], CacheError> {
+        if self.buf.len() < n {
+            return Err(CacheError::InvalidPolicyState(
+                "unexpected end of state".to_string(),
+            ));
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, CacheError> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, CacheError> {
+        self.bytes(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    /// `finish` checks that the whole state has been read.
+    pub(crate) fn finish(self) -> Result<(), CacheError> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(CacheError::InvalidPolicyState(format!(
+                "{} trailing bytes",
+                self.buf.len()
+            )))
+        }
+    }
+}
+
 // // TODO: should use SharedNonNull to replace Arc?
 // #[repr(transparent)]
 // #[allow(dead_code)]
@@ -288,12 +340,15 @@ mod test {
         // SharedNonNull,
         SharedValue,
     };
+    use crate::{Clock, MockClock};
     use crate::{ValueRef, ValueRefMut};
     use parking_lot::RwLock;
     use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, AtomicU64};
 
     #[test]
     fn test_value_ref() {
+        let clock = MockClock::new();
         let mut m = HashMap::new();
         m.insert(
             1,
@@ -301,7 +356,10 @@ mod test {
                 key: 1,
                 conflict: 0,
                 value: SharedValue::new(3),
-                expiration: Time::now(),
+                expiration: Time::never(clock.now()),
+                retained_key: None,
+                deadline: AtomicU64::new(0),
+                rescheduled: AtomicBool::new(false),
             },
         );
         m.insert(
@@ -310,7 +368,10 @@ mod test {
                 key: 2,
                 conflict: 0,
                 value: SharedValue::new(3),
-                expiration: Time::now(),
+                expiration: Time::never(clock.now()),
+                retained_key: None,
+                deadline: AtomicU64::new(0),
+                rescheduled: AtomicBool::new(false),
             },
         );
         let lm = RwLock::new(m);
@@ -318,7 +379,7 @@ mod test {
         let l = lm.read();
         let item = l.get(&1).unwrap();
         let v = unsafe { change_lifetime_const(item) };
-        let vr = ValueRef::new(l, v);
+        let vr = ValueRef::new(l, v, &clock);
         assert_eq!(vr.as_ref(), &3);
         eprintln!("{}", vr);
         eprintln!("{:?}", vr);
@@ -326,6 +387,7 @@ mod test {
 
     #[test]
     fn test_value_ref_mut() {
+        let clock = MockClock::new();
         let mut m = HashMap::new();
         m.insert(
             1,
@@ -333,7 +395,10 @@ mod test {
                 key: 1,
                 conflict: 0,
                 value: SharedValue::new(3),
-                expiration: Time::now(),
+                expiration: Time::never(clock.now()),
+                retained_key: None,
+                deadline: AtomicU64::new(0),
+                rescheduled: AtomicBool::new(false),
             },
         );
         m.insert(
@@ -342,7 +407,10 @@ mod test {
                 key: 2,
                 conflict: 0,
                 value: SharedValue::new(3),
-                expiration: Time::now(),
+                expiration: Time::never(clock.now()),
+                retained_key: None,
+                deadline: AtomicU64::new(0),
+                rescheduled: AtomicBool::new(false),
             },
         );
         let lm = RwLock::new(m);